@@ -0,0 +1,309 @@
+// a write-back cache of disk blocks, sitting between the filesystem and the block device
+
+use core::ops::Range;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use log::warn;
+
+use crate::{BLOCK_SIZE, BlockDevice, FsError, journal::Journal};
+
+/// the default number of blocks kept in a cache (256 KB)
+pub const DEFAULT_CAPACITY: usize = 512;
+
+#[derive(Debug, Clone)]
+struct CachedBlock {
+    data: [u8; BLOCK_SIZE],
+    dirty: bool,
+    // metadata, which goes through the journal rather than straight to its home location
+    journaled: bool,
+    last_used: u64,
+}
+
+/// a cache of the blocks of one disk, which evicts the least recently used block when full and only writes back dirty blocks
+#[derive(Debug, Clone)]
+pub struct BlockCache<D: BlockDevice> {
+    device: D,
+    capacity: usize,
+    blocks: BTreeMap<u64, CachedBlock>,
+    // last use -> block number, so the least recently used block is always first
+    lru: BTreeMap<u64, u64>,
+    tick: u64,
+    // the disk has not been formatted yet, so blocks that were never written read as zeroes and nothing is evicted
+    unformatted: bool,
+    journal: Option<Journal>,
+}
+
+impl<D: BlockDevice> BlockCache<D> {
+    /// create a cache for a disk that already holds a filesystem
+    pub fn new(device: D, capacity: usize) -> Self {
+        BlockCache {
+            device,
+            capacity,
+            blocks: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            unformatted: false,
+            journal: None,
+        }
+    }
+
+    /// create a cache for a disk that is about to be formatted, so its current contents are ignored
+    pub fn new_unformatted(device: D, capacity: usize) -> Self {
+        BlockCache {
+            unformatted: true,
+            ..BlockCache::new(device, capacity)
+        }
+    }
+
+    /// give back the device, dropping every cached block
+    pub fn into_device(self) -> D {
+        self.device
+    }
+
+    /// check whether the disk still needs formatting
+    pub fn is_unformatted(&self) -> bool {
+        self.unformatted
+    }
+
+    /// the number of blocks that have been modified but not written back
+    pub fn dirty_count(&self) -> usize {
+        self.blocks.values().filter(|b| b.dirty).count()
+    }
+
+    /// send metadata writes through a journal from now on
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    /// check whether enough metadata is waiting that it should be committed before the journal overflows, counting extra blocks that will be written at the commit
+    pub fn needs_commit(&self, extra: usize) -> bool {
+        let Some(journal) = &self.journal else {
+            return false;
+        };
+
+        let pending = self.blocks.values().filter(|b| b.dirty && b.journaled).count();
+        !self.unformatted && pending + extra >= journal.capacity() / 2
+    }
+
+    /// read a block, loading it from the disk if it is not cached
+    pub fn read(&mut self, block: u64) -> Result<[u8; BLOCK_SIZE], FsError> {
+        if !self.blocks.contains_key(&block) {
+            let mut data = [0; BLOCK_SIZE];
+            if !self.unformatted {
+                self.device.read(block, &mut data)?;
+            }
+            self.insert(block, data, false, false);
+        }
+
+        self.touch(block);
+        Ok(self.blocks[&block].data)
+    }
+
+    /// overwrite a metadata block in the cache, marking it dirty - it reaches the disk through the journal when flushed
+    pub fn write(&mut self, block: u64, data: &[u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        self.write_block(block, data, true)
+    }
+
+    /// overwrite a block of file contents in the cache, marking it dirty - it reaches the disk when evicted or flushed
+    pub fn write_data(&mut self, block: u64, data: &[u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        self.write_block(block, data, false)
+    }
+
+    fn write_block(
+        &mut self,
+        block: u64,
+        data: &[u8; BLOCK_SIZE],
+        journaled: bool,
+    ) -> Result<(), FsError> {
+        match self.blocks.get_mut(&block) {
+            Some(cached) => {
+                cached.data = *data;
+                // a block that is already waiting for the journal stays there
+                cached.journaled |= journaled;
+                cached.dirty = true;
+            }
+            None => self.insert(block, *data, true, journaled),
+        }
+
+        self.touch(block);
+        Ok(())
+    }
+
+    /// write every dirty block back to the disk - file contents first, then metadata as a single journal commit
+    ///
+    /// metadata that does not fit in the journal is not written at all, as writing it unjournaled could leave the filesystem torn
+    pub fn flush(&mut self) -> Result<(), FsError> {
+        // file contents reach the disk before the metadata that points at them
+        self.write_back(false)?;
+
+        let metadata: Vec<(u64, [u8; BLOCK_SIZE])> = self
+            .blocks
+            .iter()
+            .filter(|(_, b)| b.dirty)
+            .map(|(&block, b)| (block, b.data))
+            .collect();
+
+        let journaled = match &self.journal {
+            _ if metadata.is_empty() => false,
+            Some(journal) if metadata.len() <= journal.capacity() => true,
+            Some(_) => {
+                // the filesystem commits well before this, so it is a bug if it happens
+                warn!(
+                    "{} metadata blocks do not fit in the journal, not writing them",
+                    metadata.len()
+                );
+                return Err(FsError::WriteError);
+            }
+            None => false,
+        };
+
+        if journaled {
+            self.journal
+                .as_mut()
+                .unwrap()
+                .commit(&mut self.device, &metadata)?;
+        }
+
+        self.write_back(true)?;
+
+        if journaled {
+            // the commit may only be dropped once the blocks it holds are really on the disk
+            self.device.flush()?;
+            self.journal.as_mut().unwrap().clear(&mut self.device)?;
+        }
+
+        self.device.flush()
+    }
+
+    /// write the dirty blocks that are (or are not) journaled straight to their home locations
+    fn write_back(&mut self, journaled: bool) -> Result<(), FsError> {
+        for (&block, cached) in self
+            .blocks
+            .iter_mut()
+            .filter(|(_, b)| b.dirty && b.journaled == journaled)
+        {
+            self.device.write(block, &cached.data)?;
+            cached.dirty = false;
+            cached.journaled = false;
+        }
+
+        Ok(())
+    }
+
+    /// the number of blocks in a commit that may not have reached their home locations yet
+    pub fn pending_journal(&mut self) -> Result<usize, FsError> {
+        let Some(journal) = &self.journal else {
+            return Ok(0);
+        };
+
+        Ok(journal.committed(&mut self.device)?.len())
+    }
+
+    /// finish a commit that was interrupted before all of its blocks reached their home locations, returning how many blocks were replayed
+    pub fn replay_journal(&mut self) -> Result<usize, FsError> {
+        let Some(journal) = &mut self.journal else {
+            return Ok(0);
+        };
+
+        let blocks = journal.committed(&mut self.device)?;
+        for (block, data) in &blocks {
+            self.device.write(*block, data)?;
+
+            // anything read before the replay (such as the superblock) is out of date
+            if let Some(cached) = self.blocks.get_mut(block) {
+                cached.data = *data;
+            }
+        }
+
+        if !blocks.is_empty() {
+            self.device.flush()?;
+            journal.clear(&mut self.device)?;
+        }
+
+        Ok(blocks.len())
+    }
+
+    /// zero every block in the given range that has not been written, then flush - after this, misses are read from the disk
+    pub fn format(&mut self, blocks: Range<u64>) -> Result<(), FsError> {
+        let zeroes = [0; BLOCK_SIZE];
+        for block in blocks {
+            if !self.blocks.contains_key(&block) {
+                self.device.write(block, &zeroes)?;
+            }
+        }
+
+        // there is nothing on the disk yet to keep consistent, so the journal is skipped
+        self.write_back(false)?;
+        self.write_back(true)?;
+        self.unformatted = false;
+        self.device.flush()
+    }
+
+    fn touch(&mut self, block: u64) {
+        let cached = self.blocks.get_mut(&block).unwrap();
+        self.lru.remove(&cached.last_used);
+
+        self.tick += 1;
+        cached.last_used = self.tick;
+        self.lru.insert(self.tick, block);
+    }
+
+    fn insert(&mut self, block: u64, data: [u8; BLOCK_SIZE], dirty: bool, journaled: bool) {
+        if self.blocks.len() >= self.capacity && !self.unformatted {
+            self.evict();
+        }
+
+        self.tick += 1;
+        self.blocks.insert(block, CachedBlock {
+            data,
+            dirty,
+            journaled,
+            last_used: self.tick,
+        });
+        self.lru.insert(self.tick, block);
+    }
+
+    /// drop the least recently used block, writing it back first if it is dirty - metadata waiting for the journal is never evicted
+    fn evict(&mut self) {
+        let Some((&last_used, &block)) = self.lru.iter().find(|(_, block)| {
+            let cached = &self.blocks[block];
+            !(cached.dirty && cached.journaled)
+        }) else {
+            return;
+        };
+
+        let cached = &self.blocks[&block];
+        if cached.dirty && self.device.write(block, &cached.data).is_err() {
+            // the cache grows past its capacity rather than losing the write
+            warn!("Failed to write back block {}, keeping it cached", block);
+            return;
+        }
+
+        self.lru.remove(&last_used);
+        self.blocks.remove(&block);
+    }
+}
+
+/// test that reads of an unformatted disk see zeroes and writes stay dirty until flushed
+#[test]
+fn test_unformatted_cache() {
+    let mut cache = BlockCache::new_unformatted(crate::RamDisk::new(16 * BLOCK_SIZE as u64), 2);
+
+    assert_eq!(cache.read(5), Ok([0; BLOCK_SIZE]));
+    cache.write(6, &[1; BLOCK_SIZE]).unwrap();
+    cache.write(7, &[2; BLOCK_SIZE]).unwrap();
+
+    // nothing is evicted before formatting, even past capacity
+    assert_eq!(cache.read(6), Ok([1; BLOCK_SIZE]));
+    assert_eq!(cache.read(7), Ok([2; BLOCK_SIZE]));
+    assert_eq!(cache.dirty_count(), 2);
+
+    // formatting writes everything back, after which misses come from the device
+    cache.format(0..8).unwrap();
+    assert_eq!(cache.dirty_count(), 0);
+    let mut device = cache.into_device();
+    let mut data = [0; BLOCK_SIZE];
+    device.read(7, &mut data).unwrap();
+    assert_eq!(data, [2; BLOCK_SIZE]);
+}
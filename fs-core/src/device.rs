@@ -0,0 +1,147 @@
+// the disks a filesystem can live on, such as an ATA drive in the kernel or an image file on the host
+
+use alloc::{boxed::Box, vec, vec::Vec};
+
+use crate::{BLOCK_SIZE, FsError};
+
+/// a disk that is read and written in blocks of BLOCK_SIZE bytes
+pub trait BlockDevice {
+    /// read a block into buf, failing with FsError::ReadError
+    fn read(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), FsError>;
+
+    /// write buf to a block, failing with FsError::WriteError
+    fn write(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), FsError>;
+
+    /// read consecutive blocks into buf, whose length must be a whole number of blocks
+    fn read_blocks(&mut self, block: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        if buf.len() % BLOCK_SIZE != 0 {
+            return Err(FsError::ReadError);
+        }
+        for (i, chunk) in buf.chunks_exact_mut(BLOCK_SIZE).enumerate() {
+            self.read(block + i as u64, chunk.try_into().unwrap())?;
+        }
+        Ok(())
+    }
+
+    /// write buf to consecutive blocks, whose length must be a whole number of blocks
+    fn write_blocks(&mut self, block: u64, buf: &[u8]) -> Result<(), FsError> {
+        if buf.len() % BLOCK_SIZE != 0 {
+            return Err(FsError::WriteError);
+        }
+        for (i, chunk) in buf.chunks_exact(BLOCK_SIZE).enumerate() {
+            self.write(block + i as u64, chunk.try_into().unwrap())?;
+        }
+        Ok(())
+    }
+
+    /// the number of blocks on the device
+    fn block_count(&self) -> u64;
+
+    /// the size of a block in bytes
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    /// make sure everything written so far has reached the disk, rather than a cache on the way to it
+    fn flush(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
+
+/// a borrowed device, so a filesystem can be loaded without giving the device up
+impl<D: BlockDevice + ?Sized> BlockDevice for &mut D {
+    fn read(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        (**self).read(block, buf)
+    }
+
+    fn write(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        (**self).write(block, buf)
+    }
+
+    fn read_blocks(&mut self, block: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        (**self).read_blocks(block, buf)
+    }
+
+    fn write_blocks(&mut self, block: u64, buf: &[u8]) -> Result<(), FsError> {
+        (**self).write_blocks(block, buf)
+    }
+
+    fn block_count(&self) -> u64 {
+        (**self).block_count()
+    }
+
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    fn flush(&mut self) -> Result<(), FsError> {
+        (**self).flush()
+    }
+}
+
+/// a device chosen at run time, such as whichever of a drive, a RAM disk or a loopback file is available
+impl<D: BlockDevice + ?Sized> BlockDevice for Box<D> {
+    fn read(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        (**self).read(block, buf)
+    }
+
+    fn write(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        (**self).write(block, buf)
+    }
+
+    fn read_blocks(&mut self, block: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        (**self).read_blocks(block, buf)
+    }
+
+    fn write_blocks(&mut self, block: u64, buf: &[u8]) -> Result<(), FsError> {
+        (**self).write_blocks(block, buf)
+    }
+
+    fn block_count(&self) -> u64 {
+        (**self).block_count()
+    }
+
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    fn flush(&mut self) -> Result<(), FsError> {
+        (**self).flush()
+    }
+}
+
+/// a disk held in memory
+#[derive(Debug, Clone)]
+pub struct RamDisk {
+    blocks: Vec<[u8; BLOCK_SIZE]>,
+}
+
+impl RamDisk {
+    /// create a zeroed disk of the given size in bytes
+    pub fn new(size: u64) -> Self {
+        RamDisk {
+            blocks: vec![[0; BLOCK_SIZE]; size as usize / BLOCK_SIZE],
+        }
+    }
+
+    /// the size of the disk in bytes
+    pub fn size(&self) -> u64 {
+        (self.blocks.len() * BLOCK_SIZE) as u64
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn read(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        *buf = *self.blocks.get(block as usize).ok_or(FsError::ReadError)?;
+        Ok(())
+    }
+
+    fn write(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        *self.blocks.get_mut(block as usize).ok_or(FsError::WriteError)? = *buf;
+        Ok(())
+    }
+
+    fn block_count(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+}
@@ -0,0 +1,470 @@
+// consistency checker for rustnix-fs images, which can also repair what is safely fixable
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    BLOCK_SIZE, BlockDevice, FileKind, FsError, Inode, MAGIC_NUMBER, PhysFs, ROOT_INODE, Superblock,
+    encode_dir_table, parse_dir_table,
+};
+
+/// the outcome of a check
+#[derive(Debug, Default, Clone)]
+pub struct Report {
+    /// the number of problems found
+    pub problems: usize,
+    /// the number of problems that were repaired
+    pub fixed: usize,
+    /// a line describing each problem, in the order they were found
+    pub messages: Vec<String>,
+}
+
+impl Report {
+    /// the exit code, following e2fsck: 0 if the filesystem is clean, 1 if every problem was fixed, 4 if some were left
+    pub fn exit_code(&self) -> i32 {
+        if self.problems == 0 {
+            0
+        } else if self.problems == self.fixed {
+            1
+        } else {
+            4
+        }
+    }
+}
+
+/// the inodes that survived the first pass, with their kind and every block they use (including indirect blocks)
+type LiveInodes = BTreeMap<usize, (FileKind, Vec<u64>)>;
+
+struct Checker<D: BlockDevice> {
+    phys_fs: PhysFs<D>,
+    repair: bool,
+    report: Report,
+}
+
+/// group sorted block numbers into inclusive ranges, to keep the output short
+fn ranges(blocks: &[u64]) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for &block in blocks {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == block => *end = block,
+            _ => ranges.push((block, block)),
+        }
+    }
+    ranges
+}
+
+fn describe((start, end): (u64, u64)) -> String {
+    if start == end {
+        format!("block {}", start)
+    } else {
+        format!("blocks {}-{}", start, end)
+    }
+}
+
+impl<D: BlockDevice> Checker<D> {
+    /// report a problem, returning whether it should be fixed now
+    fn problem(&mut self, fixable: bool, message: String) -> bool {
+        self.report.problems += 1;
+
+        let fix = fixable && self.repair;
+        let message = if fix {
+            self.report.fixed += 1;
+            format!("{} (fixed)", message)
+        } else if fixable {
+            format!("{} (fixable)", message)
+        } else {
+            message
+        };
+        self.report.messages.push(message);
+
+        fix
+    }
+
+    /// drop an inode, leaving its blocks to be freed by the bitmap pass
+    fn clear_inode(&mut self, inode_index: usize) -> Result<(), FsError> {
+        self.phys_fs.update_inode(inode_index, Inode::default())?;
+        self.phys_fs.inode_bitmap.clear(inode_index as u64);
+        Ok(())
+    }
+
+    /// replay a pending journal commit when repairing, which means loading the filesystem again
+    fn check_journal(mut self) -> Result<Self, FsError> {
+        let pending = self.phys_fs.cache.pending_journal()?;
+
+        if pending > 0
+            && self.problem(
+                true,
+                format!("journal holds {} blocks from an interrupted flush", pending),
+            )
+        {
+            let clock = self.phys_fs.clock;
+            self.phys_fs = PhysFs::read_from_disk(self.phys_fs.into_device(), clock)?;
+        }
+
+        Ok(self)
+    }
+
+    /// check that the regions named in the superblock are in order and fit on the disk, returning false if they do not
+    fn check_layout(&mut self) -> Result<bool, FsError> {
+        let superblock: Superblock = self.phys_fs.superblock;
+        let disk_sectors = superblock.disk_size / BLOCK_SIZE as u64;
+
+        let mut ok = true;
+        if superblock.data_block_size != BLOCK_SIZE as u64 {
+            ok &= !self.problem(
+                false,
+                format!("superblock: block size is {}, expected {}", superblock.data_block_size, BLOCK_SIZE),
+            );
+        }
+
+        if superblock.data_bitmap_start < superblock.inode_bitmap_start
+            || superblock.inode_table_start < superblock.data_bitmap_start
+            || superblock.inode_table_start + superblock.num_inodes > superblock.data_blocks_start
+            || superblock.data_blocks_start + superblock.num_data_blocks > disk_sectors
+        {
+            ok &= !self.problem(false, "superblock: regions overlap or run past the end of the disk".to_string());
+        }
+
+        if ok
+            && self
+                .phys_fs
+                .cache
+                .read(superblock.data_blocks_start + superblock.num_data_blocks - 1)
+                .is_err()
+        {
+            ok &= !self.problem(
+                false,
+                format!("image is shorter than the {} bytes given in the superblock", superblock.disk_size),
+            );
+        }
+
+        Ok(ok)
+    }
+
+    /// check a block pointer and everything below it, collecting the blocks it uses - returns false if the pointer itself is out of range and should be cleared
+    fn check_tree(
+        &mut self,
+        inode_index: usize,
+        pointer: u64,
+        depth: u32,
+        location: &str,
+        blocks: &mut Vec<u64>,
+    ) -> Result<bool, FsError> {
+        if pointer >= self.phys_fs.superblock.num_data_blocks {
+            let fix = self.problem(
+                true,
+                format!("inode {}: {} points at block {}, past the end of the data area", inode_index, location, pointer),
+            );
+            return Ok(!fix);
+        }
+
+        blocks.push(pointer);
+        if depth == 0 {
+            return Ok(true);
+        }
+
+        let data = self.phys_fs.read_data_block(pointer)?;
+        for (index, entry) in data.chunks_exact(8).enumerate() {
+            let child = u64::from_le_bytes(entry.try_into().unwrap());
+            if child == 0 {
+                continue;
+            }
+
+            let location = format!("entry {} of indirect block {}", index, pointer);
+            if !self.check_tree(inode_index, child, depth - 1, &location, blocks)? {
+                self.phys_fs.write_pointer(pointer, index, 0)?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// check every allocated inode's metadata header and block pointers
+    fn check_inodes(&mut self) -> Result<LiveInodes, FsError> {
+        let mut live = BTreeMap::new();
+
+        for inode_index in 0..self.phys_fs.superblock.num_inodes as usize {
+            if !self.phys_fs.inode_bitmap.is_set(inode_index as u64) {
+                continue;
+            }
+
+            let mut inode = self.phys_fs.read_inode(inode_index)?;
+            let metadata_block = inode.data_block_pointers[0];
+            if metadata_block == 0 || metadata_block >= self.phys_fs.superblock.num_data_blocks {
+                if self.problem(true, format!("inode {}: metadata block {} is out of range", inode_index, metadata_block)) {
+                    self.clear_inode(inode_index)?;
+                }
+                continue;
+            }
+
+            let metadata = self.phys_fs.read_metadata(&inode)?;
+            let kind = match metadata.kind() {
+                Ok(kind) => kind,
+                Err(_) => {
+                    if self.problem(true, format!("inode {}: bad metadata header (unknown kind {})", inode_index, metadata.kind)) {
+                        self.clear_inode(inode_index)?;
+                    }
+                    continue;
+                }
+            };
+
+            let mut blocks = vec![metadata_block];
+            let mut changed = false;
+            let slots = [
+                ("single indirect pointer", 1, inode.single_indirect_block_pointer),
+                ("double indirect pointer", 2, inode.double_indirect_block_pointer),
+                ("triple indirect pointer", 3, inode.triple_indirect_block_pointer),
+            ];
+
+            for index in 1..inode.data_block_pointers.len() {
+                let pointer = inode.data_block_pointers[index];
+                let location = format!("direct pointer {}", index);
+                if pointer != 0 && !self.check_tree(inode_index, pointer, 0, &location, &mut blocks)? {
+                    inode.data_block_pointers[index] = 0;
+                    changed = true;
+                }
+            }
+
+            for (i, (location, depth, pointer)) in slots.into_iter().enumerate() {
+                if pointer != 0 && !self.check_tree(inode_index, pointer, depth, location, &mut blocks)? {
+                    match i {
+                        0 => inode.single_indirect_block_pointer = 0,
+                        1 => inode.double_indirect_block_pointer = 0,
+                        _ => inode.triple_indirect_block_pointer = 0,
+                    }
+                    changed = true;
+                }
+            }
+
+            if changed {
+                self.phys_fs.update_inode(inode_index, inode)?;
+            }
+
+            live.insert(inode_index, (kind, blocks));
+        }
+
+        Ok(live)
+    }
+
+    /// walk the directory tree from the root, dropping entries that point at missing inodes, and return how many entries point at each inode
+    fn check_directories(&mut self, live: &LiveInodes) -> Result<BTreeMap<usize, u64>, FsError> {
+        // the root counts as linked, so an entry pointing back at it is never followed
+        let mut links = BTreeMap::from([(ROOT_INODE, 1)]);
+        let mut queue = vec![ROOT_INODE];
+
+        while let Some(dir_index) = queue.pop() {
+            let inode = self.phys_fs.read_inode(dir_index)?;
+            let data = match self.phys_fs.read_inode_data(&inode) {
+                Ok(data) => data,
+                // a bad pointer, which has already been reported
+                Err(FsError::InvalidDataBlock) => continue,
+                Err(err) => return Err(err),
+            };
+
+            let (entries, bad) = parse_dir_table(&data);
+            let mut changed = false;
+            if let Some(offset) = bad {
+                changed = self.problem(
+                    true,
+                    format!("directory {}: the entry at byte {} is corrupt, dropping it and the rest of the table", dir_index, offset),
+                );
+            }
+
+            let mut kept = Vec::new();
+            for (child, name) in entries {
+                let remove = match live.get(&child) {
+                    None => self.problem(
+                        true,
+                        format!("directory {}: entry {} points at bad or unused inode {}", dir_index, name, child),
+                    ),
+                    // only files can have more than one entry, a second one for a directory would make a loop
+                    Some((FileKind::Directory, _)) if links.contains_key(&child) => self.problem(
+                        true,
+                        format!("directory {}: entry {} links directory {}, which already has an entry", dir_index, name, child),
+                    ),
+                    Some((kind, _)) => {
+                        *links.entry(child).or_insert(0) += 1;
+                        if *kind == FileKind::Directory {
+                            queue.push(child);
+                        }
+                        false
+                    }
+                };
+
+                if remove {
+                    changed = true;
+                } else {
+                    kept.push((child, name));
+                }
+            }
+
+            if changed {
+                self.phys_fs.write_inode_data(dir_index, &encode_dir_table(&kept), None, None)?;
+            }
+        }
+
+        Ok(links)
+    }
+
+    /// compare each inode's link count with the number of directory entries that point at it
+    fn check_link_counts(&mut self, links: &BTreeMap<usize, u64>) -> Result<(), FsError> {
+        for (&inode_index, &count) in links {
+            let mut inode = self.phys_fs.read_inode(inode_index)?;
+            if inode.links != count
+                && self.problem(
+                    true,
+                    format!("inode {}: link count is {}, but {} entries point at it", inode_index, inode.links, count),
+                )
+            {
+                inode.links = count;
+                self.phys_fs.update_inode(inode_index, inode)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// compare the data bitmap against the blocks the inodes actually use
+    fn check_blocks(&mut self, live: &LiveInodes) -> Result<(), FsError> {
+        let mut owners: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
+        for (&inode_index, (_, blocks)) in live {
+            for &block in blocks {
+                owners.entry(block).or_default().push(inode_index);
+            }
+        }
+
+        let mut shared: Vec<(&u64, &Vec<usize>)> = owners.iter().filter(|(_, o)| o.len() > 1).collect();
+        shared.sort();
+        for (block, inodes) in shared {
+            // there is no safe way to decide which inode the block belongs to
+            self.problem(false, format!("block {} is used more than once, by inodes {:?}", block, inodes));
+        }
+
+        if !self.phys_fs.data_bitmap.is_set(0) && self.problem(true, "block 0 (the null block) is marked free".to_string()) {
+            self.phys_fs.data_bitmap.set(0);
+        }
+
+        let (mut unmarked, mut orphaned) = (Vec::new(), Vec::new());
+        for block in 1..self.phys_fs.superblock.num_data_blocks {
+            match (owners.contains_key(&block), self.phys_fs.data_bitmap.is_set(block)) {
+                (true, false) => unmarked.push(block),
+                (false, true) => orphaned.push(block),
+                _ => {}
+            }
+        }
+
+        // each block marked or freed takes the bitmap closer to the truth, so the repairs are committed as they go to fit in the journal
+        for range in ranges(&unmarked) {
+            if self.problem(true, format!("{} in use but marked free", describe(range))) {
+                for block in range.0..=range.1 {
+                    self.phys_fs.data_bitmap.set(block);
+                    self.phys_fs.commit_if_needed()?;
+                }
+            }
+        }
+
+        for range in ranges(&orphaned) {
+            if self.problem(true, format!("{} marked in use but not used by any inode", describe(range))) {
+                for block in range.0..=range.1 {
+                    self.phys_fs.data_bitmap.clear(block);
+                    self.phys_fs.commit_if_needed()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_free_counts(&mut self) {
+        let superblock = self.phys_fs.superblock;
+        let (free_inodes, free_blocks) = (self.phys_fs.inode_bitmap.free, self.phys_fs.data_bitmap.free);
+
+        // the counts are rewritten from the bitmaps on the next flush
+        if superblock.free_inodes != free_inodes {
+            self.problem(
+                true,
+                format!("superblock: free inode count is {}, the bitmap has {}", superblock.free_inodes, free_inodes),
+            );
+        }
+        if superblock.free_data_blocks != free_blocks {
+            self.problem(
+                true,
+                format!("superblock: free block count is {}, the bitmap has {}", superblock.free_data_blocks, free_blocks),
+            );
+        }
+    }
+
+    fn run(&mut self) -> Result<(), FsError> {
+        if !self.check_layout()? {
+            return Ok(());
+        }
+
+        // before any repairs change the bitmaps
+        self.check_free_counts();
+
+        let mut live = self.check_inodes()?;
+        match live.get(&ROOT_INODE) {
+            Some((FileKind::Directory, _)) => {}
+            _ => {
+                self.problem(false, "the root directory is missing".to_string());
+                return Ok(());
+            }
+        }
+
+        let links = self.check_directories(&live)?;
+        self.check_link_counts(&links)?;
+
+        let mut unlinked: Vec<usize> = live.keys().filter(|i| !links.contains_key(i)).copied().collect();
+        unlinked.sort();
+        for inode_index in unlinked {
+            if self.problem(true, format!("inode {} is not linked from any directory", inode_index)) {
+                self.clear_inode(inode_index)?;
+                live.remove(&inode_index);
+            }
+        }
+
+        self.check_blocks(&live)?;
+
+        if self.report.fixed > 0 {
+            self.phys_fs.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// check the filesystem on a device, repairing what is safely fixable if repair is set
+pub fn check<D: BlockDevice>(mut device: D, clock: fn() -> u64, repair: bool) -> Result<Report, FsError> {
+    let mut sector = [0; BLOCK_SIZE];
+    device.read(0, &mut sector)?;
+
+    if Superblock::from_sector(&sector).is_err() {
+        let magic = u64::from_le_bytes(sector[..8].try_into().unwrap());
+        let message = if magic != MAGIC_NUMBER {
+            format!("superblock: bad magic number {:#x}, expected {:#x}", magic, MAGIC_NUMBER)
+        } else {
+            "superblock: the journal does not fit between the superblock and the bitmaps".to_string()
+        };
+
+        return Ok(Report {
+            problems: 1,
+            fixed: 0,
+            messages: vec![message],
+        });
+    }
+
+    let checker = Checker {
+        phys_fs: PhysFs::load(device, clock, false)?,
+        repair,
+        report: Report::default(),
+    };
+    let mut checker = checker.check_journal()?;
+    checker.run()?;
+
+    Ok(checker.report)
+}
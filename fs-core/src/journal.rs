@@ -0,0 +1,187 @@
+// a write-ahead journal of metadata blocks, so that a flush interrupted part way through can be finished at mount time
+
+use alloc::vec::Vec;
+
+use crate::{BLOCK_SIZE, BlockDevice, FsError, POINTERS_PER_BLOCK};
+
+/// Magic number marking a journal header ("rnjournl")
+pub const JOURNAL_MAGIC: u64 = 0x6c6e72756f6a6e72;
+/// Number of sectors reserved for the journal in a newly created filesystem
+pub const JOURNAL_SIZE: u64 = 128;
+
+/// a journal region on a disk - a header sector, followed by sectors listing where each journaled block belongs, followed by the blocks themselves
+///
+/// a commit writes the blocks and their locations first, then the header, so a header with a valid checksum means the whole commit reached the disk.
+/// once the blocks have been written to their home locations the header is cleared again.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    start: u64,
+    descriptor_sectors: u64,
+    capacity: usize,
+}
+
+/// a checksum (FNV-1a) over the locations and contents of a commit, so a torn commit is never replayed
+fn checksum(blocks: &[(u64, [u8; BLOCK_SIZE])]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for (block, data) in blocks {
+        for &byte in block.to_le_bytes().iter().chain(data.iter()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+impl Journal {
+    /// describe a journal of the given number of sectors, starting at the given sector
+    pub fn new(start: u64, size: u64) -> Self {
+        // each descriptor sector lists the locations of POINTERS_PER_BLOCK blocks
+        let descriptor_sectors = (size - 1).div_ceil(POINTERS_PER_BLOCK as u64 + 1);
+        Journal {
+            start,
+            descriptor_sectors,
+            capacity: (size - 1 - descriptor_sectors) as usize,
+        }
+    }
+
+    /// the most blocks a single commit can hold
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn read_sector(
+        &self,
+        device: &mut impl BlockDevice,
+        sector: u64,
+    ) -> Result<[u8; BLOCK_SIZE], FsError> {
+        let mut data = [0; BLOCK_SIZE];
+        device.read(self.start + sector, &mut data)?;
+        Ok(data)
+    }
+
+    fn write_sector(
+        &self,
+        device: &mut impl BlockDevice,
+        sector: u64,
+        data: &[u8; BLOCK_SIZE],
+    ) -> Result<(), FsError> {
+        device.write(self.start + sector, data)
+    }
+
+    fn write_header(
+        &self,
+        device: &mut impl BlockDevice,
+        count: u64,
+        checksum: u64,
+    ) -> Result<(), FsError> {
+        let mut header = [0; BLOCK_SIZE];
+        header[..8].copy_from_slice(&JOURNAL_MAGIC.to_le_bytes());
+        header[8..16].copy_from_slice(&count.to_le_bytes());
+        header[16..24].copy_from_slice(&checksum.to_le_bytes());
+        self.write_sector(device, 0, &header)
+    }
+
+    /// write blocks to the journal and commit them - once this returns, they will reach their home locations even if the flush is interrupted
+    pub fn commit(
+        &mut self,
+        device: &mut impl BlockDevice,
+        blocks: &[(u64, [u8; BLOCK_SIZE])],
+    ) -> Result<(), FsError> {
+        if blocks.len() > self.capacity {
+            return Err(FsError::WriteError);
+        }
+
+        for (i, chunk) in blocks.chunks(POINTERS_PER_BLOCK).enumerate() {
+            let mut descriptor = [0; BLOCK_SIZE];
+            for (j, (block, _)) in chunk.iter().enumerate() {
+                descriptor[j * 8..j * 8 + 8].copy_from_slice(&block.to_le_bytes());
+            }
+            self.write_sector(device, 1 + i as u64, &descriptor)?;
+        }
+
+        for (i, (_, data)) in blocks.iter().enumerate() {
+            self.write_sector(device, 1 + self.descriptor_sectors + i as u64, data)?;
+        }
+
+        // the commit point, which must not reach the disk before the blocks it vouches for, nor be overtaken by their home writes
+        device.flush()?;
+        self.write_header(device, blocks.len() as u64, checksum(blocks))?;
+        device.flush()
+    }
+
+    /// mark the journal empty, once every committed block has reached its home location
+    pub fn clear(&mut self, device: &mut impl BlockDevice) -> Result<(), FsError> {
+        self.write_header(device, 0, 0)
+    }
+
+    /// read back a complete commit, or nothing if the journal is empty or the last commit never finished
+    pub fn committed(
+        &self,
+        device: &mut impl BlockDevice,
+    ) -> Result<Vec<(u64, [u8; BLOCK_SIZE])>, FsError> {
+        let header = self.read_sector(device, 0)?;
+        let field = |index: usize| {
+            u64::from_le_bytes(header[index * 8..index * 8 + 8].try_into().unwrap())
+        };
+
+        let count = field(1) as usize;
+        if field(0) != JOURNAL_MAGIC || count == 0 || count > self.capacity {
+            return Ok(Vec::new());
+        }
+
+        let mut blocks = Vec::with_capacity(count);
+        for i in 0..count {
+            let descriptor = self.read_sector(device, 1 + (i / POINTERS_PER_BLOCK) as u64)?;
+            let offset = (i % POINTERS_PER_BLOCK) * 8;
+            let block = u64::from_le_bytes(descriptor[offset..offset + 8].try_into().unwrap());
+            blocks.push((block, self.read_sector(device, 1 + self.descriptor_sectors + i as u64)?));
+        }
+
+        if checksum(&blocks) != field(2) {
+            return Ok(Vec::new());
+        }
+
+        Ok(blocks)
+    }
+}
+
+/// test the journal layout, and that a commit with changed contents or locations gets a different checksum
+#[test]
+fn test_journal_layout() {
+    let journal = Journal::new(1, JOURNAL_SIZE);
+    // one header sector and two descriptor sectors
+    assert_eq!(journal.capacity(), 125);
+
+    let commit = [(10, [1; BLOCK_SIZE]), (11, [2; BLOCK_SIZE])];
+    let moved = [(10, [1; BLOCK_SIZE]), (12, [2; BLOCK_SIZE])];
+    let changed = [(10, [1; BLOCK_SIZE]), (11, [3; BLOCK_SIZE])];
+    assert_ne!(checksum(&commit), checksum(&moved));
+    assert_ne!(checksum(&commit), checksum(&changed));
+}
+
+/// test that a commit reads back as written, and that clearing or tearing it leaves nothing to replay
+#[test]
+fn test_journal_commit() {
+    let mut device = crate::RamDisk::new(256 * BLOCK_SIZE as u64);
+    let mut journal = Journal::new(1, JOURNAL_SIZE);
+    assert!(journal.committed(&mut device).unwrap().is_empty());
+
+    let commit: Vec<_> = (0..100).map(|i| (200 + i, [i as u8; BLOCK_SIZE])).collect();
+    journal.commit(&mut device, &commit).unwrap();
+    assert_eq!(journal.committed(&mut device).unwrap(), commit);
+
+    // a commit with a block slot that did not reach the disk fails the checksum
+    let slot = 1 + 1 + 2 + 50; // start, header, descriptor sectors
+    let mut saved = [0; BLOCK_SIZE];
+    device.read(slot, &mut saved).unwrap();
+    device.write(slot, &[0xff; BLOCK_SIZE]).unwrap();
+    assert!(journal.committed(&mut device).unwrap().is_empty());
+    device.write(slot, &saved).unwrap();
+    assert_eq!(journal.committed(&mut device).unwrap(), commit);
+
+    journal.clear(&mut device).unwrap();
+    assert!(journal.committed(&mut device).unwrap().is_empty());
+
+    let too_big: Vec<_> = (0..126).map(|i| (i, [0; BLOCK_SIZE])).collect();
+    assert_eq!(journal.commit(&mut device, &too_big), Err(FsError::WriteError));
+}
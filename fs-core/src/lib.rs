@@ -0,0 +1,1977 @@
+//! the on-disk format of rustnix-fs, shared by the kernel and the host tools, over any block device
+
+/*
+ * rustnix-fs
+ * at the start of the disk, there is a superblock, which contains the following information:
+ * - the size of the disk
+ * - the size of the inode table
+ * - the size of the data blocks
+ * - the number of inodes
+ * - the number of data blocks
+ * - where the bitmaps, inode table and data blocks start
+ * - the number of free inodes and free data blocks
+ * - where the journal starts, and its size
+ *
+ * the superblock is followed by the journal, a write-ahead log of metadata blocks. A flush first writes file contents to their home locations,
+ * then commits every changed metadata block (superblock, bitmaps, inodes, metadata headers, indirect blocks and directory tables) to the journal, and only then writes them home.
+ * If a flush is interrupted after the commit, mounting the filesystem replays the journal, so each flush reaches the disk completely or not at all.
+ *
+ * the journal is followed by the inode bitmap and the data block bitmap, which have one bit per inode or data block, set if it is in use.
+ * Data block 0 is never allocated, so that a block pointer of 0 always means "no block".
+ *
+ * the bitmaps are followed by the inode table, which contains the following information:
+ * - the number of data blocks used by the file
+ * - the data block pointers
+ * - the number of directory entries (hard links) pointing at the inode, which is freed when the last one is removed
+ *
+ * the inode table is followed by the data blocks, which contain the actual data of the files, including a metadata header that contains the following information:
+ * - the owner of the file
+ * - creation time
+ * - modification time
+ * - access time
+ * - permissions, Unix-style
+ * - the kind of the inode (file, directory or symbolic link)
+ * - the size of the file
+ *
+ * Directories are inodes whose data is a packed list of entries, each holding the inode index of a child, the length of its name and the name itself. Names are not stored anywhere else,
+ * so renaming a directory only rewrites the entry in its parent. Files may have entries in several directories (hard links), directories only ever have one.
+ * Symbolic links are inodes whose data is the path they point to. Paths are resolved by walking the directory tables one component at a time, starting from the root
+ * and following symbolic links along the way, up to MAX_SYMLINKS of them.
+ */
+
+#![cfg_attr(not(test), no_std)]
+#![warn(missing_docs)]
+
+extern crate alloc;
+
+use core::fmt::Display;
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use log::warn;
+
+use cache::{BlockCache, DEFAULT_CAPACITY};
+use journal::{JOURNAL_SIZE, Journal};
+use path::Lookup;
+
+/// the block cache, which sits between the filesystem and the device
+pub mod cache;
+/// the disks a filesystem can live on
+pub mod device;
+/// the consistency checker
+pub mod fsck;
+/// the write-ahead journal of metadata blocks
+pub mod journal;
+/// partition tables, and the partitions they describe
+pub mod partition;
+/// path resolution and rename checks shared by every filesystem
+pub mod path;
+
+pub use device::{BlockDevice, RamDisk};
+pub use partition::Partition;
+
+/// Size of a block (and a sector) in bytes
+pub const BLOCK_SIZE: usize = 512;
+
+/// Magic number for our filesystem ("rustnix2"), changed whenever the layout does so older images are rejected instead of misread
+pub const MAGIC_NUMBER: u64 = 0x727573746e697832;
+/// Number of pointers in a block
+pub const POINTERS_PER_BLOCK: usize = BLOCK_SIZE / 8; // 8: size of u64
+/// Inode index of the root directory
+pub const ROOT_INODE: usize = 0;
+/// Size of the fixed part of a directory entry in bytes, which is followed by the name
+pub const DIR_ENTRY_HEADER_SIZE: usize = 10; // 8: inode index, 2: name length
+/// Maximum length of a single path component
+pub const MAX_NAME_LEN: usize = 255;
+/// Maximum number of symbolic links followed while resolving one path, past which it is assumed to loop
+pub const MAX_SYMLINKS: usize = 40;
+/// Number of inodes in a newly created filesystem
+pub const NUM_INODES: u64 = 1024;
+/// How far, in seconds, an access time may fall behind reads of an unchanged file
+pub const RELATIME_INTERVAL: u64 = 24 * 60 * 60;
+/// Number of bits in a bitmap block
+const BITS_PER_BLOCK: u64 = BLOCK_SIZE as u64 * 8;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Superblock {
+    magic_number: u64,
+    disk_size: u64,
+    inode_table_size: u64,
+    data_block_size: u64,
+    num_inodes: u64,
+    num_data_blocks: u64,
+    inode_bitmap_start: u64,
+    data_bitmap_start: u64,
+    inode_table_start: u64,
+    data_blocks_start: u64,
+    free_inodes: u64,
+    free_data_blocks: u64,
+    journal_start: u64,
+    journal_size: u64,
+}
+
+impl Superblock {
+    /// lay out a new filesystem on a disk of the given size
+    fn new(disk_size: u64) -> Self {
+        let inode_bitmap_sectors = NUM_INODES.div_ceil(BITS_PER_BLOCK);
+        let inode_table_size = NUM_INODES; // one inode per sector
+
+        // whatever is left is shared between the data block bitmap and the data blocks it covers
+        let remaining = disk_size / BLOCK_SIZE as u64
+            - 1
+            - JOURNAL_SIZE
+            - inode_bitmap_sectors
+            - inode_table_size;
+        let data_bitmap_sectors = remaining.div_ceil(BITS_PER_BLOCK + 1);
+        let num_data_blocks = remaining - data_bitmap_sectors;
+
+        let journal_start = 1; // superblock
+        let inode_bitmap_start = journal_start + JOURNAL_SIZE;
+        let data_bitmap_start = inode_bitmap_start + inode_bitmap_sectors;
+        let inode_table_start = data_bitmap_start + data_bitmap_sectors;
+
+        Superblock {
+            magic_number: MAGIC_NUMBER,
+            disk_size,
+            inode_table_size,
+            data_block_size: BLOCK_SIZE as u64,
+            num_inodes: NUM_INODES,
+            num_data_blocks,
+            inode_bitmap_start,
+            data_bitmap_start,
+            inode_table_start,
+            data_blocks_start: inode_table_start + inode_table_size,
+            free_inodes: NUM_INODES,
+            free_data_blocks: num_data_blocks,
+            journal_start,
+            journal_size: JOURNAL_SIZE,
+        }
+    }
+
+    /// parse the superblock from the first sector of the disk
+    fn from_sector(sector_data: &[u8]) -> Result<Self, FsError> {
+        let field = |index: usize| -> Result<u64, FsError> {
+            Ok(u64::from_le_bytes(
+                sector_data[index * 8..index * 8 + 8]
+                    .try_into()
+                    .map_err(|_| FsError::InvalidSuperblock)?,
+            ))
+        };
+
+        let superblock = Superblock {
+            magic_number: field(0)?,
+            disk_size: field(1)?,
+            inode_table_size: field(2)?,
+            data_block_size: field(3)?,
+            num_inodes: field(4)?,
+            num_data_blocks: field(5)?,
+            inode_bitmap_start: field(6)?,
+            data_bitmap_start: field(7)?,
+            inode_table_start: field(8)?,
+            data_blocks_start: field(9)?,
+            free_inodes: field(10)?,
+            free_data_blocks: field(11)?,
+            journal_start: field(12)?,
+            journal_size: field(13)?,
+        };
+
+        if superblock.magic_number != MAGIC_NUMBER {
+            return Err(FsError::InvalidSuperblock);
+        }
+
+        // the journal needs at least a header and a descriptor sector, and sits between the superblock and the bitmaps
+        if superblock.journal_size < 2
+            || superblock.journal_start == 0
+            || superblock.journal_start + superblock.journal_size > superblock.inode_bitmap_start
+        {
+            return Err(FsError::InvalidSuperblock);
+        }
+
+        Ok(superblock)
+    }
+
+    /// the journal region described by the superblock
+    fn journal(&self) -> Journal {
+        Journal::new(self.journal_start, self.journal_size)
+    }
+
+    /// serialise the superblock into a full sector
+    fn to_sector(self) -> Vec<u8> {
+        let fields = [
+            self.magic_number,
+            self.disk_size,
+            self.inode_table_size,
+            self.data_block_size,
+            self.num_inodes,
+            self.num_data_blocks,
+            self.inode_bitmap_start,
+            self.data_bitmap_start,
+            self.inode_table_start,
+            self.data_blocks_start,
+            self.free_inodes,
+            self.free_data_blocks,
+            self.journal_start,
+            self.journal_size,
+        ];
+
+        let mut sector_data = vec![0; BLOCK_SIZE];
+        for (i, field) in fields.iter().enumerate() {
+            sector_data[i * 8..i * 8 + 8].copy_from_slice(&field.to_le_bytes());
+        }
+        sector_data
+    }
+}
+
+/// an allocation bitmap, with one bit per object that is set if the object is in use
+#[derive(Debug, Clone)]
+struct Bitmap {
+    bits: Vec<u8>,
+    len: u64,
+    free: u64,
+    // every object before this one is known to be in use
+    next_free: u64,
+    // the sectors changed since the last flush, which are the only ones written back
+    dirty: BTreeSet<u64>,
+    // objects freed since the last commit, which are not handed out again until the commit that frees them is on the disk
+    pending: BTreeSet<u64>,
+}
+
+impl Bitmap {
+    fn new(len: u64) -> Self {
+        Bitmap {
+            bits: vec![0; len.div_ceil(8) as usize],
+            len,
+            free: len,
+            next_free: 0,
+            dirty: BTreeSet::new(),
+            pending: BTreeSet::new(),
+        }
+    }
+
+    fn from_bytes(bytes: &[u8], len: u64) -> Result<Self, FsError> {
+        let size = len.div_ceil(8) as usize;
+        if bytes.len() < size {
+            return Err(FsError::InvalidSuperblock);
+        }
+
+        let mut bitmap = Bitmap {
+            bits: bytes[..size].to_vec(),
+            len,
+            free: 0,
+            next_free: 0,
+            dirty: BTreeSet::new(),
+            pending: BTreeSet::new(),
+        };
+        bitmap.free = (0..len).filter(|&i| !bitmap.is_set(i)).count() as u64;
+        Ok(bitmap)
+    }
+
+    fn is_set(&self, index: u64) -> bool {
+        self.bits[(index / 8) as usize] & (1 << (index % 8)) != 0
+    }
+
+    fn set(&mut self, index: u64) {
+        if !self.is_set(index) {
+            self.bits[(index / 8) as usize] |= 1 << (index % 8);
+            self.free -= 1;
+            self.dirty.insert(index / BITS_PER_BLOCK);
+        }
+    }
+
+    fn clear(&mut self, index: u64) {
+        if self.is_set(index) {
+            self.bits[(index / 8) as usize] &= !(1 << (index % 8));
+            self.free += 1;
+            self.next_free = self.next_free.min(index);
+            self.dirty.insert(index / BITS_PER_BLOCK);
+        }
+    }
+
+    /// free an object in the open transaction, keeping it from being handed out again until that transaction has committed
+    ///
+    /// otherwise a block freed and reused before the commit could have its new contents written home while the old, committed metadata still points at it
+    fn clear_after_commit(&mut self, index: u64) {
+        if self.is_set(index) {
+            self.clear(index);
+            self.pending.insert(index);
+        }
+    }
+
+    /// let the objects freed before the last commit be handed out again, now that it is on the disk
+    fn committed(&mut self) {
+        self.pending.clear();
+    }
+
+    /// find a free object, mark it as used and return its index
+    fn allocate(&mut self) -> Option<u64> {
+        if self.free == 0 {
+            return None;
+        }
+
+        // skip over whole bytes that are already full
+        let mut byte = (self.next_free / 8) as usize;
+        while byte < self.bits.len() && self.bits[byte] == 0xff {
+            byte += 1;
+        }
+
+        let index = (byte as u64 * 8..self.len).find(|&i| !self.is_set(i) && !self.pending.contains(&i))?;
+        self.set(index);
+        self.next_free = index + 1;
+        Some(index)
+    }
+}
+
+/// free space information for a filesystem, as reported by statfs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsStats {
+    /// the size of a data block in bytes
+    pub block_size: u64,
+    /// the total number of data blocks
+    pub total_blocks: u64,
+    /// the number of data blocks not in use
+    pub free_blocks: u64,
+    /// the total number of inodes
+    pub total_inodes: u64,
+    /// the number of inodes not in use
+    pub free_inodes: u64,
+}
+
+/// information about a single file, as reported by stat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStat {
+    /// the inode index
+    pub inode: u64,
+    /// whether this is a file or a directory
+    pub kind: FileKind,
+    /// the size in bytes
+    pub size: u64,
+    /// the number of data blocks in use, including the metadata block
+    pub blocks: u64,
+    /// the number of directory entries pointing at the inode
+    pub links: u64,
+    /// the owner's user id
+    pub owner: u64,
+    /// the Unix-style permissions, one digit each for the owner, group and others
+    pub permissions: [u8; 3],
+    /// when the file was created, in seconds since the Unix epoch
+    pub creation_time: u64,
+    /// when the file was last modified, in seconds since the Unix epoch
+    pub modification_time: u64,
+    /// when the file was last accessed, in seconds since the Unix epoch
+    pub access_time: u64,
+}
+
+/// an inode
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct Inode {
+    num_data_blocks: u64,
+    data_block_pointers: [u64; 12],
+    // points to a block that contains pointers to data blocks
+    single_indirect_block_pointer: u64,
+    double_indirect_block_pointer: u64,
+    triple_indirect_block_pointer: u64,
+    links: u64,
+}
+
+/// the kind of an inode, stored in its metadata block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum FileKind {
+    /// a regular file
+    File = 0,
+    /// a directory, whose data is a table of directory entries
+    Directory = 1,
+    /// a symbolic link, whose data is the path it points to
+    Symlink = 2,
+}
+
+impl TryFrom<u64> for FileKind {
+    type Error = FsError;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(FileKind::File),
+            1 => Ok(FileKind::Directory),
+            2 => Ok(FileKind::Symlink),
+            _ => Err(FsError::InvalidMetadata),
+        }
+    }
+}
+
+/// Metadata for a file
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct FileMetadata {
+    owner: u64,
+    creation_time: u64,
+    modification_time: u64,
+    access_time: u64,
+    permissions: u64, // Unix-style
+    kind: u64,
+    size: u64, // in bytes, not including the metadata block
+}
+
+impl FileMetadata {
+    /// parse the metadata header at the start of a metadata block
+    fn from_block(block: &[u8]) -> Result<Self, FsError> {
+        let field = |start: usize| -> Result<u64, FsError> {
+            Ok(u64::from_le_bytes(
+                block[start..start + 8]
+                    .try_into()
+                    .map_err(|_| FsError::InvalidMetadata)?,
+            ))
+        };
+
+        Ok(FileMetadata {
+            owner: field(0)?,
+            creation_time: field(8)?,
+            modification_time: field(16)?,
+            access_time: field(24)?,
+            permissions: field(32)?,
+            kind: field(40)?,
+            size: field(48)?,
+        })
+    }
+
+    /// serialise the metadata into a full metadata block
+    fn to_block(&self) -> Vec<u8> {
+        let mut metadata_block = vec![0u8; BLOCK_SIZE];
+        metadata_block[..8].copy_from_slice(&self.owner.to_le_bytes());
+        metadata_block[8..16].copy_from_slice(&self.creation_time.to_le_bytes());
+        metadata_block[16..24].copy_from_slice(&self.modification_time.to_le_bytes());
+        metadata_block[24..32].copy_from_slice(&self.access_time.to_le_bytes());
+        metadata_block[32..40].copy_from_slice(&self.permissions.to_le_bytes());
+        metadata_block[40..48].copy_from_slice(&self.kind.to_le_bytes());
+        metadata_block[48..56].copy_from_slice(&self.size.to_le_bytes());
+        metadata_block
+    }
+
+    /// the kind of the inode this metadata belongs to
+    pub fn kind(&self) -> Result<FileKind, FsError> {
+        FileKind::try_from(self.kind)
+    }
+
+    /// the size of the file in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// whether a read at the given time should update the access time, which (like Linux's relatime) is only when the file has changed since it was last read, or the access time is more than a day old
+    ///
+    /// this keeps reads from turning into a metadata write every time, while still telling apart files read since their last change
+    fn needs_access_update(&self, now: u64) -> bool {
+        now > self.access_time
+            && (self.access_time <= self.modification_time
+                || now - self.access_time >= RELATIME_INTERVAL)
+    }
+}
+
+/// A physical filesystem, on any block device
+#[derive(Debug, Clone)]
+pub struct PhysFs<D: BlockDevice> {
+    superblock: Superblock,
+    inode_bitmap: Bitmap,
+    data_bitmap: Bitmap,
+    // the inode table and data blocks are only loaded when they are needed
+    cache: BlockCache<D>,
+    // the current time, in seconds since the Unix epoch
+    clock: fn() -> u64,
+    // how many times each inode is held open, as an inode that loses its last link is only freed once it is closed
+    open_inodes: BTreeMap<usize, usize>,
+}
+
+/// split a path into its parent directory and final component
+fn split_parent(path: &str) -> Result<(&str, &str), FsError> {
+    let path = path.trim_end_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+
+    match name {
+        "" | "." | ".." => Err(FsError::InvalidPath),
+        _ => Ok((parent, name)),
+    }
+}
+
+/// parse a directory table into (inode index, name) pairs, stopping at the first entry that is cut short or has an invalid name
+///
+/// the byte offset of that entry is returned alongside the entries before it, or None if the whole table is valid
+fn parse_dir_table(data: &[u8]) -> (Vec<(usize, String)>, Option<usize>) {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let Some(header) = data.get(offset..offset + DIR_ENTRY_HEADER_SIZE) else {
+            return (entries, Some(offset));
+        };
+        let child = u64::from_le_bytes(header[..8].try_into().unwrap()) as usize;
+        let len = u16::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+
+        let start = offset + DIR_ENTRY_HEADER_SIZE;
+        let name = data.get(start..start + len).map(core::str::from_utf8);
+        match name {
+            // the root is never anyone's child
+            Some(Ok(name)) if child != ROOT_INODE && valid_name(name) => entries.push((child, name.to_string())),
+            _ => return (entries, Some(offset)),
+        }
+
+        offset = start + len;
+    }
+
+    (entries, None)
+}
+
+/// serialise (inode index, name) pairs into a directory table
+fn encode_dir_table(entries: &[(usize, String)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (child, name) in entries {
+        data.extend_from_slice(&(*child as u64).to_le_bytes());
+        data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        data.extend_from_slice(name.as_bytes());
+    }
+    data
+}
+
+/// whether a name can be stored in a directory entry
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME_LEN && !name.contains(['/', '\0']) && name != "." && name != ".."
+}
+
+/// where a chain of block pointers starts in an inode
+#[derive(Debug, Clone, Copy)]
+enum BlockSlot {
+    Direct(usize),
+    SingleIndirect,
+    DoubleIndirect,
+    TripleIndirect,
+}
+
+impl BlockSlot {
+    fn get(self, inode: &Inode) -> u64 {
+        match self {
+            BlockSlot::Direct(index) => inode.data_block_pointers[index],
+            BlockSlot::SingleIndirect => inode.single_indirect_block_pointer,
+            BlockSlot::DoubleIndirect => inode.double_indirect_block_pointer,
+            BlockSlot::TripleIndirect => inode.triple_indirect_block_pointer,
+        }
+    }
+
+    fn set(self, inode: &mut Inode, pointer: u64) {
+        match self {
+            BlockSlot::Direct(index) => inode.data_block_pointers[index] = pointer,
+            BlockSlot::SingleIndirect => inode.single_indirect_block_pointer = pointer,
+            BlockSlot::DoubleIndirect => inode.double_indirect_block_pointer = pointer,
+            BlockSlot::TripleIndirect => inode.triple_indirect_block_pointer = pointer,
+        }
+    }
+}
+
+/// map a logical block number to its slot in the inode, and the index to follow in each level of indirect block
+fn block_path(block_num: u64) -> Option<(BlockSlot, Vec<usize>)> {
+    let per_block = POINTERS_PER_BLOCK as u64;
+
+    let mut n = block_num;
+    if n < 12 {
+        return Some((BlockSlot::Direct(n as usize), Vec::new()));
+    }
+
+    n -= 12;
+    if n < per_block {
+        return Some((BlockSlot::SingleIndirect, vec![n as usize]));
+    }
+
+    n -= per_block;
+    if n < per_block * per_block {
+        return Some((BlockSlot::DoubleIndirect, vec![
+            (n / per_block) as usize,
+            (n % per_block) as usize,
+        ]));
+    }
+
+    n -= per_block * per_block;
+    if n < per_block * per_block * per_block {
+        return Some((BlockSlot::TripleIndirect, vec![
+            (n / (per_block * per_block)) as usize,
+            ((n / per_block) % per_block) as usize,
+            (n % per_block) as usize,
+        ]));
+    }
+
+    None
+}
+
+impl Inode {
+    /// parse an inode from its sector in the inode table
+    fn from_sector(sector_data: &[u8]) -> Result<Self, FsError> {
+        let field = |index: usize| -> Result<u64, FsError> {
+            Ok(u64::from_le_bytes(
+                sector_data[index * 8..index * 8 + 8]
+                    .try_into()
+                    .map_err(|_| FsError::InvalidInode)?,
+            ))
+        };
+
+        let mut data_block_pointers = [0; 12];
+        for (i, pointer) in data_block_pointers.iter_mut().enumerate() {
+            *pointer = field(1 + i)?;
+        }
+
+        Ok(Inode {
+            num_data_blocks: field(0)?,
+            data_block_pointers,
+            single_indirect_block_pointer: field(13)?,
+            double_indirect_block_pointer: field(14)?,
+            triple_indirect_block_pointer: field(15)?,
+            links: field(16)?,
+        })
+    }
+
+    /// serialise the inode into a full sector
+    fn to_sector(self) -> [u8; BLOCK_SIZE] {
+        let mut sector_data = [0; BLOCK_SIZE];
+        sector_data[0..8].copy_from_slice(&self.num_data_blocks.to_le_bytes());
+        for (i, pointer) in self.data_block_pointers.iter().enumerate() {
+            sector_data[8 + i * 8..16 + i * 8].copy_from_slice(&pointer.to_le_bytes());
+        }
+        sector_data[104..112].copy_from_slice(&self.single_indirect_block_pointer.to_le_bytes());
+        sector_data[112..120].copy_from_slice(&self.double_indirect_block_pointer.to_le_bytes());
+        sector_data[120..128].copy_from_slice(&self.triple_indirect_block_pointer.to_le_bytes());
+        sector_data[128..136].copy_from_slice(&self.links.to_le_bytes());
+        sector_data
+    }
+}
+
+/// list of filesystem errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsError {
+    /// invalid path
+    InvalidPath,
+    /// file not found
+    FileNotFound,
+    /// file already exists
+    FileExists,
+    /// disk is full
+    DiskFull,
+    /// out of inodes
+    OutOfInodes,
+    /// out of data blocks
+    OutOfDataBlocks,
+    /// invalid inode
+    InvalidInode,
+    /// invalid data block
+    InvalidDataBlock,
+    /// invalid superblock
+    InvalidSuperblock,
+    /// invalid inode table
+    InvalidInodeTable,
+    /// invalid metadata
+    InvalidMetadata,
+    /// write error
+    WriteError,
+    /// read error
+    ReadError,
+    /// file is unwritable
+    UnwritableFile,
+    /// file is unreadable
+    UnreadableFile,
+    /// filesystem not found
+    FilesystemNotFound,
+    /// filesystem already exists
+    FilesystemExists,
+    /// invalid file descriptor
+    InvalidFileDescriptor,
+    /// a path component is not a directory
+    NotADirectory,
+    /// the path is a directory
+    IsADirectory,
+    /// the directory still has entries
+    DirectoryNotEmpty,
+    /// a path component is too long, or not a valid name
+    NameTooLong,
+    /// too many symbolic links were followed while resolving a path
+    TooManySymlinks,
+    /// the paths are on different mounted filesystems
+    CrossDevice,
+    /// the filesystem or mount point is still in use
+    Busy,
+    /// the filesystem cannot be modified
+    ReadOnly,
+}
+
+impl Display for FsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", match self {
+            FsError::InvalidPath => "Invalid path".to_string(),
+            FsError::FileNotFound => "File not found".to_string(),
+            FsError::FileExists => "File already exists".to_string(),
+            FsError::DiskFull => "Disk is full".to_string(),
+            FsError::OutOfInodes => "Out of inodes".to_string(),
+            FsError::OutOfDataBlocks => "Out of data blocks".to_string(),
+            FsError::InvalidInode => "Invalid inode".to_string(),
+            FsError::InvalidDataBlock => "Invalid data block".to_string(),
+            FsError::InvalidSuperblock => "Invalid superblock".to_string(),
+            FsError::InvalidInodeTable => "Invalid inode table".to_string(),
+            FsError::InvalidMetadata => "Invalid metadata".to_string(),
+            FsError::WriteError => "Write error".to_string(),
+            FsError::ReadError => "Read error".to_string(),
+            FsError::UnwritableFile => "File is unwritable".to_string(),
+            FsError::UnreadableFile => "File is unreadable".to_string(),
+            FsError::FilesystemNotFound => "Filesystem not found".to_string(),
+            FsError::FilesystemExists => "Filesystem already exists".to_string(),
+            FsError::InvalidFileDescriptor => "Invalid file descriptor".to_string(),
+            FsError::NotADirectory => "Not a directory".to_string(),
+            FsError::IsADirectory => "Is a directory".to_string(),
+            FsError::DirectoryNotEmpty => "Directory not empty".to_string(),
+            FsError::NameTooLong => "File name too long".to_string(),
+            FsError::TooManySymlinks => "Too many levels of symbolic links".to_string(),
+            FsError::CrossDevice => "Cross-device link".to_string(),
+            FsError::Busy => "Device or resource busy".to_string(),
+            FsError::ReadOnly => "Read-only filesystem".to_string(),
+        })
+    }
+}
+
+impl<D: BlockDevice> PhysFs<D> {
+    /// create a new, empty filesystem containing only the root directory - nothing is written to the disk until the first flush, which formats it
+    pub fn new(device: D, disk_size: u64, clock: fn() -> u64) -> Self {
+        let superblock = Superblock::new(disk_size);
+        let mut cache = BlockCache::new_unformatted(device, DEFAULT_CAPACITY);
+        cache.set_journal(superblock.journal());
+
+        let mut phys_fs = PhysFs {
+            superblock,
+            inode_bitmap: Bitmap::new(superblock.num_inodes),
+            data_bitmap: Bitmap::new(superblock.num_data_blocks),
+            cache,
+            clock,
+            open_inodes: BTreeMap::new(),
+        };
+
+        // block 0 is the null block pointer
+        phys_fs.data_bitmap.set(0);
+
+        // the inode table is empty, so the root directory always lands in inode 0
+        phys_fs
+            .create_inode_of_kind([7, 5, 5], 0, FileKind::Directory)
+            .expect("failed to create root directory");
+
+        phys_fs
+    }
+
+    /// get the physical block backing a logical block of an inode, allocating it (and any indirect blocks on the way) if needed
+    pub fn allocate_block(&mut self, inode_index: usize, block_num: u64) -> Result<u64, FsError> {
+        let (slot, path) = block_path(block_num).ok_or(FsError::OutOfDataBlocks)?;
+
+        let mut inode = self.read_inode(inode_index)?;
+        let mut pointer = slot.get(&inode);
+        if pointer == 0 {
+            pointer = self.allocate_data_block()?;
+            slot.set(&mut inode, pointer);
+            self.update_inode(inode_index, inode)?;
+        }
+
+        for index in path {
+            let mut next = self.read_pointer(pointer, index)?;
+            if next == 0 {
+                next = self.allocate_data_block()?;
+                self.write_pointer(pointer, index, next)?;
+            }
+            pointer = next;
+        }
+
+        Ok(pointer)
+    }
+
+    /// get the block address for a given inode and block number, or 0 if it is not allocated
+    pub fn get_block(&mut self, inode_index: usize, block_num: u64) -> Result<u64, FsError> {
+        let inode = self.read_inode(inode_index)?;
+        self.lookup_block(&inode, block_num)
+    }
+
+    fn lookup_block(&mut self, inode: &Inode, block_num: u64) -> Result<u64, FsError> {
+        let Some((slot, path)) = block_path(block_num) else {
+            return Ok(0);
+        };
+
+        let mut pointer = slot.get(inode);
+        for index in path {
+            if pointer == 0 {
+                return Ok(0);
+            }
+            pointer = self.read_pointer(pointer, index)?;
+        }
+
+        Ok(pointer)
+    }
+
+    /// read the pointer at the given index of an indirect block
+    fn read_pointer(&mut self, block: u64, index: usize) -> Result<u64, FsError> {
+        let data = self.read_data_block(block)?;
+        Ok(u64::from_le_bytes(
+            data[index * 8..index * 8 + 8].try_into().unwrap(),
+        ))
+    }
+
+    fn write_pointer(&mut self, block: u64, index: usize, pointer: u64) -> Result<(), FsError> {
+        let mut data = self.read_data_block(block)?;
+        data[index * 8..index * 8 + 8].copy_from_slice(&pointer.to_le_bytes());
+        self.write_to_data_block(block, &data)
+    }
+
+    /// every block an inode uses: its metadata and data blocks, and the indirect blocks that point at them
+    fn get_all_block_addresses(&mut self, inode: &Inode) -> Result<Vec<u64>, FsError> {
+        let mut block_addresses = Vec::new();
+
+        // Direct blocks
+        for &block in &inode.data_block_pointers {
+            if block != 0 {
+                block_addresses.push(block);
+            }
+        }
+
+        // Indirect blocks, along with everything below them
+        self.collect_blocks(inode.single_indirect_block_pointer, 1, &mut block_addresses)?;
+        self.collect_blocks(inode.double_indirect_block_pointer, 2, &mut block_addresses)?;
+        self.collect_blocks(inode.triple_indirect_block_pointer, 3, &mut block_addresses)?;
+
+        Ok(block_addresses)
+    }
+
+    /// collect an indirect block with the given depth, and every block reachable from it
+    fn collect_blocks(
+        &mut self,
+        pointer: u64,
+        depth: u32,
+        blocks: &mut Vec<u64>,
+    ) -> Result<(), FsError> {
+        if pointer == 0 {
+            return Ok(());
+        }
+
+        blocks.push(pointer);
+        if depth == 0 {
+            return Ok(());
+        }
+
+        let data = self.read_data_block(pointer)?;
+        for entry in data.chunks_exact(8) {
+            let child = u64::from_le_bytes(entry.try_into().unwrap());
+            self.collect_blocks(child, depth - 1, blocks)?;
+        }
+
+        Ok(())
+    }
+
+    /// unhook every block of an inode from the given logical block on, along with any indirect block left with nothing below it
+    ///
+    /// the blocks are returned rather than freed, so the caller can free them with free_data_blocks once nothing points at them
+    fn release_blocks_from(&mut self, inode_index: usize, first: u64) -> Result<Vec<u64>, FsError> {
+        let per_block = POINTERS_PER_BLOCK as u64;
+        let mut inode = self.read_inode(inode_index)?;
+        let mut released = Vec::new();
+
+        for (i, pointer) in inode.data_block_pointers.iter_mut().enumerate() {
+            if i as u64 >= first && *pointer != 0 {
+                released.push(*pointer);
+                *pointer = 0;
+            }
+        }
+
+        let trees = [
+            (BlockSlot::SingleIndirect, 1, 12),
+            (BlockSlot::DoubleIndirect, 2, 12 + per_block),
+            (BlockSlot::TripleIndirect, 3, 12 + per_block + per_block * per_block),
+        ];
+        for (slot, depth, base) in trees {
+            if self.release_tree(slot.get(&inode), depth, base, first, &mut released)? {
+                slot.set(&mut inode, 0);
+            }
+        }
+
+        self.update_inode(inode_index, inode)?;
+        Ok(released)
+    }
+
+    /// release the blocks from the given logical block on below an indirect block of the given depth, whose first block is base, adding them to released
+    ///
+    /// returns whether the indirect block itself was released, because nothing is left below it
+    fn release_tree(
+        &mut self,
+        pointer: u64,
+        depth: u32,
+        base: u64,
+        first: u64,
+        released: &mut Vec<u64>,
+    ) -> Result<bool, FsError> {
+        if pointer == 0 {
+            return Ok(true);
+        }
+        if depth == 0 {
+            if base >= first {
+                released.push(pointer);
+            }
+            return Ok(base >= first);
+        }
+
+        let span = (POINTERS_PER_BLOCK as u64).pow(depth - 1);
+        let mut data = self.read_data_block(pointer)?;
+        let (mut changed, mut empty) = (false, true);
+        for (i, entry) in data.chunks_exact_mut(8).enumerate() {
+            let child = u64::from_le_bytes((&*entry).try_into().unwrap());
+            let child_base = base + i as u64 * span;
+            if child == 0 {
+                continue;
+            }
+
+            if child_base + span > first && self.release_tree(child, depth - 1, child_base, first, released)? {
+                entry.fill(0);
+                changed = true;
+            } else {
+                empty = false;
+            }
+        }
+
+        if empty {
+            released.push(pointer);
+        } else if changed {
+            self.write_to_data_block(pointer, &data)?;
+        }
+        Ok(empty)
+    }
+
+    /// load the filesystem on a device, finishing the last flush if it was interrupted
+    pub fn read_from_disk(device: D, clock: fn() -> u64) -> Result<Self, FsError> {
+        Self::load(device, clock, true)
+    }
+
+    /// read the superblock and bitmaps, first finishing the last flush if it was interrupted and replay_journal is set
+    fn load(device: D, clock: fn() -> u64, replay_journal: bool) -> Result<Self, FsError> {
+        let mut cache = BlockCache::new(device, DEFAULT_CAPACITY);
+
+        // read the superblock from the disk (it takes up the first sector)
+        let sector_data = cache.read(0)?;
+        let mut superblock = Superblock::from_sector(&sector_data)?;
+
+        // finish the last flush if it was interrupted, which may change anything read so far
+        cache.set_journal(superblock.journal());
+        let replayed = if replay_journal {
+            cache.replay_journal()?
+        } else {
+            0
+        };
+        if replayed > 0 {
+            warn!("Replayed {} blocks from the journal", replayed);
+            superblock = Superblock::from_sector(&cache.read(0)?)?;
+        }
+
+        // read the bitmaps from the disk, the inode table and data blocks are read as they are used
+        let mut read_bitmap = |start: u64, end: u64, len: u64| -> Result<Bitmap, FsError> {
+            let mut bytes = Vec::new();
+            for sector in start..end {
+                bytes.extend(cache.read(sector)?);
+            }
+            Bitmap::from_bytes(&bytes, len)
+        };
+
+        let inode_bitmap = read_bitmap(
+            superblock.inode_bitmap_start,
+            superblock.data_bitmap_start,
+            superblock.num_inodes,
+        )?;
+        let data_bitmap = read_bitmap(
+            superblock.data_bitmap_start,
+            superblock.inode_table_start,
+            superblock.num_data_blocks,
+        )?;
+
+        Ok(PhysFs {
+            superblock,
+            inode_bitmap,
+            data_bitmap,
+            cache,
+            clock,
+            open_inodes: BTreeMap::new(),
+        })
+    }
+
+    /// give back the device, dropping anything that has not been flushed
+    pub fn into_device(self) -> D {
+        self.cache.into_device()
+    }
+
+    /// write all pending changes to the disk, formatting it first if this is a new filesystem
+    pub fn flush(&mut self) -> Result<(), FsError> {
+        // update the superblock, with up to date free counts
+        let superblock = Superblock {
+            free_inodes: self.inode_bitmap.free,
+            free_data_blocks: self.data_bitmap.free,
+            ..self.superblock
+        };
+        self.write_sector(0, &superblock.to_sector())?;
+
+        // update the sectors of the bitmaps that changed
+        for (bitmap, start) in [
+            (&mut self.inode_bitmap, superblock.inode_bitmap_start),
+            (&mut self.data_bitmap, superblock.data_bitmap_start),
+        ] {
+            for sector in core::mem::take(&mut bitmap.dirty) {
+                let chunk = bitmap.bits.chunks(BLOCK_SIZE).nth(sector as usize).unwrap();
+                let mut sector_data = [0; BLOCK_SIZE];
+                sector_data[..chunk.len()].copy_from_slice(chunk);
+                self.cache.write(start + sector, &sector_data)?;
+            }
+        }
+
+        if self.cache.is_unformatted() {
+            // every metadata sector must be valid on the disk, the data blocks are zeroed as they are allocated
+            self.cache.format(0..superblock.data_blocks_start)?;
+        } else {
+            self.cache.flush()?;
+        }
+
+        self.data_bitmap.committed();
+        Ok(())
+    }
+
+    /// commit the metadata written so far if the journal is filling up - only called where the filesystem is consistent
+    ///
+    /// every operation calls this at least every few blocks it changes, so no commit ever outgrows the journal
+    fn commit_if_needed(&mut self) -> Result<(), FsError> {
+        // the superblock and the changed bitmap sectors join the commit too
+        let pending = 1 + self.inode_bitmap.dirty.len() + self.data_bitmap.dirty.len();
+        if self.cache.needs_commit(pending) {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// run an operation, then commit if the journal is filling up
+    ///
+    /// an operation that fits in one commit is all or nothing after a crash. writes and frees too big for the journal commit in pieces, each leaving the filesystem consistent.
+    /// nothing is rolled back if the operation fails - whatever it changed before the error stays in the cache and is committed by the next flush
+    pub fn transaction<T>(
+        &mut self,
+        op: impl FnOnce(&mut Self) -> Result<T, FsError>,
+    ) -> Result<T, FsError> {
+        let result = op(self)?;
+        self.commit_if_needed()?;
+        Ok(result)
+    }
+
+    fn write_sector(&mut self, sector: u64, data: &[u8]) -> Result<(), FsError> {
+        let mut sector_data = [0; BLOCK_SIZE];
+        sector_data[..data.len()].copy_from_slice(data);
+        self.cache.write(sector, &sector_data)
+    }
+
+    /// take a free data block from the bitmap, returning it zeroed
+    fn allocate_data_block(&mut self) -> Result<u64, FsError> {
+        let block = self.data_bitmap.allocate().ok_or(FsError::DiskFull)?;
+        // nothing points at the block yet, so it does not need journaling until it is written as metadata
+        self.write_file_block(block, &[0; BLOCK_SIZE])?;
+        Ok(block)
+    }
+
+    /// return a data block to the bitmap, though it is not reused until the next commit
+    fn free_data_block(&mut self, block: u64) {
+        if block != 0 && block < self.superblock.num_data_blocks {
+            self.data_bitmap.clear_after_commit(block);
+        }
+    }
+
+    /// return blocks that nothing points at any more to the bitmap, committing along the way so that freeing a huge file fits in the journal
+    ///
+    /// a crash part way through only leaks the blocks not freed yet, which fsck reclaims
+    fn free_data_blocks(&mut self, blocks: &[u64]) -> Result<(), FsError> {
+        for &block in blocks {
+            self.free_data_block(block);
+            self.commit_if_needed()?;
+        }
+
+        Ok(())
+    }
+
+    /// report the free space on the filesystem
+    pub fn statfs(&self) -> FsStats {
+        FsStats {
+            block_size: self.superblock.data_block_size,
+            total_blocks: self.superblock.num_data_blocks,
+            free_blocks: self.data_bitmap.free,
+            total_inodes: self.superblock.num_inodes,
+            free_inodes: self.inode_bitmap.free,
+        }
+    }
+
+    /// report information about a single file, following a symbolic link to what it points at
+    pub fn stat(&mut self, path: &str) -> Result<FileStat, FsError> {
+        let inode_index = self.find_inode_index(path)?;
+        self.stat_inode(inode_index)
+    }
+
+    /// report information about a single file, describing a symbolic link itself rather than what it points at
+    pub fn lstat(&mut self, path: &str) -> Result<FileStat, FsError> {
+        let inode_index = self.find_entry(path)?;
+        self.stat_inode(inode_index)
+    }
+
+    fn stat_inode(&mut self, inode_index: usize) -> Result<FileStat, FsError> {
+        let inode = self.read_inode(inode_index)?;
+        let metadata = self.read_metadata(&inode)?;
+
+        Ok(FileStat {
+            inode: inode_index as u64,
+            kind: metadata.kind()?,
+            size: metadata.size,
+            blocks: inode.num_data_blocks,
+            links: inode.links,
+            owner: metadata.owner,
+            // perms will never be more than 3 bytes
+            permissions: metadata.permissions.to_le_bytes()[0..3].try_into().unwrap(),
+            creation_time: metadata.creation_time,
+            modification_time: metadata.modification_time,
+            access_time: metadata.access_time,
+        })
+    }
+
+    fn write_to_data_block(&mut self, data_block: u64, data: &[u8]) -> Result<(), FsError> {
+        if data_block >= self.superblock.num_data_blocks {
+            return Err(FsError::InvalidDataBlock);
+        }
+
+        let mut block_data = [0; BLOCK_SIZE];
+        if data.len() < BLOCK_SIZE {
+            block_data = self.read_data_block(data_block)?;
+        }
+        block_data[..data.len()].copy_from_slice(data);
+
+        self.write_sector(self.superblock.data_blocks_start + data_block, &block_data)
+    }
+
+    /// write a block of file contents, which bypasses the journal
+    fn write_file_block(&mut self, data_block: u64, data: &[u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        if data_block >= self.superblock.num_data_blocks {
+            return Err(FsError::InvalidDataBlock);
+        }
+
+        self.cache
+            .write_data(self.superblock.data_blocks_start + data_block, data)
+    }
+
+    fn read_data_block(&mut self, data_block: u64) -> Result<[u8; BLOCK_SIZE], FsError> {
+        if data_block >= self.superblock.num_data_blocks {
+            return Err(FsError::InvalidDataBlock);
+        }
+
+        self.cache
+            .read(self.superblock.data_blocks_start + data_block)
+    }
+
+    fn read_inode(&mut self, inode_index: usize) -> Result<Inode, FsError> {
+        if inode_index as u64 >= self.superblock.num_inodes {
+            return Err(FsError::InvalidInode);
+        }
+
+        let sector_data = self
+            .cache
+            .read(self.superblock.inode_table_start + inode_index as u64)?;
+        Inode::from_sector(&sector_data)
+    }
+
+    fn create_inode(&mut self, inode: Inode) -> Result<usize, FsError> {
+        let inode_index = self.inode_bitmap.allocate().ok_or(FsError::OutOfInodes)? as usize;
+        self.update_inode(inode_index, inode)?;
+        Ok(inode_index)
+    }
+
+    fn update_inode(&mut self, inode_index: usize, inode: Inode) -> Result<(), FsError> {
+        if inode_index as u64 >= self.superblock.num_inodes {
+            return Err(FsError::InvalidInode);
+        }
+
+        self.write_sector(
+            self.superblock.inode_table_start + inode_index as u64,
+            &inode.to_sector(),
+        )
+    }
+
+    fn read_metadata(&mut self, inode: &Inode) -> Result<FileMetadata, FsError> {
+        // the first data block contains the metadata
+        FileMetadata::from_block(&self.read_data_block(inode.data_block_pointers[0])?)
+    }
+
+    fn write_metadata(&mut self, inode: &Inode, metadata: &FileMetadata) -> Result<(), FsError> {
+        self.write_to_data_block(inode.data_block_pointers[0], &metadata.to_block())
+    }
+
+    /// allocate an inode and its metadata block, without linking it into a directory
+    fn create_inode_of_kind(&mut self, perms: [u8; 3], owner: u64, kind: FileKind) -> Result<usize, FsError> {
+        let mut inode = Inode {
+            num_data_blocks: 1,
+            links: 1,
+            ..Inode::default()
+        };
+
+        let data_block = self.allocate_data_block()?;
+        inode.data_block_pointers[0] = data_block;
+
+        let now = (self.clock)();
+        let metadata = FileMetadata {
+            owner,
+            creation_time: now,
+            modification_time: now,
+            access_time: now,
+            permissions: u64::from_le_bytes([perms[0], perms[1], perms[2], 0, 0, 0, 0, 0]),
+            kind: kind as u64,
+            size: 0,
+        };
+
+        self.write_metadata(&inode, &metadata)?;
+
+        self.create_inode(inode)
+            .inspect_err(|_| self.free_data_block(data_block))
+    }
+
+    /// create a new inode of the given kind and link it into its parent directory
+    fn create_entry(
+        &mut self,
+        path: &str,
+        perms: [u8; 3],
+        owner: u64,
+        kind: FileKind,
+    ) -> Result<usize, FsError> {
+        let (parent, name) = split_parent(path)?;
+        let parent_index = self.find_inode_index(parent)?;
+
+        if self.find_dir_entry(parent_index, name)?.is_some() {
+            return Err(FsError::FileExists);
+        }
+
+        let inode_index = self.create_inode_of_kind(perms, owner, kind)?;
+        if let Err(err) = self.add_dir_entry(parent_index, name, inode_index) {
+            self.free_inode(inode_index)?;
+            return Err(err);
+        }
+
+        Ok(inode_index)
+    }
+
+    /// create a new, empty file
+    pub fn create_file(&mut self, file_name: &str, perms: [u8; 3], owner: u64) -> Result<(), FsError> {
+        self.transaction(|fs| fs.create_entry(file_name, perms, owner, FileKind::File))?;
+        Ok(())
+    }
+
+    /// create a new, empty directory
+    pub fn mkdir(&mut self, path: &str, perms: [u8; 3], owner: u64) -> Result<(), FsError> {
+        self.transaction(|fs| fs.create_entry(path, perms, owner, FileKind::Directory))?;
+        Ok(())
+    }
+
+    /// create a symbolic link at path, pointing at target (which does not need to exist)
+    pub fn symlink(&mut self, target: &str, path: &str, owner: u64) -> Result<(), FsError> {
+        if target.is_empty() {
+            return Err(FsError::InvalidPath);
+        }
+
+        self.transaction(|fs| {
+            let inode_index = fs.create_entry(path, [7, 7, 7], owner, FileKind::Symlink)?;
+            fs.write_inode_data(inode_index, target.as_bytes(), None, None)
+        })
+    }
+
+    /// read the path a symbolic link points at
+    pub fn readlink(&mut self, path: &str) -> Result<String, FsError> {
+        let inode_index = self.find_entry(path)?;
+        if self.kind_of(inode_index)? != FileKind::Symlink {
+            return Err(FsError::InvalidPath);
+        }
+
+        let inode = self.read_inode(inode_index)?;
+        String::from_utf8(self.read_inode_data(&inode)?).map_err(|_| FsError::InvalidMetadata)
+    }
+
+    /// give an existing file another name (a hard link) - directories can only have one
+    pub fn link(&mut self, existing: &str, new: &str) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            let inode_index = fs.find_entry(existing)?;
+            if fs.kind_of(inode_index)? == FileKind::Directory {
+                return Err(FsError::IsADirectory);
+            }
+
+            let (parent, name) = split_parent(new)?;
+            let parent_index = fs.find_inode_index(parent)?;
+            if fs.find_dir_entry(parent_index, name)?.is_some() {
+                return Err(FsError::FileExists);
+            }
+
+            fs.add_dir_entry(parent_index, name, inode_index)?;
+            let mut inode = fs.read_inode(inode_index)?;
+            inode.links += 1;
+            fs.update_inode(inode_index, inode)
+        })
+    }
+
+    /// move a file or directory to a new path, replacing what is there unless it is a non-empty directory
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            let (old_parent, old_name) = split_parent(old)?;
+            let old_parent_index = fs.find_inode_index(old_parent)?;
+            let inode_index = fs
+                .find_dir_entry(old_parent_index, old_name)?
+                .ok_or(FsError::FileNotFound)?;
+            let kind = fs.kind_of(inode_index)?;
+
+            let (new_parent, new_name) = split_parent(new)?;
+            if !valid_name(new_name) {
+                return Err(FsError::NameTooLong);
+            }
+
+            // a directory cannot be moved inside itself
+            let new_parents = fs.resolve(new_parent, true)?;
+            if kind == FileKind::Directory && new_parents.contains(&inode_index) {
+                return Err(FsError::InvalidPath);
+            }
+            let new_parent_index = *new_parents.last().unwrap();
+
+            if let Some(existing) = fs.find_dir_entry(new_parent_index, new_name)? {
+                let existing_kind = fs.kind_of(existing)?;
+                if !path::check_replace((inode_index, kind), (existing, existing_kind), || {
+                    Ok(fs.read_dir(existing)?.is_empty())
+                })? {
+                    return Ok(());
+                }
+
+                fs.remove_dir_entry(new_parent_index, new_name)?;
+                fs.drop_link(existing)?;
+            }
+
+            fs.remove_dir_entry(old_parent_index, old_name)?;
+            fs.add_dir_entry(new_parent_index, new_name, inode_index)
+        })
+    }
+
+    /// remove an empty directory
+    pub fn rmdir(&mut self, path: &str) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            let inode_index = fs.find_entry(path)?;
+            if inode_index == ROOT_INODE {
+                return Err(FsError::InvalidPath);
+            }
+
+            if fs.kind_of(inode_index)? != FileKind::Directory {
+                return Err(FsError::NotADirectory);
+            }
+
+            if !fs.read_dir(inode_index)?.is_empty() {
+                return Err(FsError::DirectoryNotEmpty);
+            }
+
+            fs.unlink_entry(path)?;
+            fs.drop_link(inode_index)
+        })
+    }
+
+    /// remove a file or symbolic link (not a directory) from the filesystem, freeing it once its last hard link is gone
+    pub fn delete(&mut self, path: &str) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            let inode_index = fs.find_entry(path)?;
+            if fs.kind_of(inode_index)? == FileKind::Directory {
+                return Err(FsError::IsADirectory);
+            }
+
+            fs.unlink_entry(path)?;
+            fs.drop_link(inode_index)
+        })
+    }
+
+    /// remove the entry for a path from its parent directory
+    fn unlink_entry(&mut self, path: &str) -> Result<(), FsError> {
+        let (parent, name) = split_parent(path)?;
+        let parent_index = self.find_inode_index(parent)?;
+        self.remove_dir_entry(parent_index, name)
+    }
+
+    /// account for a removed directory entry, freeing the inode if it was the last one and nothing holds it open
+    fn drop_link(&mut self, inode_index: usize) -> Result<(), FsError> {
+        let mut inode = self.read_inode(inode_index)?;
+        if inode.links > 1 || self.open_inodes.contains_key(&inode_index) {
+            inode.links -= 1;
+            self.update_inode(inode_index, inode)
+        } else {
+            self.free_inode(inode_index)
+        }
+    }
+
+    /// hold an inode open, so it is not freed while it is in use even if its last link is removed
+    pub fn open_inode(&mut self, inode_index: usize) {
+        *self.open_inodes.entry(inode_index).or_insert(0) += 1;
+    }
+
+    /// let go of an inode held open, freeing it if it was the last hold and it has no links left
+    pub fn close_inode(&mut self, inode_index: usize) -> Result<(), FsError> {
+        let Some(count) = self.open_inodes.get_mut(&inode_index) else {
+            return Ok(());
+        };
+        *count -= 1;
+        if *count > 0 {
+            return Ok(());
+        }
+
+        self.open_inodes.remove(&inode_index);
+        self.transaction(|fs| {
+            if fs.read_inode(inode_index)?.links == 0 {
+                fs.free_inode(inode_index)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn kind_of(&mut self, inode_index: usize) -> Result<FileKind, FsError> {
+        let inode = self.read_inode(inode_index)?;
+        self.read_metadata(&inode)?.kind()
+    }
+
+    /// release every block an inode uses, indirect blocks included, and mark its slot as free without moving any other inode
+    ///
+    /// the inode must not be linked from any directory any more
+    fn free_inode(&mut self, inode_index: usize) -> Result<(), FsError> {
+        let inode = self.read_inode(inode_index)?;
+        let blocks = self.get_all_block_addresses(&inode)?;
+        self.update_inode(inode_index, Inode::default())?;
+        self.inode_bitmap.clear(inode_index as u64);
+        self.free_data_blocks(&blocks)
+    }
+
+    fn read_inode_data(&mut self, inode: &Inode) -> Result<Vec<u8>, FsError> {
+        let mut data = vec![0; self.read_metadata(inode)?.size as usize];
+        self.read_inode_range(inode, 0, &mut data)?;
+        Ok(data)
+    }
+
+    /// read part of an inode's data, only touching the blocks that cover it
+    fn read_inode_range(
+        &mut self,
+        inode: &Inode,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<(), FsError> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let start = pos % BLOCK_SIZE;
+            let len = (BLOCK_SIZE - start).min(buf.len() - done);
+
+            // + 1 because the first block is the metadata block
+            let data_block = self.lookup_block(inode, (pos / BLOCK_SIZE) as u64 + 1)?;
+            if data_block == 0 {
+                // a block that was never written reads as zeroes
+                buf[done..done + len].fill(0);
+            } else {
+                let data = self.read_data_block(data_block)?;
+                buf[done..done + len].copy_from_slice(&data[start..start + len]);
+            }
+
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    /// read from an inode starting at a byte offset, returning the number of bytes read (0 at the end of the file)
+    pub fn read_at(
+        &mut self,
+        inode_index: usize,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, FsError> {
+        let inode = self.read_inode(inode_index)?;
+        let size = self.touch_access_time(&inode)?.size as usize;
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let len = buf.len().min(size - offset);
+        self.read_inode_range(&inode, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    /// write to an inode starting at a byte offset, allocating only the blocks that cover the written range
+    ///
+    /// the file size is committed last, so a write interrupted by a crash never exposes blocks that were not written
+    pub fn write_at(
+        &mut self,
+        inode_index: usize,
+        offset: usize,
+        buf: &[u8],
+    ) -> Result<usize, FsError> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let start = pos % BLOCK_SIZE;
+            let len = (BLOCK_SIZE - start).min(buf.len() - done);
+
+            let data_block = self.allocate_block(inode_index, (pos / BLOCK_SIZE) as u64 + 1)?;
+            let mut data = [0; BLOCK_SIZE];
+            if len < BLOCK_SIZE {
+                data = self.read_data_block(data_block)?;
+            }
+            data[start..start + len].copy_from_slice(&buf[done..done + len]);
+            self.write_file_block(data_block, &data)?;
+
+            done += len;
+
+            // a large write may not fit in the journal at once, but every block allocated so far is already reachable from the inode
+            self.commit_if_needed()?;
+        }
+
+        let mut inode = self.read_inode(inode_index)?;
+        let mut metadata = self.read_metadata(&inode)?;
+        metadata.size = metadata.size.max((offset + buf.len()) as u64);
+        metadata.modification_time = (self.clock)();
+        self.write_metadata(&inode, &metadata)?;
+
+        inode.num_data_blocks = inode
+            .num_data_blocks
+            .max(metadata.size.div_ceil(BLOCK_SIZE as u64) + 1); // metadata block
+        self.update_inode(inode_index, inode)?;
+
+        Ok(buf.len())
+    }
+
+    /// note a read of an inode, updating its access time if the relatime policy calls for it, and return its metadata
+    fn touch_access_time(&mut self, inode: &Inode) -> Result<FileMetadata, FsError> {
+        let mut metadata = self.read_metadata(inode)?;
+        let now = (self.clock)();
+        if metadata.needs_access_update(now) {
+            metadata.access_time = now;
+            self.write_metadata(inode, &metadata)?;
+        }
+
+        Ok(metadata)
+    }
+
+    /// read the whole of a file, along with its metadata
+    pub fn read_file(&mut self, file_name: &str) -> Result<(Vec<u8>, FileMetadata), FsError> {
+        let inode = self.find_inode_by_name(file_name)?;
+        let metadata = self.touch_access_time(&inode)?;
+
+        let data = self.read_inode_data(&inode)?;
+
+        Ok((data, metadata))
+    }
+
+    /// read the entries of a directory, as (inode index, name) pairs
+    pub fn read_dir(&mut self, dir_index: usize) -> Result<Vec<(usize, String)>, FsError> {
+        let data = self.read_dir_table(dir_index)?;
+        match parse_dir_table(&data) {
+            (entries, None) => Ok(entries),
+            (_, Some(_)) => Err(FsError::InvalidInode),
+        }
+    }
+
+    /// read the raw entry table of a directory
+    fn read_dir_table(&mut self, dir_index: usize) -> Result<Vec<u8>, FsError> {
+        let inode = self.read_inode(dir_index)?;
+        if self.read_metadata(&inode)?.kind()? != FileKind::Directory {
+            return Err(FsError::NotADirectory);
+        }
+
+        self.read_inode_data(&inode)
+    }
+
+    fn find_dir_entry(&mut self, dir_index: usize, name: &str) -> Result<Option<usize>, FsError> {
+        Ok(self
+            .read_dir(dir_index)?
+            .into_iter()
+            .find(|(_, entry_name)| entry_name == name)
+            .map(|(child, _)| child))
+    }
+
+    fn add_dir_entry(&mut self, dir_index: usize, name: &str, child: usize) -> Result<(), FsError> {
+        if !valid_name(name) {
+            return Err(FsError::NameTooLong);
+        }
+
+        let mut entries = self.read_dir(dir_index)?;
+        entries.push((child, name.to_string()));
+        self.write_inode_data(dir_index, &encode_dir_table(&entries), None, None)
+    }
+
+    fn remove_dir_entry(&mut self, dir_index: usize, name: &str) -> Result<(), FsError> {
+        let mut entries = self.read_dir(dir_index)?;
+        let slot = entries
+            .iter()
+            .position(|(_, entry_name)| entry_name == name)
+            .ok_or(FsError::FileNotFound)?;
+        entries.remove(slot);
+
+        self.write_inode_data(dir_index, &encode_dir_table(&entries), None, None)
+    }
+
+    /// resolve a path to an inode index by walking the directory tree from the root, following symbolic links
+    pub fn find_inode_index(&mut self, path: &str) -> Result<usize, FsError> {
+        Ok(*self.resolve(path, true)?.last().unwrap())
+    }
+
+    /// resolve a path like find_inode_index, except that a symbolic link at the end of it is not followed
+    fn find_entry(&mut self, path: &str) -> Result<usize, FsError> {
+        Ok(*self.resolve(path, false)?.last().unwrap())
+    }
+
+    /// resolve a path to the chain of directories leading to it from the root, ending with the inode it names
+    ///
+    /// symbolic links are followed wherever they appear, except at the end of the path when follow_last is false
+    fn resolve(&mut self, path: &str, follow_last: bool) -> Result<Vec<usize>, FsError> {
+        path::resolve(ROOT_INODE, path, follow_last, |dir_index, name, follow| {
+            let child = self.find_dir_entry(dir_index, name)?.ok_or(FsError::FileNotFound)?;
+            if follow && self.kind_of(child)? == FileKind::Symlink {
+                let inode = self.read_inode(child)?;
+                let target = self.read_inode_data(&inode)?;
+                let target = String::from_utf8(target).map_err(|_| FsError::InvalidMetadata)?;
+                return Ok(Lookup::Symlink(target));
+            }
+            Ok(Lookup::Inode(child))
+        })
+    }
+
+    fn find_inode_by_name(&mut self, file_name: &str) -> Result<Inode, FsError> {
+        let inode_index = self.find_inode_index(file_name)?;
+        self.read_inode(inode_index)
+    }
+
+    /// replace the whole contents of a file
+    pub fn write_file(
+        &mut self,
+        file_name: &str,
+        data: &[u8],
+        perms: Option<[u8; 3]>,
+        owner: Option<u64>,
+    ) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            let inode_index = fs.find_inode_index(file_name)?;
+            fs.write_inode_data(inode_index, data, perms, owner)
+        })
+    }
+
+    /// cut a file down to the given length, freeing the blocks past it, or grow it with a hole that reads as zeroes
+    pub fn truncate(&mut self, path: &str, len: u64) -> Result<(), FsError> {
+        let inode_index = self.find_inode_index(path)?;
+        self.truncate_inode(inode_index, len)
+    }
+
+    /// truncate an inode, as truncate does for a path
+    pub fn truncate_inode(&mut self, inode_index: usize, len: u64) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            let mut inode = fs.read_inode(inode_index)?;
+            let mut metadata = fs.read_metadata(&inode)?;
+            if metadata.kind()? == FileKind::Directory {
+                return Err(FsError::IsADirectory);
+            }
+
+            let mut released = Vec::new();
+            if len < metadata.size {
+                // + 1 because the first block is the metadata block
+                released = fs.release_blocks_from(inode_index, len.div_ceil(BLOCK_SIZE as u64) + 1)?;
+                inode = fs.read_inode(inode_index)?;
+
+                // clear the rest of the last block, so growing the file again reads zeroes there
+                let tail = len as usize % BLOCK_SIZE;
+                let last_block = fs.lookup_block(&inode, len / BLOCK_SIZE as u64 + 1)?;
+                if tail != 0 && last_block != 0 {
+                    let mut data = fs.read_data_block(last_block)?;
+                    data[tail..].fill(0);
+                    fs.write_file_block(last_block, &data)?;
+                }
+            }
+
+            metadata.size = len;
+            metadata.modification_time = (fs.clock)();
+            fs.write_metadata(&inode, &metadata)?;
+
+            // growing leaves a hole, which has no blocks to count
+            inode.num_data_blocks = inode.num_data_blocks.min(len.div_ceil(BLOCK_SIZE as u64) + 1); // metadata block
+            fs.update_inode(inode_index, inode)?;
+            fs.free_data_blocks(&released)
+        })
+    }
+
+    /// change the permissions of a file
+    pub fn chmod(&mut self, path: &str, perms: [u8; 3]) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            let inode = fs.find_inode_by_name(path)?;
+            let mut metadata = fs.read_metadata(&inode)?;
+            metadata.permissions =
+                u64::from_le_bytes([perms[0], perms[1], perms[2], 0, 0, 0, 0, 0]);
+            fs.write_metadata(&inode, &metadata)
+        })
+    }
+
+    /// change the owner of a file
+    pub fn chown(&mut self, path: &str, owner: u64) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            let inode = fs.find_inode_by_name(path)?;
+            let mut metadata = fs.read_metadata(&inode)?;
+            metadata.owner = owner;
+            fs.write_metadata(&inode, &metadata)
+        })
+    }
+
+    fn write_inode_data(
+        &mut self,
+        inode_index: usize,
+        data: &[u8],
+        perms: Option<[u8; 3]>,
+        owner: Option<u64>,
+    ) -> Result<(), FsError> {
+        // first, pad out data to be a multiple of 512 bytes
+        let size = data.len() as u64;
+        let num_data_blocks = data.len().div_ceil(BLOCK_SIZE);
+        let mut data = data.to_vec();
+        data.resize(num_data_blocks * BLOCK_SIZE, 0);
+
+        let inode = self.read_inode(inode_index)?;
+
+        // write in the metadata block
+        let existing_metadata = self.read_metadata(&inode)?;
+
+        let metadata = FileMetadata {
+            owner: owner.unwrap_or(existing_metadata.owner),
+            creation_time: existing_metadata.creation_time,
+            modification_time: (self.clock)(),
+            access_time: existing_metadata.access_time,
+            permissions: perms.map_or(existing_metadata.permissions, |p| {
+                u64::from_le_bytes([p[0], p[1], p[2], 0, 0, 0, 0, 0])
+            }),
+            kind: existing_metadata.kind,
+            size,
+        };
+
+        // now add the data to the data blocks, reusing any blocks the inode already has
+        let is_directory = metadata.kind()? == FileKind::Directory;
+        for (i, chunk) in data.chunks(BLOCK_SIZE).enumerate() {
+            // i + 1 because the first block is the metadata block
+            let data_block = self.allocate_block(inode_index, i as u64 + 1)?;
+
+            // directory tables are metadata, so they go through the journal, all in one commit
+            if is_directory {
+                self.write_to_data_block(data_block, chunk)?;
+            } else {
+                self.write_file_block(data_block, chunk.try_into().unwrap())?;
+                // as in write_at, every block allocated so far is already reachable from the inode
+                self.commit_if_needed()?;
+            }
+        }
+
+        // the size is written once the data is, as in write_at
+        self.write_metadata(&inode, &metadata)?;
+
+        // anything past the new end, left from when the data was longer, is given back
+        let released = self.release_blocks_from(inode_index, num_data_blocks as u64 + 1)?;
+
+        // now update the inode with the new block count
+        let mut updated_inode = self.read_inode(inode_index)?;
+        updated_inode.num_data_blocks = num_data_blocks as u64 + 1; // metadata block
+        self.update_inode(inode_index, updated_inode)?;
+
+        self.free_data_blocks(&released)?;
+
+        Ok(())
+    }
+}
+
+
+/// a fixed clock for tests
+#[cfg(test)]
+fn test_clock() -> u64 {
+    1_700_000_000
+}
+
+/// test that the superblock, inodes and metadata headers read back exactly as they were written
+#[test]
+fn test_encoding_round_trip() {
+    let superblock = Superblock::new((1 + 1024 + 1024) * 512);
+    let decoded = Superblock::from_sector(&superblock.to_sector()).unwrap();
+    assert_eq!(decoded.to_sector(), superblock.to_sector());
+    assert_eq!(decoded.journal_start + decoded.journal_size, decoded.inode_bitmap_start);
+
+    let mut inode = Inode {
+        num_data_blocks: 3,
+        single_indirect_block_pointer: 7,
+        double_indirect_block_pointer: 8,
+        triple_indirect_block_pointer: 9,
+        links: 2,
+        ..Inode::default()
+    };
+    inode.data_block_pointers[0] = 5;
+    inode.data_block_pointers[11] = 6;
+    assert_eq!(Inode::from_sector(&inode.to_sector()).unwrap().to_sector(), inode.to_sector());
+
+    let long_name = "n".repeat(MAX_NAME_LEN);
+    let entries = vec![(1, "bin".to_string()), (2, long_name.clone()), (3, "ünïcode".to_string())];
+    let table = encode_dir_table(&entries);
+    assert_eq!(parse_dir_table(&table), (entries.clone(), None));
+    // a table cut off part way through an entry keeps the entries before it
+    assert_eq!(parse_dir_table(&table[..table.len() - 1]), (entries[..2].to_vec(), Some(13 + 10 + MAX_NAME_LEN)));
+
+    let metadata = FileMetadata {
+        owner: 1000,
+        creation_time: 1,
+        modification_time: 2,
+        access_time: 3,
+        permissions: 0x050507,
+        kind: FileKind::Directory as u64,
+        size: 4096,
+    };
+    let decoded = FileMetadata::from_block(&metadata.to_block()).unwrap();
+    assert_eq!(decoded.to_block(), metadata.to_block());
+    assert_eq!(decoded.kind(), Ok(FileKind::Directory));
+    assert_eq!(decoded.size(), 4096);
+
+    // a sector without the magic number is not a superblock
+    assert_eq!(Superblock::from_sector(&[0; BLOCK_SIZE]).err(), Some(FsError::InvalidSuperblock));
+}
+
+/// test that logical blocks map to the right inode slot and indirect block indices
+#[test]
+fn test_block_path() {
+    let per_block = POINTERS_PER_BLOCK as u64;
+
+    assert!(matches!(block_path(11), Some((BlockSlot::Direct(11), path)) if path.is_empty()));
+    assert!(matches!(block_path(12), Some((BlockSlot::SingleIndirect, path)) if path == [0]));
+    assert!(matches!(block_path(12 + per_block), Some((BlockSlot::DoubleIndirect, path)) if path == [0, 0]));
+    assert!(matches!(
+        block_path(12 + per_block + per_block * per_block + per_block + 1),
+        Some((BlockSlot::TripleIndirect, path)) if path == [0, 1, 1]
+    ));
+    assert!(block_path(12 + per_block + per_block.pow(2) + per_block.pow(3)).is_none());
+}
+
+/// test that creating, writing and reading a file maintain its timestamps, with relatime updates of the access time
+#[test]
+fn test_timestamps() {
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    static NOW: AtomicU64 = AtomicU64::new(1000);
+    fn clock() -> u64 {
+        NOW.load(Ordering::Relaxed)
+    }
+    let times = |phys_fs: &mut PhysFs<RamDisk>| {
+        let stat = phys_fs.stat("file").unwrap();
+        (stat.creation_time, stat.modification_time, stat.access_time)
+    };
+
+    let size = (1 + 128 + 1024 + 1024) * 512;
+    let mut phys_fs = PhysFs::new(RamDisk::new(size), size, clock);
+    phys_fs.create_file("file", [6, 4, 4], 0).unwrap();
+    assert_eq!(times(&mut phys_fs), (1000, 1000, 1000));
+
+    NOW.store(2000, Ordering::Relaxed);
+    phys_fs.write_file("file", b"hello", None, None).unwrap();
+    assert_eq!(times(&mut phys_fs), (1000, 2000, 1000));
+
+    // the first read since the write updates the access time, later ones do not
+    NOW.store(3000, Ordering::Relaxed);
+    phys_fs.read_file("file").unwrap();
+    assert_eq!(times(&mut phys_fs), (1000, 2000, 3000));
+    NOW.store(4000, Ordering::Relaxed);
+    let inode_index = phys_fs.find_inode_index("file").unwrap();
+    phys_fs.read_at(inode_index, 0, &mut [0; 5]).unwrap();
+    assert_eq!(times(&mut phys_fs), (1000, 2000, 3000));
+
+    // until a day has passed
+    NOW.store(3000 + RELATIME_INTERVAL, Ordering::Relaxed);
+    phys_fs.read_at(inode_index, 0, &mut [0; 5]).unwrap();
+    assert_eq!(times(&mut phys_fs), (1000, 2000, 3000 + RELATIME_INTERVAL));
+
+    // writing at an offset changes the modification time too
+    NOW.store(5000 + RELATIME_INTERVAL, Ordering::Relaxed);
+    phys_fs.write_at(inode_index, 5, b"!").unwrap();
+    assert_eq!(times(&mut phys_fs), (1000, 5000 + RELATIME_INTERVAL, 3000 + RELATIME_INTERVAL));
+}
+
+/// test that the bitmaps track allocations, including blocks that happen to be all zeroes
+#[test]
+fn test_block_bitmap() {
+    let size = (1 + 1024 + 1024) * 512;
+    let mut phys_fs = PhysFs::new(RamDisk::new(size), size, test_clock);
+    let initial = phys_fs.statfs();
+
+    phys_fs.create_file("zeroes", [6, 4, 4], 0).unwrap();
+    phys_fs
+        .write_file("zeroes", &[0; 3 * 512], None, None)
+        .unwrap();
+    phys_fs.create_file("other", [6, 4, 4], 0).unwrap();
+    phys_fs.write_file("other", b"hello", None, None).unwrap();
+
+    let stats = phys_fs.statfs();
+    assert_eq!(stats.free_inodes, initial.free_inodes - 2);
+    // metadata + 3 data blocks, metadata + 1 data block, and the root directory's entry table
+    assert_eq!(stats.free_blocks, initial.free_blocks - 7);
+    assert_eq!(&phys_fs.read_file("other").unwrap().0[..5], b"hello");
+
+    phys_fs.delete("zeroes").unwrap();
+    phys_fs.delete("other").unwrap();
+
+    // the root directory's entry table is empty again, so its block is given back too
+    let stats = phys_fs.statfs();
+    assert_eq!(stats.free_inodes, initial.free_inodes);
+    assert_eq!(stats.free_blocks, initial.free_blocks);
+}
+
+/// test that deleting a large file gives back its indirect blocks as well as its data blocks
+#[test]
+fn test_delete_frees_indirect_blocks() {
+    let size = (1 + 1024 + 8192) * 512;
+    let mut phys_fs = PhysFs::new(RamDisk::new(size), size, test_clock);
+    phys_fs.create_file("keep", [6, 4, 4], 0).unwrap();
+    let initial = phys_fs.statfs();
+
+    phys_fs.create_file("big", [6, 4, 4], 0).unwrap();
+    let inode_index = phys_fs.find_inode_index("big").unwrap();
+    // through the direct, single indirect and double indirect pointers
+    phys_fs
+        .write_file("big", &vec![1; (12 + 64 + 100) * BLOCK_SIZE], None, None)
+        .unwrap();
+    phys_fs.delete("big").unwrap();
+
+    assert_eq!(phys_fs.statfs(), initial);
+    // the slot is free in place, and the next file takes it without moving any other inode
+    let keep = phys_fs.find_inode_index("keep").unwrap();
+    phys_fs.create_file("next", [6, 4, 4], 0).unwrap();
+    assert_eq!(phys_fs.find_inode_index("next"), Ok(inode_index));
+    assert_eq!(phys_fs.find_inode_index("keep"), Ok(keep));
+}
+
+/// test that reads and writes at an offset only touch the blocks they cover
+#[test]
+fn test_offset_io() {
+    let size = (1 + 1024 + 1024) * 512;
+    let mut phys_fs = PhysFs::new(RamDisk::new(size), size, test_clock);
+    phys_fs.create_file("log", [6, 4, 4], 0).unwrap();
+    let inode_index = phys_fs.find_inode_index("log").unwrap();
+
+    assert_eq!(phys_fs.write_at(inode_index, 0, &[1; 1000]), Ok(1000));
+    let before = phys_fs.statfs();
+
+    // appending within the last block allocates nothing, crossing into the next allocates one block
+    assert_eq!(phys_fs.write_at(inode_index, 1000, b"ab"), Ok(2));
+    assert_eq!(phys_fs.statfs().free_blocks, before.free_blocks);
+    assert_eq!(phys_fs.write_at(inode_index, 1020, &[2; 8]), Ok(8));
+    assert_eq!(phys_fs.statfs().free_blocks, before.free_blocks - 1);
+
+    let mut buf = [0; 4];
+    assert_eq!(phys_fs.read_at(inode_index, 998, &mut buf), Ok(4));
+    assert_eq!(&buf, &[1, 1, b'a', b'b']);
+
+    // reads stop at the end of the file, and the gap that was never written reads as zeroes
+    let mut buf = [0; 16];
+    assert_eq!(phys_fs.read_at(inode_index, 1018, &mut buf), Ok(10));
+    assert_eq!(&buf[..10], &[0, 0, 2, 2, 2, 2, 2, 2, 2, 2]);
+    assert_eq!(phys_fs.read_at(inode_index, 1028, &mut buf), Ok(0));
+    assert_eq!(phys_fs.read_file("log").unwrap().0.len(), 1028);
+}
@@ -0,0 +1,263 @@
+// partition tables, which split one disk into several volumes
+// an MBR keeps four entries in the first block, one of which may be an extended partition holding a chain of logical ones.
+// a GPT is announced by an MBR with a single protective entry, and keeps its header in block 1 and its entries after it.
+// every partition found becomes its own block device, whose block 0 is the first block of the partition.
+
+use alloc::{collections::BTreeSet, vec::Vec};
+
+use crate::{BLOCK_SIZE, BlockDevice, FsError};
+
+/// where the four MBR entries start in block 0
+const MBR_ENTRIES: usize = 446;
+/// the size of an MBR entry
+const MBR_ENTRY_SIZE: usize = 16;
+/// the MBR type of a protective entry, which covers a disk holding a GPT
+const MBR_PROTECTIVE: u8 = 0xEE;
+/// the MBR types of extended partitions, which hold logical partitions instead of a volume
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+/// the signature at the end of an MBR, and of each extended boot record
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+/// the signature at the start of a GPT header
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// the most partitions read from one table, so a corrupt one cannot run on forever
+const MAX_PARTITIONS: usize = 128;
+
+/// what a partition is meant to hold, as its table records it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// the type byte of an MBR entry
+    Mbr(u8),
+    /// the type GUID of a GPT entry, as stored on disk
+    Gpt([u8; 16]),
+}
+
+/// an entry in a partition table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartitionEntry {
+    /// the first block of the partition
+    pub start: u64,
+    /// the number of blocks in the partition
+    pub count: u64,
+    /// what the partition holds
+    pub kind: PartitionKind,
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// read the four entries of an MBR or extended boot record as (type, start, count), or None if the block does not hold one
+///
+/// a volume starting at block 0, like a FAT boot sector, can carry the same signature, so the entries must also look sane
+fn mbr_entries(block: &[u8; BLOCK_SIZE]) -> Option<[(u8, u64, u64); 4]> {
+    if block[BLOCK_SIZE - 2..] != MBR_SIGNATURE {
+        return None;
+    }
+
+    let mut entries = [(0, 0, 0); 4];
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let raw = &block[MBR_ENTRIES + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        let (status, kind) = (raw[0], raw[4]);
+        let (start, count) = (u32_at(raw, 8) as u64, u32_at(raw, 12) as u64);
+
+        if status & 0x7F != 0 || (kind != 0 && (start == 0 || count == 0)) {
+            return None;
+        }
+        *entry = (kind, start, count);
+    }
+
+    Some(entries)
+}
+
+/// read the GPT that a protective MBR points to
+fn read_gpt<D: BlockDevice + ?Sized>(device: &mut D) -> Result<Vec<PartitionEntry>, FsError> {
+    let mut header = [0; BLOCK_SIZE];
+    device.read(1, &mut header)?;
+    if &header[..8] != GPT_SIGNATURE {
+        return Err(FsError::InvalidMetadata);
+    }
+
+    // the checksums of the header and entries are not checked
+    let entries_start = u64_at(&header, 72);
+    let entry_count = (u32_at(&header, 80) as usize).min(MAX_PARTITIONS);
+    let entry_size = u32_at(&header, 84) as usize;
+    if !(128..=BLOCK_SIZE).contains(&entry_size) || BLOCK_SIZE % entry_size != 0 {
+        return Err(FsError::InvalidMetadata);
+    }
+
+    let per_block = BLOCK_SIZE / entry_size;
+    let mut partitions = Vec::new();
+    let mut block = [0; BLOCK_SIZE];
+    for i in 0..entry_count {
+        if i % per_block == 0 {
+            device.read(entries_start + (i / per_block) as u64, &mut block)?;
+        }
+
+        let raw = &block[(i % per_block) * entry_size..][..entry_size];
+        let kind: [u8; 16] = raw[..16].try_into().unwrap();
+        // an all-zero type marks an unused entry
+        if kind == [0; 16] {
+            continue;
+        }
+
+        let (first, last) = (u64_at(raw, 32), u64_at(raw, 40));
+        if first == 0 || last < first {
+            return Err(FsError::InvalidMetadata);
+        }
+        partitions.push(PartitionEntry {
+            start: first,
+            count: last - first + 1,
+            kind: PartitionKind::Gpt(kind),
+        });
+    }
+
+    Ok(partitions)
+}
+
+/// follow the chain of extended boot records in an extended partition, returning the logical partitions in it
+fn read_logical<D: BlockDevice + ?Sized>(device: &mut D, extended: u64) -> Result<Vec<PartitionEntry>, FsError> {
+    let mut partitions = Vec::new();
+    let mut record = extended;
+    let mut block = [0; BLOCK_SIZE];
+    let mut seen = BTreeSet::new();
+
+    // records without a partition do not add to the list, so it is the records that are counted
+    for _ in 0..MAX_PARTITIONS {
+        // a chain that leads back to a record already read would go round forever
+        if !seen.insert(record) {
+            return Err(FsError::InvalidMetadata);
+        }
+
+        device.read(record, &mut block)?;
+        let [(kind, start, count), (next_kind, next, _), ..] = mbr_entries(&block).ok_or(FsError::InvalidMetadata)?;
+
+        // the logical partition is placed relative to its own record, and the next record relative to the extended partition
+        if kind != 0 {
+            partitions.push(PartitionEntry {
+                start: record + start,
+                count,
+                kind: PartitionKind::Mbr(kind),
+            });
+        }
+        if next_kind == 0 {
+            break;
+        }
+        record = extended + next;
+    }
+
+    Ok(partitions)
+}
+
+/// read the partition table of a device, returning no entries if it does not have one
+///
+/// MBR partitions are listed in the order of their entries, followed by any logical partitions. Empty and extended entries are left out
+pub fn read_table<D: BlockDevice + ?Sized>(device: &mut D) -> Result<Vec<PartitionEntry>, FsError> {
+    let mut block = [0; BLOCK_SIZE];
+    device.read(0, &mut block)?;
+    let Some(entries) = mbr_entries(&block) else {
+        return Ok(Vec::new());
+    };
+
+    if entries.iter().any(|&(kind, _, _)| kind == MBR_PROTECTIVE) {
+        return read_gpt(device);
+    }
+
+    let mut partitions = Vec::new();
+    let mut logical = Vec::new();
+    for (kind, start, count) in entries {
+        match kind {
+            0 => {}
+            kind if MBR_EXTENDED.contains(&kind) => logical.extend(read_logical(device, start)?),
+            kind => partitions.push(PartitionEntry {
+                start,
+                count,
+                kind: PartitionKind::Mbr(kind),
+            }),
+        }
+    }
+    partitions.extend(logical);
+
+    Ok(partitions)
+}
+
+/// a range of blocks on a device, used as a device of its own
+#[derive(Debug, Clone, Copy)]
+pub struct Partition<D: BlockDevice> {
+    device: D,
+    start: u64,
+    count: u64,
+}
+
+impl<D: BlockDevice> Partition<D> {
+    /// the blocks of a device described by a partition table entry
+    pub fn new(device: D, entry: PartitionEntry) -> Self {
+        Partition {
+            device,
+            start: entry.start,
+            count: entry.count,
+        }
+    }
+
+    /// the whole of a device, for one without a partition table
+    pub fn whole(device: D) -> Self {
+        Partition {
+            start: 0,
+            count: device.block_count(),
+            device,
+        }
+    }
+
+    /// the first block of the partition on the device
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// check that a run of blocks lies inside the partition
+    fn contains(&self, block: u64, len: usize) -> bool {
+        block
+            .checked_add(len.div_ceil(BLOCK_SIZE) as u64)
+            .is_some_and(|end| end <= self.count)
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for Partition<D> {
+    fn read(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        if !self.contains(block, BLOCK_SIZE) {
+            return Err(FsError::ReadError);
+        }
+        self.device.read(self.start + block, buf)
+    }
+
+    fn write(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        if !self.contains(block, BLOCK_SIZE) {
+            return Err(FsError::WriteError);
+        }
+        self.device.write(self.start + block, buf)
+    }
+
+    fn read_blocks(&mut self, block: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        if !self.contains(block, buf.len()) {
+            return Err(FsError::ReadError);
+        }
+        self.device.read_blocks(self.start + block, buf)
+    }
+
+    fn write_blocks(&mut self, block: u64, buf: &[u8]) -> Result<(), FsError> {
+        if !self.contains(block, buf.len()) {
+            return Err(FsError::WriteError);
+        }
+        self.device.write_blocks(self.start + block, buf)
+    }
+
+    fn block_count(&self) -> u64 {
+        self.count
+    }
+
+    fn flush(&mut self) -> Result<(), FsError> {
+        self.device.flush()
+    }
+}
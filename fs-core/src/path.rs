@@ -0,0 +1,127 @@
+// walking paths and renaming entries, the same way for every filesystem
+// each filesystem only says how to find a name in one of its directories, and these do the rest
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{FileKind, FsError, MAX_SYMLINKS};
+
+/// what a name in a directory turned out to be, as reported to resolve
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup<I> {
+    /// an inode to walk into, or to end at
+    Inode(I),
+    /// a symbolic link to follow, with its target
+    Symlink(String),
+}
+
+/// split a path into its components, the next one last
+fn components(path: &str) -> impl Iterator<Item = String> + '_ {
+    path.split('/').filter(|c| !c.is_empty()).rev().map(|c| c.to_string())
+}
+
+/// resolve a path to the chain of directories leading to it from the root, ending with the inode it names
+///
+/// lookup finds a name in a directory, failing with FileNotFound if it is not there. when its last argument is set, it reports a symbolic link as Lookup::Symlink so the link is followed.
+/// symbolic links are followed wherever they appear, except at the end of the path when follow_last is false
+pub fn resolve<I: Copy>(
+    root: I,
+    path: &str,
+    follow_last: bool,
+    mut lookup: impl FnMut(I, &str, bool) -> Result<Lookup<I>, FsError>,
+) -> Result<Vec<I>, FsError> {
+    let mut stack = Vec::from([root]);
+    let mut pending: Vec<String> = components(path).collect();
+    let mut followed = 0;
+
+    while let Some(component) = pending.pop() {
+        match component.as_str() {
+            "." => {}
+            ".." => {
+                if stack.len() > 1 {
+                    stack.pop();
+                }
+            }
+            name => {
+                let follow = follow_last || !pending.is_empty();
+                match lookup(*stack.last().unwrap(), name, follow)? {
+                    Lookup::Inode(child) => stack.push(child),
+                    Lookup::Symlink(target) => {
+                        followed += 1;
+                        if followed > MAX_SYMLINKS {
+                            return Err(FsError::TooManySymlinks);
+                        }
+
+                        // walk the target in place of the link, from the root if it is absolute
+                        if target.starts_with('/') {
+                            stack.truncate(1);
+                        }
+                        pending.extend(components(&target));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(stack)
+}
+
+/// check that an inode can be renamed over the one already at its destination, given the inode and kind of each
+///
+/// returns false if there is nothing to do, as renaming a file onto itself or onto another of its hard links does nothing.
+/// a directory can only replace an empty directory (is_empty is only asked then), and anything else only something that is not a directory
+pub fn check_replace<I: PartialEq>(
+    (inode, kind): (I, FileKind),
+    (existing, existing_kind): (I, FileKind),
+    is_empty: impl FnOnce() -> Result<bool, FsError>,
+) -> Result<bool, FsError> {
+    if inode == existing {
+        return Ok(false);
+    }
+
+    match (kind, existing_kind) {
+        (FileKind::Directory, FileKind::Directory) if !is_empty()? => Err(FsError::DirectoryNotEmpty),
+        (FileKind::Directory, FileKind::Directory) => Ok(true),
+        (FileKind::Directory, _) => Err(FsError::NotADirectory),
+        (_, FileKind::Directory) => Err(FsError::IsADirectory),
+        _ => Ok(true),
+    }
+}
+
+/// test resolving through . and .., and following relative, absolute and looping links
+#[test]
+fn test_resolve() {
+    // 0 is the root, holding the directory "a" (1), which holds "up" -> "..", "abs" -> "/a" and "loop" -> "loop"
+    let lookup = |dir: u32, name: &str, follow: bool| match (dir, name) {
+        (0, "a") => Ok(Lookup::Inode(1)),
+        (1, "up") if follow => Ok(Lookup::Symlink("..".to_string())),
+        (1, "abs") if follow => Ok(Lookup::Symlink("/a".to_string())),
+        (1, "loop") if follow => Ok(Lookup::Symlink("loop".to_string())),
+        (1, "up" | "abs" | "loop") => Ok(Lookup::Inode(2)),
+        _ => Err(FsError::FileNotFound),
+    };
+
+    assert_eq!(resolve(0, "/a/./../a", true, lookup), Ok(vec![0, 1]));
+    assert_eq!(resolve(0, "/..", true, lookup), Ok(vec![0]));
+    assert_eq!(resolve(0, "/a/up/a/abs", true, lookup), Ok(vec![0, 1]));
+    assert_eq!(resolve(0, "/a/abs", false, lookup), Ok(vec![0, 1, 2]));
+    assert_eq!(resolve(0, "/a/loop", true, lookup), Err(FsError::TooManySymlinks));
+    assert_eq!(resolve(0, "/b", true, lookup), Err(FsError::FileNotFound));
+}
+
+/// test which renames over an existing entry are allowed
+#[test]
+fn test_check_replace() {
+    let (file, dir) = (FileKind::File, FileKind::Directory);
+    let empty = || Ok(true);
+    let full = || Ok(false);
+
+    assert_eq!(check_replace((1, file), (1, file), empty), Ok(false));
+    assert_eq!(check_replace((1, file), (2, file), empty), Ok(true));
+    assert_eq!(check_replace((1, dir), (2, dir), empty), Ok(true));
+    assert_eq!(check_replace((1, dir), (2, dir), full), Err(FsError::DirectoryNotEmpty));
+    assert_eq!(check_replace((1, dir), (2, file), empty), Err(FsError::NotADirectory));
+    assert_eq!(check_replace((1, FileKind::Symlink), (2, dir), empty), Err(FsError::IsADirectory));
+}
@@ -0,0 +1,21 @@
+// helpers shared by the integration tests
+
+// each test binary uses only some of these
+#![allow(dead_code)]
+
+use rustnix_fs_core::{PhysFs, RamDisk, fsck};
+
+pub fn clock() -> u64 {
+    1_700_000_000
+}
+
+pub fn pattern(len: usize, seed: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * seed % 251) as u8).collect()
+}
+
+/// flush, then check that fsck finds nothing wrong
+pub fn assert_clean(mut phys_fs: PhysFs<RamDisk>) {
+    phys_fs.flush().unwrap();
+    let report = fsck::check(phys_fs.into_device(), clock, false).unwrap();
+    assert_eq!(report.messages, Vec::<String>::new());
+}
@@ -0,0 +1,223 @@
+// names live in directory entries: hard links, symbolic links, renames and long names
+
+mod common;
+
+use common::{assert_clean, clock};
+use rustnix_fs_core::{BLOCK_SIZE, BlockDevice, FileKind, FsError, MAX_NAME_LEN, MAX_SYMLINKS, PhysFs, RamDisk, fsck};
+
+const SIZE: u64 = (1 + 128 + 1024 + 2048) * 512;
+
+fn new_fs() -> PhysFs<RamDisk> {
+    let mut phys_fs = PhysFs::new(RamDisk::new(SIZE), SIZE, clock);
+    phys_fs.mkdir("/a", [7, 5, 5], 0).unwrap();
+    phys_fs.mkdir("/a/b", [7, 5, 5], 0).unwrap();
+    phys_fs.create_file("/a/b/file", [6, 4, 4], 0).unwrap();
+    phys_fs.write_file("/a/b/file", b"contents", None, None).unwrap();
+    phys_fs
+}
+
+fn names(phys_fs: &mut PhysFs<RamDisk>, path: &str) -> Vec<String> {
+    let dir_index = phys_fs.find_inode_index(path).unwrap();
+    let mut names: Vec<String> = phys_fs.read_dir(dir_index).unwrap().into_iter().map(|(_, name)| name).collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn hard_links_share_an_inode_until_the_last_is_removed() {
+    let mut phys_fs = new_fs();
+    let free = phys_fs.statfs();
+
+    phys_fs.link("/a/b/file", "/other").unwrap();
+    let stat = phys_fs.stat("/other").unwrap();
+    assert_eq!((stat.inode, stat.links), (phys_fs.stat("/a/b/file").unwrap().inode, 2));
+    assert_eq!(phys_fs.statfs().free_inodes, free.free_inodes);
+
+    // a write through one name is seen through the other
+    phys_fs.write_file("/other", b"changed", None, None).unwrap();
+    assert_eq!(phys_fs.read_file("/a/b/file").unwrap().0, b"changed");
+
+    assert_eq!(phys_fs.link("/a", "/a2"), Err(FsError::IsADirectory));
+    assert_eq!(phys_fs.link("/a/b/file", "/other"), Err(FsError::FileExists));
+
+    phys_fs.delete("/a/b/file").unwrap();
+    assert_eq!(phys_fs.stat("/other").unwrap().links, 1);
+    assert_eq!(phys_fs.read_file("/other").unwrap().0, b"changed");
+
+    phys_fs.delete("/other").unwrap();
+    assert_eq!(phys_fs.statfs().free_inodes, free.free_inodes + 1);
+    assert_clean(phys_fs);
+}
+
+#[test]
+fn an_open_inode_outlives_its_last_link() {
+    let mut phys_fs = new_fs();
+    let free = phys_fs.statfs();
+    let inode_index = phys_fs.find_inode_index("/a/b/file").unwrap();
+    phys_fs.open_inode(inode_index);
+
+    // the inode is held, not the name, so a rename does not lose it
+    phys_fs.rename("/a/b/file", "/moved").unwrap();
+    phys_fs.delete("/moved").unwrap();
+    assert_eq!(phys_fs.statfs().free_inodes, free.free_inodes);
+
+    let mut buf = [0; 16];
+    assert_eq!(phys_fs.read_at(inode_index, 0, &mut buf), Ok(8));
+    assert_eq!(&buf[..8], b"contents");
+    phys_fs.write_at(inode_index, 8, b" and more").unwrap();
+
+    // a new file cannot take the inode while it is open
+    phys_fs.create_file("/new", [6, 4, 4], 0).unwrap();
+    assert_ne!(phys_fs.find_inode_index("/new").unwrap(), inode_index);
+    phys_fs.delete("/new").unwrap();
+
+    phys_fs.close_inode(inode_index).unwrap();
+    assert_eq!(phys_fs.statfs().free_inodes, free.free_inodes + 1);
+    assert_clean(phys_fs);
+}
+
+#[test]
+fn symbolic_links_are_followed_when_resolving_paths() {
+    let mut phys_fs = new_fs();
+
+    phys_fs.symlink("/a/b/file", "/abs", 0).unwrap();
+    phys_fs.symlink("b", "/a/rel", 0).unwrap();
+    phys_fs.symlink("../rel/./file", "/a/b/up", 0).unwrap();
+    phys_fs.symlink("/nowhere", "/dangling", 0).unwrap();
+
+    assert_eq!(phys_fs.read_file("/abs").unwrap().0, b"contents");
+    assert_eq!(phys_fs.read_file("/a/rel/file").unwrap().0, b"contents");
+    assert_eq!(phys_fs.read_file("/a/b/up").unwrap().0, b"contents");
+    // .. after a link goes to the parent of where the link led, not where it was
+    assert_eq!(phys_fs.read_file("/a/rel/../b/file").unwrap().0, b"contents");
+    assert_eq!(names(&mut phys_fs, "/a/rel"), ["file", "up"]);
+
+    assert_eq!(phys_fs.readlink("/a/rel"), Ok("b".to_string()));
+    assert_eq!(phys_fs.readlink("/a/b/file"), Err(FsError::InvalidPath));
+    assert_eq!(phys_fs.stat("/abs").unwrap().kind, FileKind::File);
+    let link = phys_fs.lstat("/abs").unwrap();
+    assert_eq!((link.kind, link.size), (FileKind::Symlink, 9));
+
+    assert_eq!(phys_fs.stat("/dangling"), Err(FsError::FileNotFound));
+    assert_eq!(phys_fs.lstat("/dangling").unwrap().kind, FileKind::Symlink);
+    assert_eq!(phys_fs.symlink("/a", "/abs", 0), Err(FsError::FileExists));
+
+    // removing a link leaves what it points at alone
+    phys_fs.delete("/abs").unwrap();
+    phys_fs.delete("/dangling").unwrap();
+    assert_eq!(phys_fs.read_file("/a/b/file").unwrap().0, b"contents");
+    assert_eq!(phys_fs.rmdir("/a/rel"), Err(FsError::NotADirectory));
+    assert_clean(phys_fs);
+}
+
+#[test]
+fn symbolic_link_loops_are_detected() {
+    let mut phys_fs = new_fs();
+
+    phys_fs.symlink("/loop2", "/loop1", 0).unwrap();
+    phys_fs.symlink("loop1", "/loop2", 0).unwrap();
+    phys_fs.symlink(".", "/a/self", 0).unwrap();
+
+    assert_eq!(phys_fs.stat("/loop1"), Err(FsError::TooManySymlinks));
+    assert_eq!(phys_fs.create_file("/loop1/file", [6, 4, 4], 0), Err(FsError::TooManySymlinks));
+    assert_eq!(phys_fs.lstat("/loop1").unwrap().kind, FileKind::Symlink);
+
+    // a long chain that does not loop still resolves, up to the limit
+    let ok = "/a".to_string() + &"/self".repeat(MAX_SYMLINKS) + "/b/file";
+    assert_eq!(phys_fs.read_file(&ok).unwrap().0, b"contents");
+    let too_many = "/a".to_string() + &"/self".repeat(MAX_SYMLINKS + 1) + "/b/file";
+    assert_eq!(phys_fs.read_file(&too_many).err(), Some(FsError::TooManySymlinks));
+    assert_clean(phys_fs);
+}
+
+#[test]
+fn rename_moves_entries_without_touching_what_is_below_them() {
+    let mut phys_fs = new_fs();
+    let inode = phys_fs.stat("/a/b/file").unwrap().inode;
+
+    // renaming a directory only rewrites the entry in its parent
+    phys_fs.rename("/a", "/moved").unwrap();
+    assert_eq!(phys_fs.stat("/moved/b/file").unwrap().inode, inode);
+    assert_eq!(phys_fs.stat("/a"), Err(FsError::FileNotFound));
+
+    phys_fs.rename("/moved/b/file", "/file").unwrap();
+    assert_eq!(phys_fs.read_file("/file").unwrap().0, b"contents");
+    assert!(names(&mut phys_fs, "/moved/b").is_empty());
+
+    // an existing file is replaced, and freed if that was its last name
+    phys_fs.create_file("/victim", [6, 4, 4], 0).unwrap();
+    let free_inodes = phys_fs.statfs().free_inodes;
+    phys_fs.rename("/file", "/victim").unwrap();
+    assert_eq!(phys_fs.stat("/victim").unwrap().inode, inode);
+    assert_eq!(phys_fs.statfs().free_inodes, free_inodes + 1);
+
+    // onto itself, or another of its hard links, nothing happens
+    phys_fs.link("/victim", "/moved/hard").unwrap();
+    phys_fs.rename("/victim", "/moved/hard").unwrap();
+    assert_eq!(phys_fs.stat("/victim").unwrap().links, 2);
+
+    assert_eq!(phys_fs.rename("/moved", "/moved/b/inside"), Err(FsError::InvalidPath));
+    assert_eq!(phys_fs.rename("/moved", "/victim"), Err(FsError::NotADirectory));
+    assert_eq!(phys_fs.rename("/victim", "/moved"), Err(FsError::IsADirectory));
+    phys_fs.mkdir("/empty", [7, 5, 5], 0).unwrap();
+    assert_eq!(phys_fs.rename("/empty", "/moved"), Err(FsError::DirectoryNotEmpty));
+    phys_fs.rename("/moved/b", "/empty").unwrap();
+    assert_eq!(names(&mut phys_fs, "/"), ["empty", "moved", "victim"]);
+    assert_eq!(phys_fs.rename("/missing", "/x"), Err(FsError::FileNotFound));
+    assert_eq!(phys_fs.rename("/", "/x"), Err(FsError::InvalidPath));
+    assert_clean(phys_fs);
+}
+
+#[test]
+fn long_names_are_stored_in_full() {
+    let mut phys_fs = new_fs();
+
+    let long = "x".repeat(MAX_NAME_LEN);
+    let mut names_in_dir = Vec::new();
+    // enough entries to spill the directory table over several blocks
+    for i in 0..8 {
+        let name = format!("{}{}", i, &long[1..]);
+        phys_fs.create_file(&format!("/a/{}", name), [6, 4, 4], 0).unwrap();
+        names_in_dir.push(name);
+    }
+    assert!(phys_fs.stat("/a").unwrap().size > 4 * BLOCK_SIZE as u64);
+
+    names_in_dir.push("b".to_string());
+    names_in_dir.sort();
+    assert_eq!(names(&mut phys_fs, "/a"), names_in_dir);
+
+    let too_long = format!("/a/{}", "x".repeat(MAX_NAME_LEN + 1));
+    assert_eq!(phys_fs.create_file(&too_long, [6, 4, 4], 0), Err(FsError::NameTooLong));
+    assert_eq!(phys_fs.rename("/a/b/file", &too_long), Err(FsError::NameTooLong));
+
+    phys_fs.delete(&format!("/a/{}", names_in_dir[3])).unwrap();
+    assert_eq!(names(&mut phys_fs, "/a").len(), 8);
+    assert_clean(phys_fs);
+}
+
+#[test]
+fn fsck_repairs_a_wrong_link_count() {
+    let mut phys_fs = new_fs();
+    phys_fs.link("/a/b/file", "/a/hard").unwrap();
+    phys_fs.flush().unwrap();
+    let inode = phys_fs.stat("/a/hard").unwrap().inode;
+    let mut disk = phys_fs.into_device();
+
+    // the inode table starts at the sector named by the ninth field of the superblock, and the link count is at byte 128 of each inode
+    let mut sector = [0; BLOCK_SIZE];
+    disk.read(0, &mut sector).unwrap();
+    let inode_sector = u64::from_le_bytes(sector[64..72].try_into().unwrap()) + inode;
+    disk.read(inode_sector, &mut sector).unwrap();
+    sector[128..136].copy_from_slice(&5u64.to_le_bytes());
+    disk.write(inode_sector, &sector).unwrap();
+
+    let report = fsck::check(&mut disk, clock, true).unwrap();
+    assert_eq!(report.messages, [format!("inode {}: link count is 5, but 2 entries point at it (fixed)", inode)]);
+
+    let mut phys_fs = PhysFs::read_from_disk(&mut disk, clock).unwrap();
+    assert_eq!(phys_fs.stat("/a/hard").unwrap().links, 2);
+    phys_fs.delete("/a/hard").unwrap();
+    phys_fs.delete("/a/b/file").unwrap();
+    assert_eq!(phys_fs.stat("/a/b/file"), Err(FsError::FileNotFound));
+    assert_eq!(fsck::check(&mut disk, clock, false).unwrap().problems, 0);
+}
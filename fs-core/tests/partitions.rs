@@ -0,0 +1,165 @@
+// partition tables: MBR with logical partitions, GPT, and volumes that live inside a partition
+
+mod common;
+
+use common::clock;
+use rustnix_fs_core::{
+    BLOCK_SIZE, BlockDevice, FsError, Partition, PhysFs, RamDisk,
+    partition::{PartitionEntry, PartitionKind, read_table},
+};
+
+const DISK_BLOCKS: u64 = 8192;
+const FS_SIZE: u64 = (1 + 128 + 1024 + 2048) * 512;
+
+/// the type GUID of a Linux filesystem partition, as stored on disk
+const LINUX_GUID: [u8; 16] = [
+    0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4,
+];
+
+/// write the four entries and signature of an MBR or extended boot record into a block
+fn write_mbr(disk: &mut RamDisk, block: u64, entries: &[(u8, u32, u32)]) {
+    let mut buf = [0; BLOCK_SIZE];
+    for (i, &(kind, start, count)) in entries.iter().enumerate() {
+        let entry = &mut buf[446 + i * 16..][..16];
+        entry[4] = kind;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&count.to_le_bytes());
+    }
+    buf[510..].copy_from_slice(&[0x55, 0xAA]);
+    disk.write(block, &buf).unwrap();
+}
+
+/// write a GPT with the given partitions as (first, last) blocks, behind a protective MBR
+fn write_gpt(disk: &mut RamDisk, partitions: &[(u64, u64)]) {
+    write_mbr(disk, 0, &[(0xEE, 1, DISK_BLOCKS as u32 - 1)]);
+
+    let mut header = [0; BLOCK_SIZE];
+    header[..8].copy_from_slice(b"EFI PART");
+    header[72..80].copy_from_slice(&2u64.to_le_bytes());
+    header[80..84].copy_from_slice(&128u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+    disk.write(1, &header).unwrap();
+
+    // four entries to a block, leaving the unused ones zeroed
+    for block in 0..32 {
+        let mut buf = [0; BLOCK_SIZE];
+        for slot in 0..4 {
+            if let Some(&(first, last)) = partitions.get(block * 4 + slot) {
+                let entry = &mut buf[slot * 128..][..128];
+                entry[..16].copy_from_slice(&LINUX_GUID);
+                entry[32..40].copy_from_slice(&first.to_le_bytes());
+                entry[40..48].copy_from_slice(&last.to_le_bytes());
+            }
+        }
+        disk.write(2 + block as u64, &buf).unwrap();
+    }
+}
+
+#[test]
+fn no_table() {
+    let mut disk = RamDisk::new(DISK_BLOCKS * 512);
+    assert_eq!(read_table(&mut disk), Ok(Vec::new()));
+
+    // a volume at block 0 carries the signature too, but its boot code does not read as entries
+    let mut boot_sector = [0xEB; BLOCK_SIZE];
+    boot_sector[510..].copy_from_slice(&[0x55, 0xAA]);
+    disk.write(0, &boot_sector).unwrap();
+    assert_eq!(read_table(&mut disk), Ok(Vec::new()));
+}
+
+#[test]
+fn mbr_with_logical_partitions() {
+    let mut disk = RamDisk::new(DISK_BLOCKS * 512);
+    write_mbr(&mut disk, 0, &[(0x83, 2048, 1024), (0, 0, 0), (0x05, 4096, 4096), (0x0C, 3072, 1024)]);
+    // each record places its partition relative to itself, and the next record relative to the extended partition
+    write_mbr(&mut disk, 4096, &[(0x83, 64, 1000), (0x05, 2048, 2048)]);
+    write_mbr(&mut disk, 6144, &[(0x07, 64, 2000)]);
+
+    assert_eq!(
+        read_table(&mut disk),
+        Ok(vec![
+            PartitionEntry { start: 2048, count: 1024, kind: PartitionKind::Mbr(0x83) },
+            PartitionEntry { start: 3072, count: 1024, kind: PartitionKind::Mbr(0x0C) },
+            PartitionEntry { start: 4160, count: 1000, kind: PartitionKind::Mbr(0x83) },
+            PartitionEntry { start: 6208, count: 2000, kind: PartitionKind::Mbr(0x07) },
+        ])
+    );
+
+    // a broken chain is reported rather than cut short
+    disk.write(6144, &[0; BLOCK_SIZE]).unwrap();
+    assert_eq!(read_table(&mut disk), Err(FsError::InvalidMetadata));
+
+    // as is one that loops back through a record with no partition in it
+    write_mbr(&mut disk, 6144, &[(0, 0, 0), (0x05, 2048, 2048)]);
+    assert_eq!(read_table(&mut disk), Err(FsError::InvalidMetadata));
+}
+
+#[test]
+fn gpt() {
+    let mut disk = RamDisk::new(DISK_BLOCKS * 512);
+    write_gpt(&mut disk, &[(34, 2047), (2048, 8191)]);
+
+    assert_eq!(
+        read_table(&mut disk),
+        Ok(vec![
+            PartitionEntry { start: 34, count: 2014, kind: PartitionKind::Gpt(LINUX_GUID) },
+            PartitionEntry { start: 2048, count: 6144, kind: PartitionKind::Gpt(LINUX_GUID) },
+        ])
+    );
+
+    // a protective MBR without a GPT behind it
+    disk.write(1, &[0; BLOCK_SIZE]).unwrap();
+    assert_eq!(read_table(&mut disk), Err(FsError::InvalidMetadata));
+}
+
+#[test]
+fn volumes_in_partitions() {
+    let mut disk = RamDisk::new(DISK_BLOCKS * 512);
+    let half = FS_SIZE / 512;
+    write_gpt(&mut disk, &[(34, 33 + half), (34 + half, 33 + 2 * half)]);
+    let table = read_table(&mut disk).unwrap();
+
+    // two volumes side by side, each reading and writing only its own blocks
+    for (i, &entry) in table.iter().enumerate() {
+        let mut phys_fs = PhysFs::new(Partition::new(&mut disk, entry), FS_SIZE, clock);
+        phys_fs.create_file("/file", [6, 4, 4], 0).unwrap();
+        phys_fs.write_file("/file", format!("volume {}", i).as_bytes(), None, None).unwrap();
+        phys_fs.flush().unwrap();
+    }
+
+    for (i, &entry) in table.iter().enumerate() {
+        let partition = Partition::new(&mut disk, entry);
+        assert_eq!(partition.start(), entry.start);
+        let mut phys_fs = PhysFs::read_from_disk(partition, clock).unwrap();
+        assert_eq!(phys_fs.read_file("/file").unwrap().0, format!("volume {}", i).as_bytes());
+    }
+
+    // the table itself was left alone, and nothing past the end of a partition can be reached
+    assert_eq!(read_table(&mut disk).unwrap(), table);
+    let mut partition = Partition::new(&mut disk, table[0]);
+    let mut buf = [0; BLOCK_SIZE];
+    assert!(partition.read(half - 1, &mut buf).is_ok());
+    assert_eq!(partition.read(half, &mut buf), Err(FsError::ReadError));
+    assert_eq!(partition.write(half, &buf), Err(FsError::WriteError));
+}
+
+#[test]
+fn runs_of_blocks() {
+    let mut disk = RamDisk::new(DISK_BLOCKS * 512);
+    write_mbr(&mut disk, 0, &[(0x83, 2048, 16)]);
+    let entry = read_table(&mut disk).unwrap()[0];
+
+    let data: Vec<u8> = (0..16 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+    let mut partition = Partition::new(&mut disk, entry);
+    assert_eq!(partition.block_count(), 16);
+    partition.write_blocks(0, &data).unwrap();
+
+    // a run that would cross the end of the partition is refused whole, as is one that is not a whole number of blocks
+    assert_eq!(partition.write_blocks(8, &data[..9 * BLOCK_SIZE]), Err(FsError::WriteError));
+    assert_eq!(partition.write_blocks(0, &data[..100]), Err(FsError::WriteError));
+
+    let mut buf = vec![0; 4 * BLOCK_SIZE];
+    disk.read_blocks(2050, &mut buf).unwrap();
+    assert_eq!(buf, data[2 * BLOCK_SIZE..6 * BLOCK_SIZE]);
+    assert_eq!(Partition::whole(&mut disk).block_count(), DISK_BLOCKS);
+}
@@ -0,0 +1,309 @@
+// round trips through a device: everything written and flushed must read back the same once the filesystem is loaded again
+
+use std::{cell::Cell, rc::Rc};
+
+mod common;
+
+use common::{clock, pattern};
+use rustnix_fs_core::{
+    BLOCK_SIZE, BlockDevice, FileKind, FsError, PhysFs, RamDisk, fsck, journal::JOURNAL_SIZE,
+};
+
+const SIZE: u64 = (1 + 128 + 1024 + 2048) * 512;
+
+/// a disk that loses power once its write budget runs out, silently dropping every write after that
+struct CrashDisk {
+    disk: RamDisk,
+    // shared with the test, which sets it just before the flush it wants to interrupt
+    writes_left: Rc<Cell<u64>>,
+}
+
+impl BlockDevice for CrashDisk {
+    fn read(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        self.disk.read(block, buf)
+    }
+
+    fn write(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        match self.writes_left.get() {
+            0 => Ok(()),
+            left => {
+                self.writes_left.set(left - 1);
+                self.disk.write(block, buf)
+            }
+        }
+    }
+
+    fn block_count(&self) -> u64 {
+        self.disk.block_count()
+    }
+}
+
+/// flush a filesystem and load it again from the same disk
+fn reload(mut phys_fs: PhysFs<RamDisk>) -> PhysFs<RamDisk> {
+    phys_fs.flush().unwrap();
+    PhysFs::read_from_disk(phys_fs.into_device(), clock).unwrap()
+}
+
+#[test]
+fn files_and_directories_survive_a_reload() {
+    let mut phys_fs = PhysFs::new(RamDisk::new(SIZE), SIZE, clock);
+    phys_fs.mkdir("/bin", [7, 5, 5], 0).unwrap();
+    phys_fs.mkdir("/home", [7, 5, 5], 0).unwrap();
+    phys_fs.mkdir("/home/user", [7, 0, 0], 1000).unwrap();
+    phys_fs.create_file("/bin/hello", [7, 5, 5], 0).unwrap();
+    phys_fs.write_file("/bin/hello", &pattern(1584, 7), None, None).unwrap();
+    phys_fs.create_file("/home/user/notes", [6, 0, 0], 1000).unwrap();
+    phys_fs.write_file("/home/user/notes", b"hello", None, None).unwrap();
+    phys_fs.create_file("/empty", [6, 4, 4], 0).unwrap();
+    phys_fs.chmod("/bin/hello", [5, 5, 5]).unwrap();
+    phys_fs.chown("/empty", 42).unwrap();
+
+    let stats = phys_fs.statfs();
+    let hello = phys_fs.stat("/bin/hello").unwrap();
+    let notes = phys_fs.stat("/home/user/notes").unwrap();
+
+    let mut phys_fs = reload(phys_fs);
+    assert_eq!(phys_fs.statfs(), stats);
+    assert_eq!(phys_fs.stat("/bin/hello").unwrap(), hello);
+    assert_eq!(phys_fs.stat("/home/user/notes").unwrap(), notes);
+
+    assert_eq!(phys_fs.read_file("/bin/hello").unwrap().0, pattern(1584, 7));
+    assert_eq!(phys_fs.read_file("/home/user/notes").unwrap().0, b"hello");
+    assert_eq!(phys_fs.read_file("/empty").unwrap().0, b"");
+
+    let empty = phys_fs.stat("/empty").unwrap();
+    assert_eq!((empty.owner, empty.permissions, empty.size), (42, [6, 4, 4], 0));
+    assert_eq!(hello.permissions, [5, 5, 5]);
+    assert_eq!(hello.kind, FileKind::File);
+    assert_eq!(hello.modification_time, clock());
+
+    let user = phys_fs.stat("/home/user").unwrap();
+    assert_eq!((user.kind, user.owner, user.permissions), (FileKind::Directory, 1000, [7, 0, 0]));
+
+    let mut root: Vec<String> = phys_fs.read_dir(0).unwrap().into_iter().map(|(_, name)| name).collect();
+    root.sort();
+    assert_eq!(root, ["bin", "empty", "home"]);
+
+    // changes after a reload are kept by the next one
+    phys_fs.delete("/home/user/notes").unwrap();
+    phys_fs.rmdir("/home/user").unwrap();
+    let mut phys_fs = reload(phys_fs);
+    assert_eq!(phys_fs.find_inode_index("/home/user"), Err(FsError::FileNotFound));
+    let home = phys_fs.find_inode_index("/home").unwrap();
+    assert!(phys_fs.read_dir(home).unwrap().is_empty());
+}
+
+#[test]
+fn indirect_blocks_survive_a_reload() {
+    let size = (1 + 128 + 1024 + 8192) * 512;
+    let mut phys_fs = PhysFs::new(RamDisk::new(size), size, clock);
+
+    // through the direct, single indirect and double indirect pointers
+    let data = pattern((12 + 64 + 200) * BLOCK_SIZE + 37, 13);
+    phys_fs.create_file("/big", [6, 4, 4], 0).unwrap();
+    phys_fs.write_file("/big", &data, None, None).unwrap();
+
+    let mut phys_fs = reload(phys_fs);
+    assert_eq!(phys_fs.read_file("/big").unwrap().0, data);
+
+    // rewriting the same contents reuses every block
+    let stats = phys_fs.statfs();
+    phys_fs.write_file("/big", &data, None, None).unwrap();
+    assert_eq!(phys_fs.statfs(), stats);
+
+    // an unaligned read across an indirect boundary
+    let inode_index = phys_fs.find_inode_index("/big").unwrap();
+    let mut buf = vec![0; 1000];
+    let offset = (12 + 64) * BLOCK_SIZE - 500;
+    assert_eq!(phys_fs.read_at(inode_index, offset, &mut buf), Ok(1000));
+    assert_eq!(buf, data[offset..offset + 1000]);
+}
+
+#[test]
+fn writes_too_big_for_one_commit_are_committed_in_pieces() {
+    let size = (1 + 128 + 1024 + 16384) * 512;
+    let mut phys_fs = reload(PhysFs::new(RamDisk::new(size), size, clock));
+    let free = phys_fs.statfs();
+    phys_fs.create_file("/big", [6, 4, 4], 0).unwrap();
+
+    // 142 indirect blocks, more than a journal commit can hold
+    let data = pattern((12 + 64 + 140 * 64) * BLOCK_SIZE, 11);
+    phys_fs.write_file("/big", &data, None, None).unwrap();
+    let mut phys_fs = reload(phys_fs);
+    assert_eq!(phys_fs.read_file("/big").unwrap().0, data);
+
+    phys_fs.truncate("/big", 100).unwrap();
+    let mut phys_fs = reload(phys_fs);
+    assert_eq!(phys_fs.read_file("/big").unwrap().0, data[..100]);
+
+    // deleting it frees every block again, indirect blocks included
+    phys_fs.write_file("/big", &data, None, None).unwrap();
+    phys_fs.delete("/big").unwrap();
+    phys_fs.flush().unwrap();
+    assert_eq!(phys_fs.statfs(), free);
+    assert_eq!(fsck::check(phys_fs.into_device(), clock, false).unwrap().problems, 0);
+}
+
+#[test]
+fn sparse_writes_survive_a_reload() {
+    let mut phys_fs = PhysFs::new(RamDisk::new(SIZE), SIZE, clock);
+    phys_fs.create_file("/sparse", [6, 4, 4], 0).unwrap();
+    let inode_index = phys_fs.find_inode_index("/sparse").unwrap();
+
+    let free = phys_fs.statfs().free_blocks;
+    phys_fs.write_at(inode_index, 100 * BLOCK_SIZE, b"end").unwrap();
+    // the data block and the two levels of indirect block above it, nothing for the hole
+    assert_eq!(free - phys_fs.statfs().free_blocks, 3);
+
+    let mut phys_fs = reload(phys_fs);
+    let data = phys_fs.read_file("/sparse").unwrap().0;
+    assert_eq!(data.len(), 100 * BLOCK_SIZE + 3);
+    assert!(data[..100 * BLOCK_SIZE].iter().all(|&b| b == 0));
+    assert_eq!(&data[100 * BLOCK_SIZE..], b"end");
+}
+
+#[test]
+fn an_interrupted_flush_is_all_or_nothing() {
+    let mut phys_fs = PhysFs::new(RamDisk::new(SIZE), SIZE, clock);
+    phys_fs.mkdir("/d", [7, 5, 5], 0).unwrap();
+    phys_fs.create_file("/d/old", [6, 4, 4], 0).unwrap();
+    phys_fs.write_file("/d/old", &[3; 3000], None, None).unwrap();
+    let base = reload(phys_fs);
+    let before = base.statfs();
+    let base = base.into_device();
+
+    // make the change on a copy of the base image, then flush it with the given write budget
+    let crash_after = |writes_left: u64| {
+        let budget = Rc::new(Cell::new(u64::MAX));
+        let device = CrashDisk {
+            disk: base.clone(),
+            writes_left: budget.clone(),
+        };
+
+        let mut phys_fs = PhysFs::read_from_disk(device, clock).unwrap();
+        phys_fs.create_file("/d/new", [6, 4, 4], 0).unwrap();
+        phys_fs.write_file("/d/new", &[7; 2000], None, None).unwrap();
+        phys_fs.chmod("/d/old", [4, 4, 4]).unwrap();
+        phys_fs.delete("/d/old").unwrap();
+
+        budget.set(writes_left);
+        phys_fs.flush().unwrap();
+        (phys_fs.into_device().disk, u64::MAX - budget.get())
+    };
+
+    let (disk, total) = crash_after(u64::MAX);
+    let after = PhysFs::read_from_disk(disk, clock).unwrap().statfs();
+    assert_ne!(before, after);
+
+    let (mut old, mut new) = (0, 0);
+    for writes_left in 0..total {
+        let (disk, _) = crash_after(writes_left);
+
+        // mounting replays a complete commit, and ignores a torn one
+        let mut phys_fs = PhysFs::read_from_disk(disk, clock).unwrap();
+        let stats = phys_fs.statfs();
+        if stats == before {
+            old += 1;
+            assert_eq!(phys_fs.read_file("/d/old").unwrap().0, [3; 3000]);
+            assert_eq!(phys_fs.stat("/d/old").unwrap().permissions, [6, 4, 4]);
+            assert_eq!(phys_fs.find_inode_index("/d/new"), Err(FsError::FileNotFound));
+        } else {
+            new += 1;
+            assert_eq!(stats, after, "crashed after {} writes", writes_left);
+            assert_eq!(phys_fs.read_file("/d/new").unwrap().0, [7; 2000]);
+            assert_eq!(phys_fs.find_inode_index("/d/old"), Err(FsError::FileNotFound));
+        }
+
+        // the replay leaves nothing behind for fsck to find
+        let report = fsck::check(phys_fs.into_device(), clock, false).unwrap();
+        assert_eq!(report.messages, Vec::<String>::new(), "crashed after {} writes", writes_left);
+    }
+
+    assert!(old > 0 && new > 0);
+}
+
+#[test]
+fn blocks_freed_before_an_interrupted_flush_are_not_reused() {
+    let mut phys_fs = PhysFs::new(RamDisk::new(SIZE), SIZE, clock);
+    phys_fs.create_file("/a", [6, 4, 4], 0).unwrap();
+    phys_fs.write_file("/a", &pattern(10 * BLOCK_SIZE, 3), None, None).unwrap();
+    let base = reload(phys_fs).into_device();
+
+    // free the blocks of /a and write /b in the same commit, which must not land /b in blocks /a still owns on the disk
+    let crash_after = |writes_left: u64| {
+        let budget = Rc::new(Cell::new(u64::MAX));
+        let device = CrashDisk {
+            disk: base.clone(),
+            writes_left: budget.clone(),
+        };
+
+        let mut phys_fs = PhysFs::read_from_disk(device, clock).unwrap();
+        phys_fs.delete("/a").unwrap();
+        phys_fs.create_file("/b", [6, 4, 4], 0).unwrap();
+        phys_fs.write_file("/b", &pattern(10 * BLOCK_SIZE, 7), None, None).unwrap();
+
+        budget.set(writes_left);
+        phys_fs.flush().unwrap();
+        (phys_fs.into_device().disk, u64::MAX - budget.get())
+    };
+
+    let (_, total) = crash_after(u64::MAX);
+    let (mut old, mut new) = (0, 0);
+    for writes_left in 0..total {
+        let (disk, _) = crash_after(writes_left);
+
+        let mut phys_fs = PhysFs::read_from_disk(disk, clock).unwrap();
+        match phys_fs.read_file("/a") {
+            Ok((data, _)) => {
+                old += 1;
+                assert_eq!(data, pattern(10 * BLOCK_SIZE, 3), "crashed after {} writes", writes_left);
+                assert_eq!(phys_fs.find_inode_index("/b"), Err(FsError::FileNotFound));
+            }
+            Err(_) => {
+                new += 1;
+                assert_eq!(phys_fs.read_file("/b").unwrap().0, pattern(10 * BLOCK_SIZE, 7));
+            }
+        }
+
+        let report = fsck::check(phys_fs.into_device(), clock, false).unwrap();
+        assert_eq!(report.messages, Vec::<String>::new(), "crashed after {} writes", writes_left);
+    }
+
+    assert!(old > 0 && new > 0);
+}
+
+#[test]
+fn fsck_repairs_a_corrupted_bitmap() {
+    let mut phys_fs = PhysFs::new(RamDisk::new(SIZE), SIZE, clock);
+    phys_fs.create_file("/file", [6, 4, 4], 0).unwrap();
+    phys_fs.write_file("/file", &pattern(5000, 3), None, None).unwrap();
+    phys_fs.flush().unwrap();
+    let free_blocks = phys_fs.statfs().free_blocks;
+    let mut disk = phys_fs.into_device();
+
+    let report = fsck::check(&mut disk, clock, false).unwrap();
+    assert_eq!((report.problems, report.exit_code()), (0, 0));
+
+    // mark blocks 800-807 as in use, though no inode uses them
+    let data_bitmap = 1 + JOURNAL_SIZE + 1; // superblock, journal, inode bitmap
+    let mut sector = [0; BLOCK_SIZE];
+    disk.read(data_bitmap, &mut sector).unwrap();
+    sector[100] = 0xff;
+    disk.write(data_bitmap, &sector).unwrap();
+
+    let report = fsck::check(&mut disk, clock, false).unwrap();
+    assert_eq!(report.messages, [
+        format!("superblock: free block count is {}, the bitmap has {} (fixable)", free_blocks, free_blocks - 8),
+        "blocks 800-807 marked in use but not used by any inode (fixable)".to_string(),
+    ]);
+    assert_eq!(report.exit_code(), 4);
+
+    let report = fsck::check(&mut disk, clock, true).unwrap();
+    assert_eq!((report.problems, report.fixed, report.exit_code()), (2, 2, 1));
+
+    let report = fsck::check(&mut disk, clock, false).unwrap();
+    assert_eq!(report.problems, 0);
+    let mut phys_fs = PhysFs::read_from_disk(&mut disk, clock).unwrap();
+    assert_eq!(phys_fs.statfs().free_blocks, free_blocks);
+    assert_eq!(phys_fs.read_file("/file").unwrap().0, pattern(5000, 3));
+}
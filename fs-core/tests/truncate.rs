@@ -0,0 +1,94 @@
+// truncating and shrinking files: every block past the new end goes back to the bitmap, indirect blocks included
+
+mod common;
+
+use common::{assert_clean, clock, pattern};
+use rustnix_fs_core::{BLOCK_SIZE, FsError, PhysFs, RamDisk};
+
+const SIZE: u64 = (1 + 128 + 1024 + 8192) * 512;
+
+#[test]
+fn truncating_frees_blocks_through_every_level() {
+    let mut phys_fs = PhysFs::new(RamDisk::new(SIZE), SIZE, clock);
+    phys_fs.create_file("/big", [6, 4, 4], 0).unwrap();
+    let empty = phys_fs.statfs().free_blocks;
+
+    // through the direct, single indirect and double indirect pointers
+    let data = pattern((12 + 64 + 200) * BLOCK_SIZE + 37, 13);
+    phys_fs.write_file("/big", &data, None, None).unwrap();
+
+    // back into the single indirect range: the double indirect tree goes entirely
+    let len = (12 + 10) * BLOCK_SIZE - 100;
+    phys_fs.truncate("/big", len as u64).unwrap();
+    // the metadata block is not counted, the data blocks and the single indirect block are
+    let kept = empty - ((len.div_ceil(BLOCK_SIZE) + 1) as u64);
+    assert_eq!(phys_fs.statfs().free_blocks, kept);
+    assert_eq!(phys_fs.read_file("/big").unwrap().0, data[..len]);
+    assert_eq!(phys_fs.stat("/big").unwrap().size, len as u64);
+
+    // growing again leaves a hole of zeroes, including the rest of the block that was cut
+    phys_fs.truncate("/big", (len + 1000) as u64).unwrap();
+    let grown = phys_fs.read_file("/big").unwrap().0;
+    assert_eq!(grown[..len], data[..len]);
+    assert!(grown[len..].iter().all(|&b| b == 0));
+    assert_eq!(phys_fs.statfs().free_blocks, kept);
+
+    phys_fs.truncate("/big", 0).unwrap();
+    assert_eq!(phys_fs.statfs().free_blocks, empty);
+    assert_eq!(phys_fs.read_file("/big").unwrap().0, b"");
+    assert_clean(phys_fs);
+}
+
+#[test]
+fn truncating_keeps_indirect_blocks_still_in_use() {
+    let mut phys_fs = PhysFs::new(RamDisk::new(SIZE), SIZE, clock);
+    phys_fs.create_file("/sparse", [6, 4, 4], 0).unwrap();
+    let inode_index = phys_fs.find_inode_index("/sparse").unwrap();
+    let empty = phys_fs.statfs().free_blocks;
+
+    // two blocks under the same double indirect pointer, with a hole between them
+    phys_fs.write_at(inode_index, 80 * BLOCK_SIZE, b"first").unwrap();
+    phys_fs.write_at(inode_index, 200 * BLOCK_SIZE, b"second").unwrap();
+    // two data blocks, the double indirect block and one indirect block under it for each
+    assert_eq!(empty - phys_fs.statfs().free_blocks, 5);
+
+    phys_fs.truncate("/sparse", (100 * BLOCK_SIZE) as u64).unwrap();
+    assert_eq!(empty - phys_fs.statfs().free_blocks, 3);
+    let data = phys_fs.read_file("/sparse").unwrap().0;
+    assert_eq!(&data[80 * BLOCK_SIZE..80 * BLOCK_SIZE + 5], b"first");
+
+    assert_eq!(phys_fs.truncate("/", 0), Err(FsError::IsADirectory));
+    assert_eq!(phys_fs.truncate("/missing", 0), Err(FsError::FileNotFound));
+    assert_clean(phys_fs);
+}
+
+#[test]
+fn rewriting_a_file_shorter_frees_its_tail() {
+    let mut phys_fs = PhysFs::new(RamDisk::new(SIZE), SIZE, clock);
+    phys_fs.create_file("/file", [6, 4, 4], 0).unwrap();
+    let empty = phys_fs.statfs().free_blocks;
+
+    phys_fs.write_file("/file", &pattern(100 * BLOCK_SIZE, 7), None, None).unwrap();
+    phys_fs.write_file("/file", b"short", None, None).unwrap();
+    assert_eq!(empty - phys_fs.statfs().free_blocks, 1);
+    assert_eq!(phys_fs.read_file("/file").unwrap().0, b"short");
+    assert_clean(phys_fs);
+}
+
+#[test]
+fn growing_a_file_counts_no_blocks_for_the_hole() {
+    let mut phys_fs = PhysFs::new(RamDisk::new(SIZE), SIZE, clock);
+    phys_fs.create_file("/empty", [6, 4, 4], 0).unwrap();
+    let empty = phys_fs.statfs().free_blocks;
+
+    // only the metadata block is in use
+    phys_fs.truncate("/empty", 1_000_000).unwrap();
+    let stat = phys_fs.stat("/empty").unwrap();
+    assert_eq!((stat.size, stat.blocks), (1_000_000, 1));
+    assert_eq!(phys_fs.statfs().free_blocks, empty);
+
+    phys_fs.write_file("/empty", &pattern(3 * BLOCK_SIZE, 5), None, None).unwrap();
+    phys_fs.truncate("/empty", BLOCK_SIZE as u64).unwrap();
+    assert_eq!(phys_fs.stat("/empty").unwrap().blocks, 2);
+    assert_clean(phys_fs);
+}
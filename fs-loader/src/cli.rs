@@ -0,0 +1,376 @@
+// the rustnix-fs command line, for inspecting and changing an existing image without rebuilding the whole disk directory
+
+use std::io::Write;
+
+use rustnix_fs_core::{fsck, journal::JOURNAL_SIZE, FileKind, FileStat, FsError, PhysFs, BLOCK_SIZE, NUM_INODES};
+
+use crate::{clk, img::Image};
+
+/// the size of an image made by mkfs without --size, the same as the one made from ../disk (4.5 MB: the superblock, journal, bitmaps and inode table, leaving 8061 data blocks)
+pub const DEFAULT_IMAGE_SIZE: u64 = (8192 + 1024 + 1) * 512;
+
+pub const USAGE: &str = "usage:
+    rustnix-fs                                   copy ../disk into a new disk.img
+    rustnix-fs mkfs <image> [--size <bytes>[K|M|G]]
+    rustnix-fs ls <image> [path]
+    rustnix-fs cat <image> <path>
+    rustnix-fs put <image> <host file> <path>
+    rustnix-fs get <image> <path> <host file>
+    rustnix-fs rm <image> <path>
+    rustnix-fs mkdir <image> <path>
+    rustnix-fs ln [-s] <image> <target> <path>
+    rustnix-fs mv <image> <old path> <new path>
+    rustnix-fs chmod <image> <mode> <path>
+    rustnix-fs chown <image> <owner> <path>
+    rustnix-fs stat <image> <path>
+    rustnix-fs fsck [-y|--repair] [image]";
+
+/// the permissions given to files created by put and directories created by mkdir
+const NEW_PERMS: [u8; 3] = [7, 5, 5];
+
+/// run a subcommand, returning the process exit code
+pub fn run(command: &str, args: &[String]) -> i32 {
+    let result = match command {
+        "fsck" => return fsck(args),
+        "mkfs" => mkfs(args),
+        "ls" => ls(args),
+        "cat" => cat(args),
+        "put" => put(args),
+        "get" => get(args),
+        "rm" => rm(args),
+        "mkdir" => mkdir(args),
+        "ln" => ln(args),
+        "mv" => mv(args),
+        "chmod" => chmod(args),
+        "chown" => chown(args),
+        "stat" => stat(args),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            return 0;
+        }
+        _ => Err(format!("unknown command {}\n{}", command, USAGE)),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(message) => {
+            eprintln!("rustnix-fs: {}", message);
+            1
+        }
+    }
+}
+
+/// take exactly the given number of positional arguments
+fn positional<'a, const N: usize>(args: &'a [String], usage: &str) -> Result<[&'a str; N], String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    args.try_into().map_err(|_| format!("usage: rustnix-fs {}", usage))
+}
+
+/// select an image and load the filesystem on it
+fn open(image: &str) -> Result<PhysFs<Image>, String> {
+    let device = Image::open(image).map_err(|err| format!("{}: {}", image, err))?;
+    PhysFs::read_from_disk(device, clk::get_unix_time).map_err(|err| format!("{}: {}", image, err))
+}
+
+/// write back every change made by a command
+fn flush(phys_fs: &mut PhysFs<Image>, image: &str) -> Result<(), String> {
+    phys_fs.flush().map_err(|err| format!("{}: {}", image, err))
+}
+
+/// prefix a filesystem error with the path it happened on
+fn at(path: &str) -> impl Fn(FsError) -> String + '_ {
+    move |err| format!("{}: {}", path, err)
+}
+
+/// parse a size in bytes, with an optional K, M or G suffix
+fn parse_size(size: &str) -> Option<u64> {
+    let (digits, multiplier) = match size.char_indices().last()? {
+        (i, 'K' | 'k') => (&size[..i], 1 << 10),
+        (i, 'M' | 'm') => (&size[..i], 1 << 20),
+        (i, 'G' | 'g') => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// parse Unix-style permissions, such as 755
+fn parse_mode(mode: &str) -> Option<[u8; 3]> {
+    let digits: Vec<u8> = mode
+        .chars()
+        .map(|c| c.to_digit(8).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+
+    digits.try_into().ok()
+}
+
+/// format a kind and permissions like ls -l, such as drwxr-xr-x
+fn mode_string(kind: FileKind, perms: [u8; 3]) -> String {
+    let mut mode = String::from(match kind {
+        FileKind::File => "-",
+        FileKind::Directory => "d",
+        FileKind::Symlink => "l",
+    });
+    for digit in perms {
+        for (bit, c) in [(4, 'r'), (2, 'w'), (1, 'x')] {
+            mode.push(if digit & bit != 0 { c } else { '-' });
+        }
+    }
+    mode
+}
+
+fn stat_line(stat: &FileStat, name: &str) -> String {
+    let suffix = if stat.kind == FileKind::Directory { "/" } else { "" };
+    format!(
+        "{} {:>5} {:>10} {}{}",
+        mode_string(stat.kind, stat.permissions),
+        stat.owner,
+        stat.size,
+        name,
+        suffix
+    )
+}
+
+/// create a new, empty image: rustnix-fs mkfs <image> [--size <bytes>[K|M|G]]
+fn mkfs(args: &[String]) -> Result<(), String> {
+    let usage = "mkfs <image> [--size <bytes>[K|M|G]]";
+    let (image, size) = match args {
+        [image] => (image, DEFAULT_IMAGE_SIZE),
+        [image, flag, size] if flag == "--size" || flag == "-s" => {
+            let size = parse_size(size).ok_or(format!("{}: not a valid size", size))?;
+            (image, size)
+        }
+        _ => return Err(format!("usage: rustnix-fs {}", usage)),
+    };
+
+    // the superblock, journal, inode bitmap and inode table, plus a data bitmap sector, the null block and the root directory
+    let min_sectors = 1 + JOURNAL_SIZE + NUM_INODES.div_ceil(BLOCK_SIZE as u64 * 8) + NUM_INODES + 3;
+    if size / (BLOCK_SIZE as u64) < min_sectors {
+        return Err(format!(
+            "{}: an image must be at least {} bytes",
+            image,
+            min_sectors * BLOCK_SIZE as u64
+        ));
+    }
+    // the disk driver addresses sectors with 32 bits
+    if size / (BLOCK_SIZE as u64) > u32::MAX as u64 {
+        return Err(format!("{}: an image can be at most {} bytes", image, u32::MAX as u64 * BLOCK_SIZE as u64));
+    }
+
+    let device = Image::create(image, size).map_err(|err| format!("{}: {}", image, err))?;
+    let mut phys_fs = PhysFs::new(device, size, clk::get_unix_time);
+    flush(&mut phys_fs, image)?;
+
+    let stats = phys_fs.statfs();
+    println!(
+        "{}: {} bytes, {} inodes, {} data blocks",
+        image, size, stats.total_inodes, stats.total_blocks
+    );
+    Ok(())
+}
+
+/// list a directory, or describe a single file: rustnix-fs ls <image> [path]
+fn ls(args: &[String]) -> Result<(), String> {
+    let (image, path) = match args {
+        [image] => (image.as_str(), "/"),
+        [image, path] => (image.as_str(), path.as_str()),
+        _ => return Err("usage: rustnix-fs ls <image> [path]".to_string()),
+    };
+
+    let mut phys_fs = open(image)?;
+    let stat = phys_fs.stat(path).map_err(at(path))?;
+    if stat.kind != FileKind::Directory {
+        println!("{}", stat_line(&stat, path));
+        return Ok(());
+    }
+
+    let mut entries = phys_fs.read_dir(stat.inode as usize).map_err(at(path))?;
+    entries.sort_by(|(_, a), (_, b)| a.cmp(b));
+
+    for (_, name) in entries {
+        let child = format!("{}/{}", path.trim_end_matches('/'), name);
+        // links are listed as themselves, with where they point
+        let stat = phys_fs.lstat(&child).map_err(at(&child))?;
+        match stat.kind {
+            FileKind::Symlink => {
+                let target = phys_fs.readlink(&child).map_err(at(&child))?;
+                println!("{} -> {}", stat_line(&stat, &name), target);
+            }
+            _ => println!("{}", stat_line(&stat, &name)),
+        }
+    }
+
+    Ok(())
+}
+
+/// print a file to stdout: rustnix-fs cat <image> <path>
+fn cat(args: &[String]) -> Result<(), String> {
+    let [image, path] = positional(args, "cat <image> <path>")?;
+
+    let mut phys_fs = open(image)?;
+    let (data, _) = phys_fs.read_file(path).map_err(at(path))?;
+
+    std::io::stdout()
+        .write_all(&data)
+        .map_err(|err| format!("stdout: {}", err))
+}
+
+/// copy a host file into the image, creating it if needed: rustnix-fs put <image> <host file> <path>
+fn put(args: &[String]) -> Result<(), String> {
+    let [image, host, path] = positional(args, "put <image> <host file> <path>")?;
+
+    let data = std::fs::read(host).map_err(|err| format!("{}: {}", host, err))?;
+
+    let mut phys_fs = open(image)?;
+    match phys_fs.create_file(path, NEW_PERMS, 0) {
+        // an existing file is overwritten, keeping its owner and permissions
+        Ok(()) | Err(FsError::FileExists) => {}
+        Err(err) => return Err(at(path)(err)),
+    }
+
+    phys_fs.write_file(path, &data, None, None).map_err(at(path))?;
+    flush(&mut phys_fs, image)
+}
+
+/// copy a file out of the image: rustnix-fs get <image> <path> <host file>
+fn get(args: &[String]) -> Result<(), String> {
+    let [image, path, host] = positional(args, "get <image> <path> <host file>")?;
+
+    let mut phys_fs = open(image)?;
+    let (data, _) = phys_fs.read_file(path).map_err(at(path))?;
+
+    std::fs::write(host, data).map_err(|err| format!("{}: {}", host, err))
+}
+
+/// remove a file or an empty directory: rustnix-fs rm <image> <path>
+fn rm(args: &[String]) -> Result<(), String> {
+    let [image, path] = positional(args, "rm <image> <path>")?;
+
+    let mut phys_fs = open(image)?;
+    match phys_fs.delete(path) {
+        Err(FsError::IsADirectory) => phys_fs.rmdir(path),
+        result => result,
+    }
+    .map_err(at(path))?;
+
+    flush(&mut phys_fs, image)
+}
+
+/// create a directory: rustnix-fs mkdir <image> <path>
+fn mkdir(args: &[String]) -> Result<(), String> {
+    let [image, path] = positional(args, "mkdir <image> <path>")?;
+
+    let mut phys_fs = open(image)?;
+    phys_fs.mkdir(path, NEW_PERMS, 0).map_err(at(path))?;
+    flush(&mut phys_fs, image)
+}
+
+/// make a hard link, or a symbolic one with -s: rustnix-fs ln [-s] <image> <target> <path>
+fn ln(args: &[String]) -> Result<(), String> {
+    let usage = "ln [-s] <image> <target> <path>";
+    let (symbolic, args) = match args {
+        [flag, rest @ ..] if flag == "-s" => (true, rest),
+        _ => (false, args),
+    };
+    let [image, target, path] = positional(args, usage)?;
+
+    let mut phys_fs = open(image)?;
+    if symbolic {
+        phys_fs.symlink(target, path, 0).map_err(at(path))?;
+    } else {
+        phys_fs.link(target, path).map_err(at(path))?;
+    }
+    flush(&mut phys_fs, image)
+}
+
+/// move or rename a file or directory: rustnix-fs mv <image> <old path> <new path>
+fn mv(args: &[String]) -> Result<(), String> {
+    let [image, old, new] = positional(args, "mv <image> <old path> <new path>")?;
+
+    let mut phys_fs = open(image)?;
+    phys_fs.rename(old, new).map_err(at(old))?;
+    flush(&mut phys_fs, image)
+}
+
+/// change the permissions of a file: rustnix-fs chmod <image> <mode> <path>
+fn chmod(args: &[String]) -> Result<(), String> {
+    let [image, mode, path] = positional(args, "chmod <image> <mode> <path>")?;
+    let perms = parse_mode(mode).ok_or(format!("{}: not a valid mode, expected three octal digits", mode))?;
+
+    let mut phys_fs = open(image)?;
+    phys_fs.chmod(path, perms).map_err(at(path))?;
+    flush(&mut phys_fs, image)
+}
+
+/// change the owner of a file: rustnix-fs chown <image> <owner> <path>
+fn chown(args: &[String]) -> Result<(), String> {
+    let [image, owner, path] = positional(args, "chown <image> <owner> <path>")?;
+    let owner = owner.parse().map_err(|_| format!("{}: not a valid user id", owner))?;
+
+    let mut phys_fs = open(image)?;
+    phys_fs.chown(path, owner).map_err(at(path))?;
+    flush(&mut phys_fs, image)
+}
+
+/// describe a file in detail: rustnix-fs stat <image> <path>
+fn stat(args: &[String]) -> Result<(), String> {
+    let [image, path] = positional(args, "stat <image> <path>")?;
+
+    let mut phys_fs = open(image)?;
+    let stat = phys_fs.lstat(path).map_err(at(path))?;
+
+    let kind = match stat.kind {
+        FileKind::File => "regular file",
+        FileKind::Directory => "directory",
+        FileKind::Symlink => "symbolic link",
+    };
+    let [owner, group, other] = stat.permissions;
+
+    match stat.kind {
+        FileKind::Symlink => println!("  File: {} -> {}", path, phys_fs.readlink(path).map_err(at(path))?),
+        _ => println!("  File: {}", path),
+    }
+    println!("  Type: {}", kind);
+    println!("  Size: {} bytes, {} blocks", stat.size, stat.blocks);
+    println!(" Inode: {}, {} links", stat.inode, stat.links);
+    println!("  Mode: {}{}{} ({})", owner, group, other, mode_string(stat.kind, stat.permissions));
+    println!(" Owner: {}", stat.owner);
+    println!("Access: {}", stat.access_time);
+    println!("Modify: {}", stat.modification_time);
+    println!("Create: {}", stat.creation_time);
+    Ok(())
+}
+
+/// check an image, and repair it if -y or --repair is given: rustnix-fs fsck [-y|--repair] [image]
+fn fsck(args: &[String]) -> i32 {
+    let repair = args.iter().any(|arg| arg == "-y" || arg == "--repair");
+    let image = args
+        .iter()
+        .find(|arg| !arg.starts_with('-'))
+        .map_or("disk.img", String::as_str);
+
+    let device = match Image::open(image) {
+        Ok(device) => device,
+        Err(err) => {
+            println!("{}: {}", image, err);
+            return 8;
+        }
+    };
+
+    match fsck::check(device, clk::get_unix_time, repair) {
+        Ok(report) => {
+            for message in &report.messages {
+                println!("{}", message);
+            }
+            println!(
+                "{}: {} problems found, {} fixed",
+                image, report.problems, report.fixed
+            );
+            report.exit_code()
+        }
+        Err(err) => {
+            println!("{}: check failed: {}", image, err);
+            8
+        }
+    }
+}
@@ -0,0 +1,8 @@
+// the host's clock, standing in for the kernel's clk module
+
+/// Calculate Unix time (seconds since 1970-01-01 00:00:00 UTC)
+pub fn get_unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
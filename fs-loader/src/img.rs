@@ -1,23 +1,71 @@
-use std::io::{Read, Seek, Write};
-
-pub fn read(img_file: &str, block: u32, buf: &mut [u8])  -> Result<(), ()> {
-    // write to img_file
-    println!("Reading from {} block {}", img_file, block);
-    // open img_file
-    let mut file = std::fs::File::open(img_file).unwrap();
-    // seek to block
-    file.seek(std::io::SeekFrom::Start(block as u64 * 512)).unwrap();
-    // read into buf
-    file.read_exact(buf).unwrap();
-    Ok(())
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use rustnix_fs_core::{BLOCK_SIZE, BlockDevice, FsError};
+
+/// an image file that stands in for the disk
+#[derive(Debug)]
+pub struct Image {
+    file: File,
+}
+
+impl Image {
+    /// open an existing image for reading and writing
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = File::options().read(true).write(true).open(path)?;
+        Ok(Image { file })
+    }
+
+    /// create an image of the given size in bytes, replacing any existing file
+    pub fn create(path: impl AsRef<Path>, size: u64) -> std::io::Result<Self> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(size)?;
+        Ok(Image { file })
+    }
 }
 
-pub fn write(img_file: &str, block: u32, buf: &[u8]) -> Result<(), ()> {
-    // open img_file
-    let mut file = std::fs::OpenOptions::new().write(true).open(img_file).unwrap();
-    // seek to block
-    file.seek(std::io::SeekFrom::Start(block as u64 * 512)).unwrap();
-    // write buf
-    file.write_all(buf).unwrap();
-    Ok(())
+impl BlockDevice for Image {
+    fn read(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        self.file
+            .seek(SeekFrom::Start(block * BLOCK_SIZE as u64))
+            .and_then(|_| self.file.read_exact(buf))
+            .map_err(|_| FsError::ReadError)
+    }
+
+    fn write(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        self.file
+            .seek(SeekFrom::Start(block * BLOCK_SIZE as u64))
+            .and_then(|_| self.file.write_all(buf))
+            .map_err(|_| FsError::WriteError)
+    }
+
+    fn read_blocks(&mut self, block: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        self.file
+            .seek(SeekFrom::Start(block * BLOCK_SIZE as u64))
+            .and_then(|_| self.file.read_exact(buf))
+            .map_err(|_| FsError::ReadError)
+    }
+
+    fn write_blocks(&mut self, block: u64, buf: &[u8]) -> Result<(), FsError> {
+        self.file
+            .seek(SeekFrom::Start(block * BLOCK_SIZE as u64))
+            .and_then(|_| self.file.write_all(buf))
+            .map_err(|_| FsError::WriteError)
+    }
+
+    fn block_count(&self) -> u64 {
+        self.file.metadata().map_or(0, |metadata| metadata.len() / BLOCK_SIZE as u64)
+    }
+
+    fn flush(&mut self) -> Result<(), FsError> {
+        self.file.sync_data().map_err(|_| FsError::WriteError)
+    }
 }
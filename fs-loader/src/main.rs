@@ -1,7 +1,13 @@
 use std::{collections::HashMap, path::Path};
 
-mod fs;
+use rustnix_fs_core::PhysFs;
+
+use crate::img::Image;
+
+mod cli;
+mod clk;
 mod img;
+mod logger;
 
 fn list_files(
     initial_path: &Path,
@@ -45,6 +51,21 @@ fn list_files(
     Ok(files)
 }
 
+/// create every missing parent directory of a path, like `mkdir -p`
+fn create_parents(phys_fs: &mut PhysFs<Image>, file_name: &str) {
+    let mut parent = String::new();
+    let components: Vec<&str> = file_name.split('/').filter(|c| !c.is_empty()).collect();
+
+    for component in &components[..components.len().saturating_sub(1)] {
+        parent.push('/');
+        parent.push_str(component);
+
+        if phys_fs.find_inode_index(&parent).is_err() {
+            phys_fs.mkdir(&parent, [7, 7, 7], 0).unwrap();
+        }
+    }
+}
+
 fn human_readable(size: u64) -> String {
     let units = ["B", "KB", "MB", "GB", "TB"];
     let mut size = size as f64;
@@ -58,7 +79,8 @@ fn human_readable(size: u64) -> String {
     format!("{:.2} {}", size, units[unit])
 }
 
-fn main() {
+/// copy ../disk into a new disk.img
+fn load_disk() {
     let files = list_files(
         std::path::Path::new("../disk"),
         std::path::Path::new("../disk"),
@@ -66,20 +88,31 @@ fn main() {
     )
     .unwrap();
 
-    fs::init();
-    let virtfs = fs::get_fs_mut(0,0).unwrap();
-    virtfs.phys_fs.write_to_disk(0, 0).unwrap();
+    let image = Image::create("disk.img", cli::DEFAULT_IMAGE_SIZE).unwrap();
+    let mut phys_fs = PhysFs::new(image, cli::DEFAULT_IMAGE_SIZE, clk::get_unix_time);
+    phys_fs.flush().unwrap();
 
     for (file_name, contents) in files.clone() {
-        virtfs.phys_fs.create_file(file_name.as_str(), [7,7,7], 0).unwrap();
+        create_parents(&mut phys_fs, &file_name);
+        phys_fs.create_file(file_name.as_str(), [7,7,7], 0).unwrap();
 
-        virtfs.phys_fs.write_file(file_name.as_str(), &contents, Some([7,7,7]), Some(0)).unwrap();
+        phys_fs.write_file(file_name.as_str(), &contents, Some([7,7,7]), Some(0)).unwrap();
         println!("Handled file: {}, wrote {}", file_name, human_readable(contents.len() as u64));
     }
 
-    virtfs.phys_fs.write_to_disk(0, 0).unwrap();
+    phys_fs.flush().unwrap();
 
     let file_size = std::fs::metadata("disk.img").unwrap().len();
 
     println!("Handled {} files, total image size {} bytes", files.len(), human_readable(file_size));
 }
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    logger::init();
+
+    match args.get(1) {
+        Some(command) => std::process::exit(cli::run(command, &args[2..])),
+        None => load_disk(),
+    }
+}
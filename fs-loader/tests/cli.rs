@@ -0,0 +1,100 @@
+// the command line, run against images in a scratch directory
+
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+/// a path in the scratch directory for this test, with anything left from an earlier run removed
+fn scratch(name: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn rustnix_fs(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rustnix-fs"))
+        .args(args)
+        .output()
+        .expect("failed to run rustnix-fs")
+}
+
+/// run a command that must succeed, returning what it printed
+fn ok(args: &[&str]) -> String {
+    let output = rustnix_fs(args);
+    assert!(
+        output.status.success(),
+        "rustnix-fs {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn pattern(len: usize, seed: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * seed % 251) as u8).collect()
+}
+
+/// make a fresh image with the default size
+fn mkfs(name: &str) -> String {
+    let image = scratch(name).to_str().unwrap().to_string();
+    ok(&["mkfs", &image]);
+    image
+}
+
+#[test]
+fn put_and_get_round_trip() {
+    let image = mkfs("round_trip.img");
+    let host = scratch("round_trip.in");
+    let out = scratch("round_trip.out");
+    let (host, out) = (host.to_str().unwrap(), out.to_str().unwrap());
+
+    // big enough to need indirect blocks
+    let data = pattern(100_000, 7);
+    fs::write(host, &data).unwrap();
+    ok(&["mkdir", &image, "/dir"]);
+    ok(&["put", &image, host, "/dir/file"]);
+
+    ok(&["get", &image, "/dir/file", out]);
+    assert_eq!(fs::read(out).unwrap(), data);
+    assert!(ok(&["ls", &image, "/dir"]).contains(" 100000 file"));
+
+    // putting over an existing file replaces its contents, and frees what it no longer needs
+    fs::write(host, b"short").unwrap();
+    ok(&["put", &image, host, "/dir/file"]);
+    assert_eq!(ok(&["cat", &image, "/dir/file"]), "short");
+    assert!(ok(&["fsck", &image]).contains("0 problems found"));
+
+    let missing = rustnix_fs(&["get", &image, "/missing", out]);
+    assert_eq!(missing.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("/missing: File not found"));
+}
+
+#[test]
+fn fsck_finds_and_repairs_damage() {
+    let image = mkfs("fsck.img");
+    let host = scratch("fsck.in");
+    let host = host.to_str().unwrap();
+    let data = pattern(20_000, 3);
+    fs::write(host, &data).unwrap();
+    ok(&["put", &image, host, "/file"]);
+
+    let clean = rustnix_fs(&["fsck", &image]);
+    assert_eq!(clean.status.code(), Some(0));
+
+    // clear the data bitmap, which starts at the sector named by the eighth field of the superblock
+    let mut bytes = fs::read(&image).unwrap();
+    let start = u64::from_le_bytes(bytes[56..64].try_into().unwrap()) as usize * 512;
+    bytes[start..start + 512].fill(0);
+    fs::write(&image, &bytes).unwrap();
+
+    let damaged = rustnix_fs(&["fsck", &image]);
+    assert_eq!(damaged.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&damaged.stdout).contains("in use but marked free"));
+
+    let repaired = rustnix_fs(&["fsck", "-y", &image]);
+    assert_eq!(repaired.status.code(), Some(1));
+    assert_eq!(rustnix_fs(&["fsck", &image]).status.code(), Some(0));
+    assert_eq!(rustnix_fs(&["cat", &image, "/file"]).stdout, data);
+}
@@ -5,6 +5,7 @@
 */
 
 use crate::internal::clk;
+use rustnix_fs_core::{BlockDevice, FsError};
 use alloc::vec::Vec;
 use alloc::{borrow::ToOwned, string::String};
 use bit_field::BitField;
@@ -23,6 +24,9 @@ use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
 /// The size of a block in bytes
 pub const BLOCK_SIZE: usize = 512;
 
+/// The most blocks a single command can read or write, written to the sector count register as 0
+const MAX_BLOCKS: usize = 256;
+
 /// Keep track of the last selected bus and drive pair to speed up operations
 pub static LAST_SELECTED: Mutex<Option<(u8, u8)>> = Mutex::new(None);
 
@@ -31,6 +35,7 @@ pub static LAST_SELECTED: Mutex<Option<(u8, u8)>> = Mutex::new(None);
 enum Command {
     Read = 0x20,
     Write = 0x30,
+    CacheFlush = 0xE7,
     Identify = 0xEC,
 }
 
@@ -179,7 +184,7 @@ impl Bus {
         Ok(())
     }
 
-    fn write_command_params(&mut self, drive: u8, block: u32) -> Result<(), ()> {
+    fn write_command_params(&mut self, drive: u8, block: u32, count: usize) -> Result<(), ()> {
         let lba = true;
         let mut bytes = block.to_le_bytes();
         bytes[3].set_bit(4, drive > 0);
@@ -187,7 +192,8 @@ impl Bus {
         bytes[3].set_bit(6, lba);
         bytes[3].set_bit(7, true);
         unsafe {
-            self.sector_count_register.write(1);
+            // a count of 0 means 256 blocks
+            self.sector_count_register.write(count as u8);
             self.lba0_register.write(bytes[0]);
             self.lba1_register.write(bytes[1]);
             self.lba2_register.write(bytes[2]);
@@ -215,19 +221,31 @@ impl Bus {
         Ok(())
     }
 
-    fn setup_pio(&mut self, drive: u8, block: u32) -> Result<(), ()> {
+    fn setup_pio(&mut self, drive: u8, block: u32, count: usize) -> Result<(), ()> {
         self.select_drive(drive)?;
-        self.write_command_params(drive, block)?;
+        self.write_command_params(drive, block, count)?;
         Ok(())
     }
 
+    /// Wait for the drive to be ready to transfer the next block of a command
+    fn wait_next_block(&mut self) -> Result<(), ()> {
+        self.wait(400);
+        self.poll(Status::BSY, false)?;
+        self.poll(Status::DRQ, true)
+    }
+
     fn read(&mut self, drive: u8, block: u32, buf: &mut [u8]) -> Result<(), ()> {
-        assert!(buf.len() == BLOCK_SIZE);
-        self.setup_pio(drive, block)?;
+        assert!(buf.len() % BLOCK_SIZE == 0 && buf.len() <= MAX_BLOCKS * BLOCK_SIZE);
+        self.setup_pio(drive, block, buf.len() / BLOCK_SIZE)?;
         self.write_command(Command::Read)?;
-        for chunk in buf.chunks_mut(2) {
-            let data = self.read_data().to_le_bytes();
-            chunk.clone_from_slice(&data);
+        for (i, sector) in buf.chunks_mut(BLOCK_SIZE).enumerate() {
+            if i > 0 {
+                self.wait_next_block()?;
+            }
+            for chunk in sector.chunks_mut(2) {
+                let data = self.read_data().to_le_bytes();
+                chunk.clone_from_slice(&data);
+            }
         }
         if self.is_error() {
             error!("ATA read: data error");
@@ -239,13 +257,21 @@ impl Bus {
     }
 
     fn write(&mut self, drive: u8, block: u32, buf: &[u8]) -> Result<(), ()> {
-        assert!(buf.len() == BLOCK_SIZE);
-        self.setup_pio(drive, block)?;
+        assert!(buf.len() % BLOCK_SIZE == 0 && buf.len() <= MAX_BLOCKS * BLOCK_SIZE);
+        self.setup_pio(drive, block, buf.len() / BLOCK_SIZE)?;
         self.write_command(Command::Write)?;
-        for chunk in buf.chunks(2) {
-            let data = u16::from_le_bytes(chunk.try_into().unwrap());
-            self.write_data(data);
+        for (i, sector) in buf.chunks(BLOCK_SIZE).enumerate() {
+            if i > 0 {
+                self.wait_next_block()?;
+            }
+            for chunk in sector.chunks(2) {
+                let data = u16::from_le_bytes(chunk.try_into().unwrap());
+                self.write_data(data);
+            }
         }
+        // the last block has been taken once the drive is no longer busy
+        self.wait(400);
+        self.poll(Status::BSY, false)?;
         if self.is_error() {
             error!("ATA write: data error");
             self.debug();
@@ -255,12 +281,27 @@ impl Bus {
         }
     }
 
+    fn flush(&mut self, drive: u8) -> Result<(), ()> {
+        self.select_drive(drive)?;
+        // unlike reads and writes, there is no data to wait for, only the drive to finish
+        unsafe { self.command_register.write(Command::CacheFlush as u8) }
+        self.wait(400);
+        self.poll(Status::BSY, false)?;
+        if self.is_error() {
+            error!("ATA flush: cache error");
+            self.debug();
+            Err(())
+        } else {
+            Ok(())
+        }
+    }
+
     fn identify_drive(&mut self, drive: u8) -> Result<IdentifyResponse, ()> {
         if self.check_floating_bus().is_err() {
             return Ok(IdentifyResponse::None);
         }
         self.select_drive(drive)?;
-        self.write_command_params(drive, 0)?;
+        self.write_command_params(drive, 0, 1)?;
         if self.write_command(Command::Identify).is_err() {
             if self.status() == 0 {
                 return Ok(IdentifyResponse::None);
@@ -417,16 +458,87 @@ pub fn list() -> Vec<Drive> {
     res
 }
 
-/// Read a block from a drive
+/// Read consecutive blocks from a drive, as many as fit in buf
 pub fn read(bus: u8, drive: u8, block: u32, buf: &mut [u8]) -> Result<(), ()> {
     let mut buses = BUSES.lock();
-    buses[bus as usize].read(drive, block, buf)
+    for (i, chunk) in buf.chunks_mut(MAX_BLOCKS * BLOCK_SIZE).enumerate() {
+        buses[bus as usize].read(drive, block + (i * MAX_BLOCKS) as u32, chunk)?;
+    }
+    Ok(())
 }
 
-/// Write a block to a drive
+/// Write consecutive blocks to a drive, as many as fill buf
 pub fn write(bus: u8, drive: u8, block: u32, buf: &[u8]) -> Result<(), ()> {
     let mut buses = BUSES.lock();
-    buses[bus as usize].write(drive, block, buf)
+    for (i, chunk) in buf.chunks(MAX_BLOCKS * BLOCK_SIZE).enumerate() {
+        buses[bus as usize].write(drive, block + (i * MAX_BLOCKS) as u32, chunk)?;
+    }
+    Ok(())
+}
+
+/// Write back the drive's cache, so everything written so far is on the disk
+pub fn flush(bus: u8, drive: u8) -> Result<(), ()> {
+    let mut buses = BUSES.lock();
+    buses[bus as usize].flush(drive)
+}
+
+/// A drive addressed by its bus and drive number, which a filesystem reads and writes through
+#[derive(Clone, Copy, Debug)]
+pub struct AtaDisk {
+    /// The bus number
+    pub bus: u8,
+    /// The drive number
+    pub dsk: u8,
+    block_count: u64,
+}
+
+impl AtaDisk {
+    /// Address a drive, taking its size from the drive, which has no blocks if it is not there
+    pub fn new(bus: u8, dsk: u8) -> Self {
+        let block_count = Drive::open(bus, dsk).map_or(0, |drive| drive.block_count() as u64);
+        AtaDisk {
+            bus,
+            dsk,
+            block_count,
+        }
+    }
+
+    /// Check that a run of blocks is on the drive, which can only address blocks with 28 bits
+    fn contains(&self, block: u64, len: usize) -> bool {
+        block + (len / BLOCK_SIZE) as u64 <= self.block_count.min(1 << 28)
+    }
+}
+
+impl BlockDevice for AtaDisk {
+    fn read(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        self.read_blocks(block, buf)
+    }
+
+    fn write(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        self.write_blocks(block, buf)
+    }
+
+    fn read_blocks(&mut self, block: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        if buf.len() % BLOCK_SIZE != 0 || !self.contains(block, buf.len()) {
+            return Err(FsError::ReadError);
+        }
+        read(self.bus, self.dsk, block as u32, buf).map_err(|_| FsError::ReadError)
+    }
+
+    fn write_blocks(&mut self, block: u64, buf: &[u8]) -> Result<(), FsError> {
+        if buf.len() % BLOCK_SIZE != 0 || !self.contains(block, buf.len()) {
+            return Err(FsError::WriteError);
+        }
+        write(self.bus, self.dsk, block as u32, buf).map_err(|_| FsError::WriteError)
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn flush(&mut self) -> Result<(), FsError> {
+        flush(self.bus, self.dsk).map_err(|_| FsError::WriteError)
+    }
 }
 
 /// Get the bus and drive of the most likely disk (the one with the most blocks)
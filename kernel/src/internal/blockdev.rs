@@ -0,0 +1,211 @@
+/*
+ * block devices, the disks a filesystem can be loaded from
+ * the BlockDevice trait lives in the rustnix-fs-core crate, and is implemented by ATA drives (in the ata module), RAM disks (in rustnix-fs-core) and loopback files (here).
+ * Device holds any one of them behind a lock, so the filesystems in FILESYSTEMS, and the copies of them handed to the mount table, can share it.
+ * a loopback file is read and written through its Stream, so it cannot back a volume held in FILESYSTEMS if it is itself on a rustnix-fs volume, as both would need that lock.
+ */
+
+use alloc::{boxed::Box, sync::Arc};
+use core::fmt::{self, Debug, Formatter};
+use spin::Mutex;
+
+#[cfg(test)]
+use crate::internal::{
+    clk,
+    file::{ALL_FLAGS, FileSystem},
+    tmpfs::TmpFs,
+};
+use crate::internal::{
+    file::{FileError, FileFlags, Stream},
+    fs::FsError,
+    vfs,
+};
+#[cfg(test)]
+use rustnix_fs_core::PhysFs;
+
+pub use rustnix_fs_core::{BLOCK_SIZE, BlockDevice, RamDisk};
+
+/// any block device, shared between everything that holds a copy
+#[derive(Clone)]
+pub struct Device(Arc<Mutex<Box<dyn BlockDevice + Send>>>);
+
+impl Device {
+    /// wrap a block device so it can be shared
+    pub fn new(device: impl BlockDevice + Send + 'static) -> Self {
+        Device(Arc::new(Mutex::new(Box::new(device))))
+    }
+}
+
+impl Debug for Device {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Device")
+            .field("block_count", &self.block_count())
+            .finish()
+    }
+}
+
+impl BlockDevice for Device {
+    fn read(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        self.0.lock().read(block, buf)
+    }
+
+    fn write(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        self.0.lock().write(block, buf)
+    }
+
+    fn read_blocks(&mut self, block: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        self.0.lock().read_blocks(block, buf)
+    }
+
+    fn write_blocks(&mut self, block: u64, buf: &[u8]) -> Result<(), FsError> {
+        self.0.lock().write_blocks(block, buf)
+    }
+
+    fn block_count(&self) -> u64 {
+        self.0.lock().block_count()
+    }
+
+    fn block_size(&self) -> usize {
+        self.0.lock().block_size()
+    }
+
+    fn flush(&mut self) -> Result<(), FsError> {
+        self.0.lock().flush()
+    }
+}
+
+/// a file used as a block device, where block n is the n-th BLOCK_SIZE bytes of the file
+pub struct LoopDevice {
+    file: Box<dyn Stream + Send>,
+    block_count: u64,
+}
+
+impl LoopDevice {
+    /// use an open file as a device of the given size in bytes, which reads as zeroes past the end of the file
+    pub fn new(file: Box<dyn Stream + Send>, size: u64) -> Self {
+        LoopDevice {
+            file,
+            block_count: size / BLOCK_SIZE as u64,
+        }
+    }
+
+    /// open a file as a device covering all of it
+    pub fn open(path: &str) -> Result<Self, FileError> {
+        let size = vfs::stat(path)?.size;
+        let file = vfs::open(path, FileFlags::Read | FileFlags::Write)?;
+        Ok(Self::new(Box::new(file), size))
+    }
+
+    /// check that a run of blocks is inside the device, and move to the first of them
+    fn seek(&mut self, block: u64, len: usize) -> bool {
+        len % BLOCK_SIZE == 0
+            && block + (len / BLOCK_SIZE) as u64 <= self.block_count
+            && self.file.seek(block as usize * BLOCK_SIZE).is_ok()
+    }
+}
+
+impl Debug for LoopDevice {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("LoopDevice")
+            .field("block_count", &self.block_count)
+            .finish()
+    }
+}
+
+impl BlockDevice for LoopDevice {
+    fn read(&mut self, block: u64, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        self.read_blocks(block, buf)
+    }
+
+    fn write(&mut self, block: u64, buf: &[u8; BLOCK_SIZE]) -> Result<(), FsError> {
+        self.write_blocks(block, buf)
+    }
+
+    fn read_blocks(&mut self, block: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        if !self.seek(block, buf.len()) {
+            return Err(FsError::ReadError);
+        }
+
+        // a read can come back short, and comes back empty at the end of the file
+        let mut done = 0;
+        while done < buf.len() {
+            match self.file.read(&mut buf[done..]) {
+                Ok(0) => break,
+                Ok(len) => done += len,
+                Err(_) => return Err(FsError::ReadError),
+            }
+        }
+        buf[done..].fill(0);
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, block: u64, buf: &[u8]) -> Result<(), FsError> {
+        if !self.seek(block, buf.len()) {
+            return Err(FsError::WriteError);
+        }
+
+        let mut done = 0;
+        while done < buf.len() {
+            match self.file.write(&buf[done..]) {
+                Ok(0) | Err(_) => return Err(FsError::WriteError),
+                Ok(len) => done += len,
+            }
+        }
+        Ok(())
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn flush(&mut self) -> Result<(), FsError> {
+        self.file.flush().map_err(|_| FsError::WriteError)
+    }
+}
+
+/// test that copies of a device all see the same blocks
+#[test_case]
+fn test_shared_device() {
+    let mut device = Device::new(RamDisk::new(16 * BLOCK_SIZE as u64));
+    let mut copy = device.clone();
+    assert_eq!(copy.block_count(), 16);
+
+    let data = [7; 2 * BLOCK_SIZE];
+    device.write_blocks(3, &data).unwrap();
+    let mut buf = [0; BLOCK_SIZE];
+    copy.read(4, &mut buf).unwrap();
+    assert_eq!(buf, [7; BLOCK_SIZE]);
+    assert_eq!(copy.read(16, &mut buf), Err(FsError::ReadError));
+}
+
+/// test a volume held in a file on a tmpfs
+#[test_case]
+fn test_loopback_device() {
+    const SIZE: u64 = (1 + 1024 + 1024) * 512;
+    let mut tmpfs = TmpFs::new(SIZE);
+
+    let device = LoopDevice::new(tmpfs.open("/disk", ALL_FLAGS).unwrap(), SIZE);
+    let mut phys_fs = PhysFs::new(device, SIZE, clk::get_unix_time);
+    phys_fs.create_file("/hello", [6, 4, 4], 0).unwrap();
+    phys_fs
+        .write_file("/hello", b"Hello, loop!", None, None)
+        .unwrap();
+    phys_fs.flush().unwrap();
+
+    // the file only grows as far as blocks have been written, and the device reads zeroes past its end
+    assert!(tmpfs.stat("/disk").unwrap().size <= SIZE);
+    let file = tmpfs
+        .open("/disk", FileFlags::Read | FileFlags::Write)
+        .unwrap();
+    let mut device = LoopDevice::new(file, SIZE);
+    let mut buf = [1; BLOCK_SIZE];
+    device.read(SIZE / BLOCK_SIZE as u64 - 1, &mut buf).unwrap();
+    assert_eq!(buf, [0; BLOCK_SIZE]);
+    assert_eq!(
+        device.write(SIZE / BLOCK_SIZE as u64, &buf),
+        Err(FsError::WriteError)
+    );
+
+    let mut phys_fs = PhysFs::read_from_disk(device, clk::get_unix_time).unwrap();
+    assert_eq!(phys_fs.read_file("/hello").unwrap().0, b"Hello, loop!");
+}
@@ -27,13 +27,18 @@ pub fn get_time() -> String {
 /// Calculate Unix time (seconds since 1970-01-01 00:00:00 UTC)
 pub fn get_unix_time() -> u64 {
     let (second, minute, hour, day, month, year) = rtc::read_rtc();
-    let year = year as i64 + 2000;
-    let month = month as i64;
-    let day = day as i64;
-    let hour = hour as u64;
-    let minute = minute as u64;
-    let second = second as u64;
+    to_unix_time(
+        year as i64 + 2000,
+        month as i64,
+        day as i64,
+        hour as u64,
+        minute as u64,
+        second as u64,
+    )
+}
 
+/// Convert a date and time to Unix time, such as a timestamp stored on disk
+pub fn to_unix_time(year: i64, month: i64, day: i64, hour: u64, minute: u64, second: u64) -> u64 {
     // Calculate days since 1970-01-01
     let days = (year - 1970) * 365 + (year - 1969) / 4 - (year - 1901) / 100
         + (year - 1601) / 400
@@ -57,4 +62,4 @@ fn is_leap_year(year: i64) -> bool {
     (year % 4 == 0) && (year % 100 != 0 || year % 400 == 0)
 }
 
-pub use pit::{get_boot_time_ns, sleep, wait};
+pub use pit::{get_boot_time_ns, sleep, sleep_process, wait};
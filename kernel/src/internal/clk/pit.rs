@@ -1,5 +1,7 @@
-use crate::internal::interrupts;
+use crate::internal::{interrupts, process};
+use alloc::vec::Vec;
 use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
 use x86_64::instructions::port::Port;
 
 use x86_64::instructions::interrupts as x86_interrupts;
@@ -15,6 +17,8 @@ pub const PIT_INTERVAL: f64 = (PIT_DIVIDER as f64) / PIT_FREQUENCY;
 
 static PIT_TICKS: AtomicUsize = AtomicUsize::new(0);
 static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+/// the sleeping processes, with the tick each one wakes at
+static SLEEPERS: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
 
 /// Initialize the PIT
 pub fn init() {
@@ -34,7 +38,14 @@ pub fn init() {
 
 // now setup the interrupt handler
 fn pit_handler() {
-    PIT_TICKS.fetch_add(1, Ordering::SeqCst);
+    let ticks = PIT_TICKS.fetch_add(1, Ordering::SeqCst) + 1;
+
+    SLEEPERS.lock().retain(|&(wake_at, id)| {
+        if wake_at <= ticks {
+            process::wake(id);
+        }
+        wake_at > ticks
+    });
 }
 
 /// Get the current TSC value
@@ -69,6 +80,24 @@ pub fn sleep(seconds: f64) {
     }
 }
 
+/// Sleep for a given number of nanoseconds, parking the current process until the PIT wakes it
+///
+/// the SLEEP syscall is run again when the process wakes, so the tick it wakes at is kept by the process. The kernel itself halts as in sleep
+pub fn sleep_process(ns: u64) {
+    let seconds = ns as f64 / 1_000_000_000.0;
+    let wake_at = process::get_wake_at()
+        .unwrap_or(get_ticks() + (seconds / PIT_INTERVAL) as usize);
+
+    if get_ticks() >= wake_at {
+        process::set_wake_at(None);
+    } else if process::block() {
+        process::set_wake_at(Some(wake_at));
+        SLEEPERS.lock().push((wake_at, process::pid()));
+    } else {
+        sleep(seconds);
+    }
+}
+
 /// Calibrate the TSC frequency
 pub fn calibrate_tsc() {
     let start = get_tsc();
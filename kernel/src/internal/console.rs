@@ -6,7 +6,9 @@ use x86_64::instructions::interrupts;
 
 use crate::kprint;
 
-use crate::internal::file::Stream;
+use crate::internal::clk::pit::hlt;
+use crate::internal::file::{IOEvent, Stream};
+use crate::internal::process::WaitQueue;
 
 /// Backspace character
 pub const BACKSPACE: char = '\x08';
@@ -30,6 +32,9 @@ pub static ECHO: AtomicBool = AtomicBool::new(true);
 /// stdin buffer
 pub static STDIN: Mutex<String> = Mutex::new(String::new());
 
+/// the processes waiting for a key to be typed
+pub static STDIN_WAITERS: WaitQueue = WaitQueue::new();
+
 /// raw mode flag
 pub static RAW_MODE: AtomicBool = AtomicBool::new(false);
 
@@ -40,14 +45,21 @@ impl Console {
     }
 }
 
+/// take the first character typed from stdin, if there is one
+fn pop_char() -> Option<char> {
+    interrupts::without_interrupts(|| {
+        let mut stdin = STDIN.lock();
+        (!stdin.is_empty()).then(|| stdin.remove(0))
+    })
+}
+
 /// Read a single character from stdin
 pub fn read_single_char() -> char {
     loop {
-        let res = interrupts::without_interrupts(|| STDIN.lock().pop());
-
-        if let Some(c) = res {
+        if let Some(c) = pop_char() {
             return c;
         }
+        hlt();
     }
 }
 
@@ -103,6 +115,7 @@ pub fn handle_key(key: char) {
         }
     } else {
         STDIN.lock().push(key);
+        STDIN_WAITERS.wake_all();
 
         if is_echo() {
             match key {
@@ -125,13 +138,23 @@ pub fn handle_key(key: char) {
 
 impl Stream for Console {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, crate::internal::file::FileError> {
-        // read buf.len() bytes from stdin
-        let mut i = 0;
-        while i < buf.len() {
-            buf[i] = read_single_char() as u8;
-            i += 1;
+        // read what has been typed, up to buf.len() bytes, waiting for at least one
+        loop {
+            let mut i = 0;
+            while i < buf.len() {
+                match pop_char() {
+                    Some(c) => buf[i] = c as u8,
+                    None => break,
+                }
+                i += 1;
+            }
+
+            // a process is parked until a key is typed, and reads again then
+            if i > 0 || buf.is_empty() || STDIN_WAITERS.wait() {
+                return Ok(i);
+            }
+            hlt();
         }
-        Ok(i)
     }
 
     fn write(&mut self, buf: &[u8]) -> Result<usize, crate::internal::file::FileError> {
@@ -148,8 +171,11 @@ impl Stream for Console {
         Ok(())
     }
 
-    fn poll(&mut self, _event: crate::internal::file::IOEvent) -> bool {
-        true
+    fn poll(&mut self, event: IOEvent) -> bool {
+        match event {
+            IOEvent::Read => interrupts::without_interrupts(|| !STDIN.lock().is_empty()),
+            IOEvent::Write => true,
+        }
     }
 
     fn seek(&mut self, pos: usize) -> Result<usize, crate::internal::file::FileError> {
@@ -0,0 +1,156 @@
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::internal::{
+    file::{FileError, FileSystem, Stream},
+    fs::{FileKind, FileStat, FsError},
+    io::{DEVICE_NAMES, Device},
+};
+#[cfg(test)]
+use crate::internal::file::FileFlags;
+
+/// devfs - a single directory holding a file for each device, which cannot be changed
+#[derive(Debug, Clone)]
+pub struct DevFs;
+
+impl DevFs {
+    /// get the device number for a path within the filesystem
+    fn device(path: &str) -> Result<u8, FileError> {
+        let name = path.trim_start_matches('/');
+        DEVICE_NAMES
+            .iter()
+            .position(|&device| device == name)
+            .map(|number| number as u8)
+            .ok_or(FsError::FileNotFound.into())
+    }
+}
+
+impl FileSystem for DevFs {
+    fn open(&mut self, path: &str, flags: u8) -> Result<Box<dyn Stream + Send>, FileError> {
+        if path == "/" {
+            return Err(FsError::IsADirectory.into());
+        }
+
+        let device = Device::try_from((DevFs::device(path)?, flags))
+            .map_err(|_| FileError::from(FsError::FileNotFound))?;
+        Ok(Box::new(device))
+    }
+
+    fn create(&mut self, _path: &str, _perms: [u8; 3], _owner: u64) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn delete(&mut self, _path: &str) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn exists(&mut self, path: &str) -> bool {
+        self.stat(path).is_ok()
+    }
+
+    fn chmod(&mut self, _path: &str, _perms: [u8; 3]) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn chown(&mut self, _path: &str, _owner: u64) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn list(&mut self, path: &str) -> Result<Vec<String>, FileError> {
+        if path != "/" {
+            DevFs::device(path)?;
+            return Err(FsError::NotADirectory.into());
+        }
+
+        Ok(DEVICE_NAMES.iter().map(|name| name.to_string()).collect())
+    }
+
+    fn mkdir(&mut self, _path: &str, _perms: [u8; 3]) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn rmdir(&mut self, _path: &str) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn get_owner(&mut self, path: &str) -> Result<u64, FileError> {
+        Ok(self.stat(path)?.owner)
+    }
+
+    fn get_perms(&mut self, path: &str) -> Result<[u8; 3], FileError> {
+        Ok(self.stat(path)?.permissions)
+    }
+
+    fn stat(&mut self, path: &str) -> Result<FileStat, FileError> {
+        // every device can be read and written by anyone, the flags it is opened with decide what is allowed
+        let (inode, kind, permissions) = if path == "/" {
+            (0, FileKind::Directory, [7, 5, 5])
+        } else {
+            (DevFs::device(path)? as u64 + 1, FileKind::File, [6, 6, 6])
+        };
+
+        Ok(FileStat {
+            inode,
+            kind,
+            size: 0,
+            blocks: 0,
+            links: 1,
+            owner: 0,
+            permissions,
+            creation_time: 0,
+            modification_time: 0,
+            access_time: 0,
+        })
+    }
+
+    fn rename(&mut self, _old: &str, _new: &str) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn truncate(&mut self, _path: &str, _len: u64) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn link(&mut self, _old: &str, _new: &str) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn symlink(&mut self, _target: &str, _path: &str, _owner: u64) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn readlink(&mut self, path: &str) -> Result<String, FileError> {
+        // there are no symbolic links
+        self.stat(path)?;
+        Err(FsError::InvalidPath.into())
+    }
+
+    fn sync(&mut self) -> Result<(), FileError> {
+        Ok(())
+    }
+}
+
+/// Test opening devices through devfs
+#[test_case]
+fn test_devfs() {
+    let mut dev = DevFs;
+    let mut buf = [1u8; 10];
+
+    dev.open("/zero", FileFlags::Read as u8)
+        .unwrap()
+        .read(&mut buf)
+        .unwrap();
+    assert!(buf.iter().all(|&x| x == 0));
+    assert_eq!(
+        dev.open("/null", FileFlags::Write as u8).unwrap().write(&buf).unwrap(),
+        10
+    );
+
+    assert!(dev.list("/").unwrap().contains(&"random".to_string()));
+    assert_eq!(dev.stat("/null").unwrap().kind, FileKind::File);
+    assert!(!dev.exists("/sda"));
+    assert_eq!(dev.delete("/null"), Err(FsError::ReadOnly.into()));
+}
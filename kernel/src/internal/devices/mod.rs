@@ -9,3 +9,6 @@ pub mod zero;
 
 /// proc - implementation of /proc hierarchy
 pub mod proc;
+
+/// dev - the devfs filesystem mounted at /dev
+pub mod dev;
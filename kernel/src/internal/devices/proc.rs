@@ -1,6 +1,33 @@
-use alloc::{format, string::String};
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::{internal::{file::Stream, fs::FsError, process::PROCESS_TABLE, user}, kprintln};
+use crate::{internal::{
+    file::{FileError, FileFlags, FileSystem, Stream},
+    fs::{FileKind, FileStat, FsError},
+    process::{self, PROCESS_TABLE},
+    user,
+}, kprintln};
+
+/// the files in each process's directory
+const ROUTES: [&str; 4] = ["ppid", "used_memory", "heap_size", "uid"];
+
+/// the files in /sys/kernel, each a limit root can change by writing a new number to it
+const SYSCTLS: [&str; 2] = ["max_procs", "pid_max"];
+
+/// the value behind a file in /sys/kernel, and the lowest it can be set to
+fn sysctl(name: &str) -> Option<(&'static AtomicUsize, usize)> {
+    match name {
+        "max_procs" => Some((&process::MAX_PROCS, 1)),
+        // 0 is the kernel and 1 is init, so anything lower leaves no IDs for other processes
+        "pid_max" => Some((&process::MAX_PID, 2)),
+        _ => None,
+    }
+}
 
 /// process handle
 #[derive(Debug, Clone)]
@@ -120,3 +147,285 @@ impl Stream for ProcInfo {
         }
     }
 }
+
+/// a limit in /sys/kernel, read and written as a decimal number
+#[derive(Debug, Clone)]
+pub struct Sysctl {
+    value: &'static AtomicUsize,
+    min: usize,
+    writable: bool,
+}
+
+impl Stream for Sysctl {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, FileError> {
+        let out = format!("{}", self.value.load(Ordering::SeqCst));
+        let len = out.len().min(buf.len());
+        buf[..len].copy_from_slice(&out.as_bytes()[..len]);
+        Ok(len)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, FileError> {
+        if !self.writable {
+            return Err(FileError::PermissionError(FsError::WriteError.into()));
+        }
+
+        // anything that is not a number, or is too low, is turned down with EINVAL
+        let value = core::str::from_utf8(buf)
+            .ok()
+            .and_then(|s| s.trim().parse::<usize>().ok())
+            .filter(|&value| value >= self.min)
+            .ok_or(FileError::WriteError(FsError::InvalidPath.into()))?;
+        self.value.store(value, Ordering::SeqCst);
+        Ok(buf.len())
+    }
+
+    fn seek(&mut self, _offset: usize) -> Result<usize, FileError> {
+        Ok(0)
+    }
+
+    fn flush(&mut self) -> Result<(), FileError> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), FileError> {
+        Ok(())
+    }
+
+    fn poll(&mut self, event: crate::internal::file::IOEvent) -> bool {
+        match event {
+            crate::internal::file::IOEvent::Read => true,
+            crate::internal::file::IOEvent::Write => self.writable,
+        }
+    }
+}
+
+/// what a path in procfs names
+enum Entry<'a> {
+    /// the root, holding a directory for each process and sys
+    Root,
+    /// a process's directory
+    Process(u32),
+    /// a file in a process's directory
+    Route(u32, &'a str),
+    /// /sys, which holds kernel
+    Sys,
+    /// /sys/kernel, which holds the sysctls
+    SysKernel,
+    /// a file in /sys/kernel
+    Sysctl(&'a str),
+}
+
+/// procfs - a directory for each process, holding a read-only file for each route, and the limits root can change in /sys/kernel
+#[derive(Debug, Clone)]
+pub struct ProcFs;
+
+impl ProcFs {
+    /// work out what a path within the filesystem names, checking that it exists
+    fn parse(path: &str) -> Result<Entry<'_>, FileError> {
+        let mut parts = path.split('/').filter(|part| !part.is_empty());
+
+        let Some(pid) = parts.next() else {
+            return Ok(Entry::Root);
+        };
+        if pid == "sys" {
+            let entry = match (parts.next(), parts.next()) {
+                (None, _) => Entry::Sys,
+                (Some("kernel"), None) => Entry::SysKernel,
+                (Some("kernel"), Some(name)) if SYSCTLS.contains(&name) => Entry::Sysctl(name),
+                _ => return Err(FsError::FileNotFound.into()),
+            };
+            if parts.next().is_some() {
+                return Err(FsError::NotADirectory.into());
+            }
+            return Ok(entry);
+        }
+        let pid = pid
+            .parse::<u32>()
+            .ok()
+            .filter(|&pid| PROCESS_TABLE.read().get(pid as usize).is_some())
+            .ok_or(FsError::FileNotFound)?;
+
+        let entry = match parts.next() {
+            None => Entry::Process(pid),
+            Some(route) if ROUTES.contains(&route) => Entry::Route(pid, route),
+            Some(_) => return Err(FsError::FileNotFound.into()),
+        };
+
+        if parts.next().is_some() {
+            return Err(FsError::NotADirectory.into());
+        }
+
+        Ok(entry)
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn open(&mut self, path: &str, flags: u8) -> Result<Box<dyn Stream + Send>, FileError> {
+        match ProcFs::parse(path)? {
+            Entry::Route(pid, _) => Ok(Box::new(ProcInfo::new(pid, path.to_string()))),
+            Entry::Sysctl(name) => {
+                let (value, min) = sysctl(name).ok_or(FsError::FileNotFound)?;
+                let writable = FileFlags::Write.is_set(flags);
+                Ok(Box::new(Sysctl { value, min, writable }))
+            }
+            _ => Err(FsError::IsADirectory.into()),
+        }
+    }
+
+    fn create(&mut self, _path: &str, _perms: [u8; 3], _owner: u64) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn delete(&mut self, _path: &str) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn exists(&mut self, path: &str) -> bool {
+        ProcFs::parse(path).is_ok()
+    }
+
+    fn chmod(&mut self, _path: &str, _perms: [u8; 3]) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn chown(&mut self, _path: &str, _owner: u64) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn list(&mut self, path: &str) -> Result<Vec<String>, FileError> {
+        match ProcFs::parse(path)? {
+            Entry::Root => {
+                let mut names: Vec<String> =
+                    PROCESS_TABLE.read().pids().map(|pid| format!("{}", pid)).collect();
+                names.push("sys".to_string());
+                Ok(names)
+            }
+            Entry::Process(_) => Ok(ROUTES.iter().map(|route| route.to_string()).collect()),
+            Entry::Sys => Ok(["kernel".to_string()].into()),
+            Entry::SysKernel => Ok(SYSCTLS.iter().map(|name| name.to_string()).collect()),
+            Entry::Route(..) | Entry::Sysctl(_) => Err(FsError::NotADirectory.into()),
+        }
+    }
+
+    fn mkdir(&mut self, _path: &str, _perms: [u8; 3]) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn rmdir(&mut self, _path: &str) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn get_owner(&mut self, path: &str) -> Result<u64, FileError> {
+        Ok(self.stat(path)?.owner)
+    }
+
+    fn get_perms(&mut self, path: &str) -> Result<[u8; 3], FileError> {
+        Ok(self.stat(path)?.permissions)
+    }
+
+    fn stat(&mut self, path: &str) -> Result<FileStat, FileError> {
+        // processes start at inode 256, which leaves those below for the rest
+        let (inode, kind, permissions) = match ProcFs::parse(path)? {
+            Entry::Root => (0, FileKind::Directory, [5, 5, 5]),
+            Entry::Process(pid) => ((pid as u64 + 1) << 8, FileKind::Directory, [5, 5, 5]),
+            Entry::Route(pid, route) => {
+                let route_index = ROUTES.iter().position(|&r| r == route).unwrap_or(0) as u64;
+                ((pid as u64 + 1) << 8 | (route_index + 1), FileKind::File, [4, 4, 4])
+            }
+            Entry::Sys => (1, FileKind::Directory, [5, 5, 5]),
+            Entry::SysKernel => (2, FileKind::Directory, [5, 5, 5]),
+            Entry::Sysctl(name) => {
+                let index = SYSCTLS.iter().position(|&n| n == name).unwrap_or(0) as u64;
+                (3 + index, FileKind::File, [6, 4, 4])
+            }
+        };
+
+        Ok(FileStat {
+            inode,
+            kind,
+            size: 0,
+            blocks: 0,
+            links: 1,
+            owner: 0,
+            permissions,
+            creation_time: 0,
+            modification_time: 0,
+            access_time: 0,
+        })
+    }
+
+    fn rename(&mut self, _old: &str, _new: &str) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn truncate(&mut self, _path: &str, _len: u64) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn link(&mut self, _old: &str, _new: &str) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn symlink(&mut self, _target: &str, _path: &str, _owner: u64) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn readlink(&mut self, path: &str) -> Result<String, FileError> {
+        // there are no symbolic links
+        self.stat(path)?;
+        Err(FsError::InvalidPath.into())
+    }
+
+    fn sync(&mut self) -> Result<(), FileError> {
+        Ok(())
+    }
+}
+
+/// Test the layout of procfs
+#[test_case]
+fn test_procfs() {
+    let mut proc = ProcFs;
+
+    assert!(proc.list("/").unwrap().contains(&"0".to_string()));
+    assert_eq!(proc.list("/0").unwrap().len(), ROUTES.len());
+    assert_eq!(proc.stat("/0").unwrap().kind, FileKind::Directory);
+    assert_eq!(proc.stat("/0/heap_size").unwrap().kind, FileKind::File);
+    assert!(!proc.exists("/0/missing"));
+    assert!(!proc.exists("/not-a-pid"));
+    assert_eq!(proc.mkdir("/1", [7, 5, 5]), Err(FsError::ReadOnly.into()));
+}
+
+/// Test reading and changing the process limits in /sys/kernel
+#[test_case]
+fn test_procfs_sysctl() {
+    let mut proc = ProcFs;
+    assert!(proc.list("/").unwrap().contains(&"sys".to_string()));
+    assert_eq!(proc.list("/sys/kernel").unwrap(), SYSCTLS);
+    assert_eq!(proc.stat("/sys/kernel/pid_max").unwrap().permissions, [6, 4, 4]);
+    assert!(!proc.exists("/sys/kernel/missing"));
+
+    let old = process::MAX_PID.load(Ordering::SeqCst);
+    let mut file = proc
+        .open("/sys/kernel/pid_max", FileFlags::Read | FileFlags::Write)
+        .unwrap();
+    let mut buf = [0; 16];
+    let len = file.read(&mut buf).unwrap();
+    assert_eq!(&buf[..len], format!("{}", old).as_bytes());
+
+    assert_eq!(file.write(b"100\n").unwrap(), 4);
+    assert_eq!(process::MAX_PID.load(Ordering::SeqCst), 100);
+    let len = file.read(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"100");
+
+    // too low, not a number, or not opened for writing
+    assert!(file.write(b"1").is_err());
+    assert!(file.write(b"lots").is_err());
+    assert!(proc
+        .open("/sys/kernel/pid_max", FileFlags::Read as u8)
+        .unwrap()
+        .write(b"200")
+        .is_err());
+    assert_eq!(process::MAX_PID.load(Ordering::SeqCst), 100);
+
+    file.write(format!("{}", old).as_bytes()).unwrap();
+}
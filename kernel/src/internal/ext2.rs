@@ -0,0 +1,1625 @@
+/*
+ * ext2, as made by mkfs.ext2 and debugfs
+ * the superblock sits 1024 bytes into the volume, and the group descriptors follow it. Each block group has a bitmap of its blocks,
+ * a bitmap of its inodes and its part of the inode table.
+ * inodes address their data through 12 direct blocks, then single, double and triple indirect blocks. Directories are lists of
+ * variable-length entries, and symbolic links shorter than 60 bytes are kept in the inode itself.
+ * every change is written straight through to the drive, so there is nothing to flush. Access times are not updated on reads.
+ * volumes with features that are not understood are refused, or mounted read-only if they can still be read safely.
+ */
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use log::warn;
+use rustnix_fs_core::{
+    BLOCK_SIZE, BlockDevice, MAX_NAME_LEN,
+    path::{self, Lookup},
+};
+use spin::Mutex;
+
+use crate::internal::{
+    clk,
+    file::{FileError, FileFlags, FileSystem, IOEvent, Stream},
+    fs::{FileKind, FileStat, FsError, FsStats},
+};
+
+#[cfg(test)]
+use crate::internal::file::ALL_FLAGS;
+#[cfg(test)]
+use rustnix_fs_core::RamDisk;
+
+/// where the superblock starts, in bytes
+const SUPERBLOCK_OFFSET: u64 = 1024;
+/// the size of the superblock
+const SUPERBLOCK_SIZE: usize = 1024;
+/// the magic number in the superblock
+const EXT2_MAGIC: u16 = 0xEF53;
+/// the inode of the root directory
+const ROOT_INO: u32 = 2;
+/// the size of a group descriptor
+const GROUP_DESC_SIZE: usize = 32;
+/// the number of direct block pointers in an inode
+const DIRECT_BLOCKS: u64 = 12;
+/// the bytes of block pointers in an inode, where short symbolic links keep their target instead
+const INLINE_SYMLINK_LEN: usize = 60;
+
+/// directory entries record the type of the file they name
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+/// sparse superblock backups and files over 4 GB can be read and written without knowing about them
+const RO_COMPAT_SUPPORTED: u32 = 0x0001 | 0x0002;
+/// the directory has a hashed index, which is no longer valid once the directory is changed
+const INDEX_FL: u32 = 0x1000;
+
+/// the file type bits of a mode
+const S_IFMT: u16 = 0o170000;
+const S_IFDIR: u16 = 0o040000;
+const S_IFREG: u16 = 0o100000;
+const S_IFLNK: u16 = 0o120000;
+
+/// the file types in directory entries
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_SYMLINK: u8 = 7;
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn set_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn set_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// the space a directory entry with a name of this length takes up
+fn entry_size(name_len: usize) -> usize {
+    (8 + name_len + 3) & !3
+}
+
+/// split a path into its parent directory and final name
+fn split_parent(path: &str) -> Result<(&str, &str), FsError> {
+    let path = path.trim_end_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+
+    match name {
+        "" | "." | ".." => Err(FsError::InvalidPath),
+        _ if name.len() > MAX_NAME_LEN || name.contains('\0') => Err(FsError::NameTooLong),
+        _ => Ok((parent, name)),
+    }
+}
+
+/// split permissions into the bits of a mode
+fn mode_bits(perms: [u8; 3]) -> u16 {
+    ((perms[0] as u16 & 7) << 6) | ((perms[1] as u16 & 7) << 3) | (perms[2] as u16 & 7)
+}
+
+/// an inode, kept as it is on disk so that fields this driver does not know about survive being written back
+#[derive(Debug, Clone)]
+struct Inode {
+    raw: Vec<u8>,
+}
+
+impl Inode {
+    fn mode(&self) -> u16 {
+        u16_at(&self.raw, 0)
+    }
+
+    fn set_mode(&mut self, mode: u16) {
+        set_u16(&mut self.raw, 0, mode);
+    }
+
+    fn kind(&self) -> FileKind {
+        match self.mode() & S_IFMT {
+            S_IFDIR => FileKind::Directory,
+            S_IFLNK => FileKind::Symlink,
+            // device nodes and pipes cannot be used, but can be looked at like files
+            _ => FileKind::File,
+        }
+    }
+
+    fn file_type(&self) -> u8 {
+        match self.kind() {
+            FileKind::File => FT_REG_FILE,
+            FileKind::Directory => FT_DIR,
+            FileKind::Symlink => FT_SYMLINK,
+        }
+    }
+
+    fn uid(&self) -> u32 {
+        u16_at(&self.raw, 2) as u32 | (u16_at(&self.raw, 120) as u32) << 16
+    }
+
+    fn set_uid(&mut self, uid: u32) {
+        set_u16(&mut self.raw, 2, uid as u16);
+        set_u16(&mut self.raw, 120, (uid >> 16) as u16);
+    }
+
+    fn size(&self) -> u64 {
+        // the upper half of the size is only kept for regular files
+        let high = if self.mode() & S_IFMT == S_IFREG {
+            u32_at(&self.raw, 108) as u64
+        } else {
+            0
+        };
+        u32_at(&self.raw, 4) as u64 | high << 32
+    }
+
+    fn set_size(&mut self, size: u64) {
+        set_u32(&mut self.raw, 4, size as u32);
+        if self.mode() & S_IFMT == S_IFREG {
+            set_u32(&mut self.raw, 108, (size >> 32) as u32);
+        }
+    }
+
+    fn time(&self, offset: usize) -> u64 {
+        u32_at(&self.raw, offset) as u64
+    }
+
+    fn set_time(&mut self, offset: usize, time: u64) {
+        set_u32(&mut self.raw, offset, time as u32);
+    }
+
+    fn links(&self) -> u16 {
+        u16_at(&self.raw, 26)
+    }
+
+    fn set_links(&mut self, links: u16) {
+        set_u16(&mut self.raw, 26, links);
+    }
+
+    /// the number of 512 byte sectors in use, including indirect blocks
+    fn sectors(&self) -> u32 {
+        u32_at(&self.raw, 28)
+    }
+
+    fn set_sectors(&mut self, sectors: u32) {
+        set_u32(&mut self.raw, 28, sectors);
+    }
+
+    fn flags(&self) -> u32 {
+        u32_at(&self.raw, 32)
+    }
+
+    fn set_flags(&mut self, flags: u32) {
+        set_u32(&mut self.raw, 32, flags);
+    }
+
+    fn block(&self, index: usize) -> u32 {
+        u32_at(&self.raw, 40 + index * 4)
+    }
+
+    fn set_block(&mut self, index: usize, block: u32) {
+        set_u32(&mut self.raw, 40 + index * 4, block);
+    }
+
+    /// the extended attribute block, which is counted in the sectors in use
+    fn file_acl(&self) -> u32 {
+        u32_at(&self.raw, 104)
+    }
+}
+
+/// the layout of an ext2 volume, and the device it is on
+#[derive(Debug)]
+struct Volume<D: BlockDevice> {
+    device: D,
+    superblock: Vec<u8>,
+    /// the group descriptor table, kept in memory and written through
+    groups: Vec<u8>,
+    block_size: usize,
+    first_data_block: u32,
+    blocks_count: u32,
+    blocks_per_group: u32,
+    inodes_count: u32,
+    inodes_per_group: u32,
+    inode_size: usize,
+    first_ino: u32,
+    group_count: u32,
+    has_file_type: bool,
+    read_only: bool,
+}
+
+impl<D: BlockDevice> Volume<D> {
+    /// read the superblock and group descriptors, failing with InvalidSuperblock if the volume is not ext2 or cannot be read
+    fn read(mut device: D) -> Result<Self, FsError> {
+        let mut superblock = vec![0; SUPERBLOCK_SIZE];
+        device.read_blocks(SUPERBLOCK_OFFSET / BLOCK_SIZE as u64, &mut superblock)?;
+
+        if u16_at(&superblock, 56) != EXT2_MAGIC {
+            return Err(FsError::InvalidSuperblock);
+        }
+
+        let log_block_size = u32_at(&superblock, 24);
+        let blocks_per_group = u32_at(&superblock, 32);
+        let inodes_per_group = u32_at(&superblock, 40);
+        if log_block_size > 6 || blocks_per_group == 0 || inodes_per_group == 0 {
+            return Err(FsError::InvalidSuperblock);
+        }
+
+        // revision 0 has fixed inode sizes and no features
+        let (first_ino, inode_size, incompat, ro_compat) = if u32_at(&superblock, 76) == 0 {
+            (11, 128, 0, 0)
+        } else {
+            (
+                u32_at(&superblock, 84),
+                u16_at(&superblock, 88) as usize,
+                u32_at(&superblock, 96),
+                u32_at(&superblock, 100),
+            )
+        };
+
+        let block_size = 1024 << log_block_size;
+        if inode_size < 128 || !inode_size.is_power_of_two() || inode_size > block_size {
+            return Err(FsError::InvalidSuperblock);
+        }
+        if incompat & !INCOMPAT_FILETYPE != 0 {
+            warn!(
+                "ext2 volume uses unsupported features {:#x}",
+                incompat & !INCOMPAT_FILETYPE
+            );
+            return Err(FsError::InvalidSuperblock);
+        }
+
+        let first_data_block = u32_at(&superblock, 20);
+        let blocks_count = u32_at(&superblock, 4);
+        if first_data_block >= blocks_count {
+            return Err(FsError::InvalidSuperblock);
+        }
+        let group_count = (blocks_count - first_data_block).div_ceil(blocks_per_group);
+
+        // every group but the last has a full set of inodes, so the count must land in the last group
+        let inodes_count = u32_at(&superblock, 0);
+        let inodes = (group_count - 1) as u64 * inodes_per_group as u64..=group_count as u64 * inodes_per_group as u64;
+        if !inodes.contains(&(inodes_count as u64)) || inodes_count == 0 {
+            return Err(FsError::InvalidSuperblock);
+        }
+
+        let mut volume = Volume {
+            device,
+            groups: Vec::new(),
+            block_size,
+            first_data_block,
+            blocks_count,
+            blocks_per_group,
+            inodes_count,
+            inodes_per_group,
+            inode_size,
+            first_ino,
+            group_count,
+            has_file_type: incompat & INCOMPAT_FILETYPE != 0,
+            read_only: ro_compat & !RO_COMPAT_SUPPORTED != 0,
+            superblock,
+        };
+        if volume.read_only {
+            warn!(
+                "ext2 volume uses unsupported features {:#x}, mounting read-only",
+                ro_compat & !RO_COMPAT_SUPPORTED
+            );
+        }
+
+        // the group descriptors start in the block after the superblock
+        let table_blocks = (group_count as usize * GROUP_DESC_SIZE).div_ceil(block_size);
+        for block in 0..table_blocks as u32 {
+            let data = volume.read_block(first_data_block + 1 + block)?;
+            volume.groups.extend(data);
+        }
+
+        Ok(volume)
+    }
+
+    fn read_block(&mut self, block: u32) -> Result<Vec<u8>, FsError> {
+        let mut data = vec![0; self.block_size];
+        self.device
+            .read_blocks(block as u64 * self.sectors_per_block() as u64, &mut data)?;
+        Ok(data)
+    }
+
+    fn write_block(&mut self, block: u32, data: &[u8]) -> Result<(), FsError> {
+        self.device
+            .write_blocks(block as u64 * self.sectors_per_block() as u64, data)
+    }
+
+    fn write_superblock(&mut self) -> Result<(), FsError> {
+        self.device
+            .write_blocks(SUPERBLOCK_OFFSET / BLOCK_SIZE as u64, &self.superblock)
+    }
+
+    /// read a field of a group descriptor
+    fn group_field(&self, group: u32, offset: usize) -> u32 {
+        let start = group as usize * GROUP_DESC_SIZE;
+        match offset {
+            // the counts are 16 bits wide
+            12.. => u16_at(&self.groups, start + offset) as u32,
+            _ => u32_at(&self.groups, start + offset),
+        }
+    }
+
+    /// change the free block, free inode and directory counts of a group and the free counts in the superblock
+    fn adjust_counts(
+        &mut self,
+        group: u32,
+        blocks: i32,
+        inodes: i32,
+        dirs: i32,
+    ) -> Result<(), FsError> {
+        let start = group as usize * GROUP_DESC_SIZE;
+        for (offset, delta) in [(12, blocks), (14, inodes), (16, dirs)] {
+            let value = u16_at(&self.groups, start + offset) as i32 + delta;
+            set_u16(&mut self.groups, start + offset, value as u16);
+        }
+        for (offset, delta) in [(12, blocks), (16, inodes)] {
+            let value = u32_at(&self.superblock, offset) as i64 + delta as i64;
+            set_u32(&mut self.superblock, offset, value as u32);
+        }
+
+        let block = start / self.block_size;
+        let data = self.groups[block * self.block_size..(block + 1) * self.block_size].to_vec();
+        self.write_block(self.first_data_block + 1 + block as u32, &data)?;
+        self.write_superblock()
+    }
+
+    /// find and set the first clear bit in a group's bitmap, below the given limit
+    fn take_bit(&mut self, bitmap: u32, limit: u32) -> Result<Option<u32>, FsError> {
+        let mut data = self.read_block(bitmap)?;
+        let bit = (0..limit).find(|&bit| data[bit as usize / 8] & (1 << (bit % 8)) == 0);
+
+        if let Some(bit) = bit {
+            data[bit as usize / 8] |= 1 << (bit % 8);
+            self.write_block(bitmap, &data)?;
+        }
+        Ok(bit)
+    }
+
+    /// clear a bit in a group's bitmap
+    fn clear_bit(&mut self, bitmap: u32, bit: u32) -> Result<(), FsError> {
+        let mut data = self.read_block(bitmap)?;
+        data[bit as usize / 8] &= !(1 << (bit % 8));
+        self.write_block(bitmap, &data)
+    }
+
+    /// allocate a block and zero it, so that holes and new indirect blocks read as zeroes
+    fn alloc_block(&mut self) -> Result<u32, FsError> {
+        for group in 0..self.group_count {
+            if self.group_field(group, 12) == 0 {
+                continue;
+            }
+
+            // the last group may be cut short by the end of the volume
+            let first = self.first_data_block + group * self.blocks_per_group;
+            let limit = self.blocks_per_group.min(self.blocks_count - first);
+            if let Some(bit) = self.take_bit(self.group_field(group, 0), limit)? {
+                self.adjust_counts(group, -1, 0, 0)?;
+                let block = first + bit;
+                self.write_block(block, &vec![0; self.block_size])?;
+                return Ok(block);
+            }
+        }
+
+        Err(FsError::OutOfDataBlocks)
+    }
+
+    fn free_block(&mut self, block: u32) -> Result<(), FsError> {
+        let group = (block - self.first_data_block) / self.blocks_per_group;
+        let bit = (block - self.first_data_block) % self.blocks_per_group;
+        self.clear_bit(self.group_field(group, 0), bit)?;
+        self.adjust_counts(group, 1, 0, 0)
+    }
+
+    fn alloc_inode(&mut self, is_dir: bool) -> Result<u32, FsError> {
+        for group in 0..self.group_count {
+            if self.group_field(group, 14) == 0 {
+                continue;
+            }
+
+            let limit = self
+                .inodes_per_group
+                .min(self.inodes_count - group * self.inodes_per_group);
+            let mut data = self.read_block(self.group_field(group, 4))?;
+            // the inodes before first_ino are reserved
+            let bit = (0..limit).find(|&bit| {
+                group * self.inodes_per_group + bit + 1 >= self.first_ino
+                    && data[bit as usize / 8] & (1 << (bit % 8)) == 0
+            });
+
+            if let Some(bit) = bit {
+                data[bit as usize / 8] |= 1 << (bit % 8);
+                self.write_block(self.group_field(group, 4), &data)?;
+                self.adjust_counts(group, 0, -1, is_dir as i32)?;
+                return Ok(group * self.inodes_per_group + bit + 1);
+            }
+        }
+
+        Err(FsError::OutOfInodes)
+    }
+
+    fn free_inode(&mut self, ino: u32, is_dir: bool) -> Result<(), FsError> {
+        let group = (ino - 1) / self.inodes_per_group;
+        self.clear_bit(
+            self.group_field(group, 4),
+            (ino - 1) % self.inodes_per_group,
+        )?;
+        self.adjust_counts(group, 0, 1, -(is_dir as i32))
+    }
+
+    /// the block an inode is in, and where in the block it starts
+    fn inode_location(&self, ino: u32) -> Result<(u32, usize), FsError> {
+        if ino == 0 || ino > self.inodes_count {
+            return Err(FsError::InvalidInode);
+        }
+
+        let group = (ino - 1) / self.inodes_per_group;
+        let offset = ((ino - 1) % self.inodes_per_group) as usize * self.inode_size;
+        Ok((
+            self.group_field(group, 8) + (offset / self.block_size) as u32,
+            offset % self.block_size,
+        ))
+    }
+
+    fn read_inode(&mut self, ino: u32) -> Result<Inode, FsError> {
+        let (block, offset) = self.inode_location(ino)?;
+        let data = self.read_block(block)?;
+        Ok(Inode {
+            raw: data[offset..offset + self.inode_size].to_vec(),
+        })
+    }
+
+    fn write_inode(&mut self, ino: u32, inode: &Inode) -> Result<(), FsError> {
+        let (block, offset) = self.inode_location(ino)?;
+        let mut data = self.read_block(block)?;
+        data[offset..offset + self.inode_size].copy_from_slice(&inode.raw);
+        self.write_block(block, &data)
+    }
+
+    /// the number of block pointers in an indirect block
+    fn pointers(&self) -> u64 {
+        (self.block_size / 4) as u64
+    }
+
+    fn sectors_per_block(&self) -> u32 {
+        (self.block_size / BLOCK_SIZE) as u32
+    }
+
+    /// find the block holding a block of a file, allocating it and any indirect blocks on the way if allocate is set
+    ///
+    /// returns None for a hole, unless allocate is set. The inode must be written back by the caller
+    fn map_block(
+        &mut self,
+        inode: &mut Inode,
+        index: u64,
+        allocate: bool,
+    ) -> Result<Option<u32>, FsError> {
+        let pointers = self.pointers();
+        let (slot, depth, mut index) = if index < DIRECT_BLOCKS {
+            (index as usize, 0, 0)
+        } else if index - DIRECT_BLOCKS < pointers {
+            (12, 1, index - DIRECT_BLOCKS)
+        } else if index - DIRECT_BLOCKS - pointers < pointers.pow(2) {
+            (13, 2, index - DIRECT_BLOCKS - pointers)
+        } else if index - DIRECT_BLOCKS - pointers - pointers.pow(2) < pointers.pow(3) {
+            (14, 3, index - DIRECT_BLOCKS - pointers - pointers.pow(2))
+        } else {
+            return Err(FsError::DiskFull);
+        };
+
+        let mut block = inode.block(slot);
+        if block == 0 {
+            if !allocate {
+                return Ok(None);
+            }
+            block = self.alloc_block()?;
+            inode.set_block(slot, block);
+            inode.set_sectors(inode.sectors() + self.sectors_per_block());
+        }
+
+        for level in (0..depth).rev() {
+            let span = pointers.pow(level);
+            let entry = (index / span) as usize * 4;
+            index %= span;
+
+            let mut data = self.read_block(block)?;
+            let mut next = u32_at(&data, entry);
+            if next == 0 {
+                if !allocate {
+                    return Ok(None);
+                }
+                next = self.alloc_block()?;
+                set_u32(&mut data, entry, next);
+                self.write_block(block, &data)?;
+                inode.set_sectors(inode.sectors() + self.sectors_per_block());
+            }
+            block = next;
+        }
+
+        Ok(Some(block))
+    }
+
+    /// free a block and, for an indirect block, whatever it points to from the block index keep onwards
+    ///
+    /// start is the index of the first block of the file under this one. The block is only freed itself if all of it is past keep
+    fn free_tree(
+        &mut self,
+        inode: &mut Inode,
+        block: &mut u32,
+        depth: u32,
+        start: u64,
+        keep: u64,
+    ) -> Result<(), FsError> {
+        if *block == 0 {
+            return Ok(());
+        }
+
+        if depth > 0 {
+            let span = self.pointers().pow(depth - 1);
+            let mut data = self.read_block(*block)?;
+            for entry in 0..self.pointers() {
+                let child_start = start + entry * span;
+                if child_start + span <= keep {
+                    continue;
+                }
+
+                let mut child = u32_at(&data, entry as usize * 4);
+                self.free_tree(inode, &mut child, depth - 1, child_start, keep)?;
+                set_u32(&mut data, entry as usize * 4, child);
+            }
+
+            if start < keep {
+                return self.write_block(*block, &data);
+            }
+        }
+
+        self.free_block(*block)?;
+        inode.set_sectors(inode.sectors() - self.sectors_per_block());
+        *block = 0;
+        Ok(())
+    }
+
+    /// whether a symbolic link keeps its target in the inode instead of a block
+    fn is_fast_symlink(&self, inode: &Inode) -> bool {
+        let acl_sectors = if inode.file_acl() != 0 {
+            self.sectors_per_block()
+        } else {
+            0
+        };
+        inode.kind() == FileKind::Symlink && inode.sectors() == acl_sectors
+    }
+
+    /// cut a file down or grow it to the given length, freeing the blocks past the end
+    fn set_len(&mut self, ino: u32, inode: &mut Inode, len: u64) -> Result<(), FsError> {
+        if len < inode.size() && !self.is_fast_symlink(inode) {
+            let block_size = self.block_size as u64;
+            let keep = len.div_ceil(block_size);
+
+            for slot in keep..DIRECT_BLOCKS {
+                let mut block = inode.block(slot as usize);
+                self.free_tree(inode, &mut block, 0, slot, keep)?;
+                inode.set_block(slot as usize, block);
+            }
+
+            let mut start = DIRECT_BLOCKS;
+            for (slot, depth) in [(12, 1), (13, 2), (14, 3)] {
+                let span = self.pointers().pow(depth);
+                if keep < start + span {
+                    let mut block = inode.block(slot);
+                    self.free_tree(inode, &mut block, depth, start, keep)?;
+                    inode.set_block(slot, block);
+                }
+                start += span;
+            }
+
+            // zero the rest of the last block, so that growing the file again reads zeroes
+            if len % block_size != 0 {
+                if let Some(block) = self.map_block(inode, len / block_size, false)? {
+                    let mut data = self.read_block(block)?;
+                    data[(len % block_size) as usize..].fill(0);
+                    self.write_block(block, &data)?;
+                }
+            }
+        }
+
+        let now = clk::get_unix_time();
+        inode.set_size(len);
+        inode.set_time(12, now);
+        inode.set_time(16, now);
+        self.write_inode(ino, inode)
+    }
+
+    /// read from a file at an offset, with holes reading as zeroes
+    fn read_at(&mut self, ino: u32, pos: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let mut inode = self.read_inode(ino)?;
+        let block_size = self.block_size as u64;
+        let end = inode.size().min(pos + buf.len() as u64);
+
+        let mut pos = pos;
+        let mut read = 0;
+        while pos < end {
+            let offset = (pos % block_size) as usize;
+            let len = (self.block_size - offset).min((end - pos) as usize);
+            match self.map_block(&mut inode, pos / block_size, false)? {
+                Some(block) => buf[read..read + len]
+                    .copy_from_slice(&self.read_block(block)?[offset..offset + len]),
+                None => buf[read..read + len].fill(0),
+            }
+
+            read += len;
+            pos += len as u64;
+        }
+
+        Ok(read)
+    }
+
+    /// write to a file at an offset, allocating blocks as they are needed
+    fn write_at(&mut self, ino: u32, pos: u64, buf: &[u8]) -> Result<usize, FsError> {
+        let mut inode = self.read_inode(ino)?;
+        let block_size = self.block_size as u64;
+
+        let mut written = 0;
+        let mut result = Ok(());
+        while written < buf.len() {
+            let at = pos + written as u64;
+            let offset = (at % block_size) as usize;
+            let len = (self.block_size - offset).min(buf.len() - written);
+
+            result = self
+                .map_block(&mut inode, at / block_size, true)
+                .and_then(|block| {
+                    let block = block.unwrap();
+                    let mut data = if len == self.block_size {
+                        vec![0; self.block_size]
+                    } else {
+                        self.read_block(block)?
+                    };
+                    data[offset..offset + len].copy_from_slice(&buf[written..written + len]);
+                    self.write_block(block, &data)
+                });
+            if result.is_err() {
+                break;
+            }
+            written += len;
+        }
+
+        // whatever was written before running out of space is kept
+        let now = clk::get_unix_time();
+        if pos + written as u64 > inode.size() {
+            inode.set_size(pos + written as u64);
+        }
+        inode.set_time(12, now);
+        inode.set_time(16, now);
+        self.write_inode(ino, &inode)?;
+
+        match result {
+            Ok(()) => Ok(written),
+            Err(err) if written == 0 => Err(err),
+            Err(_) => Ok(written),
+        }
+    }
+
+    /// read the entries of a directory as name, inode and file type, leaving out . and ..
+    fn read_dir(&mut self, ino: u32) -> Result<Vec<(String, u32, u8)>, FsError> {
+        let mut inode = self.read_inode(ino)?;
+        if inode.kind() != FileKind::Directory {
+            return Err(FsError::NotADirectory);
+        }
+
+        let mut entries = Vec::new();
+        for index in 0..inode.size() / self.block_size as u64 {
+            let Some(block) = self.map_block(&mut inode, index, false)? else {
+                continue;
+            };
+            let data = self.read_block(block)?;
+
+            let mut offset = 0;
+            while offset + 8 <= self.block_size {
+                let entry_ino = u32_at(&data, offset);
+                let rec_len = u16_at(&data, offset + 4) as usize;
+                let name_len = data[offset + 6] as usize;
+                if rec_len < 8 || offset + rec_len > self.block_size || 8 + name_len > rec_len {
+                    return Err(FsError::InvalidMetadata);
+                }
+
+                let name = &data[offset + 8..offset + 8 + name_len];
+                if entry_ino != 0 && name != b"." && name != b".." {
+                    let name = core::str::from_utf8(name).map_err(|_| FsError::InvalidMetadata)?;
+                    entries.push((name.to_string(), entry_ino, data[offset + 7]));
+                }
+                offset += rec_len;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// look up a name in a directory
+    fn lookup(&mut self, dir: u32, name: &str) -> Result<Option<u32>, FsError> {
+        Ok(self
+            .read_dir(dir)?
+            .into_iter()
+            .find(|(entry, _, _)| entry == name)
+            .map(|(_, ino, _)| ino))
+    }
+
+    /// read the target of a symbolic link
+    fn read_link(&mut self, ino: u32) -> Result<String, FsError> {
+        let inode = self.read_inode(ino)?;
+        if inode.kind() != FileKind::Symlink {
+            return Err(FsError::InvalidPath);
+        }
+
+        let target = if self.is_fast_symlink(&inode) {
+            inode.raw[40..40 + (inode.size() as usize).min(INLINE_SYMLINK_LEN)].to_vec()
+        } else {
+            let mut target = vec![0; inode.size() as usize];
+            self.read_at(ino, 0, &mut target)?;
+            target
+        };
+        String::from_utf8(target).map_err(|_| FsError::InvalidMetadata)
+    }
+
+    /// resolve a path to the chain of directories leading to it from the root, ending with the inode it names
+    ///
+    /// symbolic links are followed wherever they appear, except at the end of the path when follow_last is false
+    fn resolve(&mut self, path: &str, follow_last: bool) -> Result<Vec<u32>, FsError> {
+        path::resolve(ROOT_INO, path, follow_last, |dir, name, follow| {
+            let child = self.lookup(dir, name)?.ok_or(FsError::FileNotFound)?;
+            if follow && self.read_inode(child)?.kind() == FileKind::Symlink {
+                return Ok(Lookup::Symlink(self.read_link(child)?));
+            }
+            Ok(Lookup::Inode(child))
+        })
+    }
+
+    /// resolve a path to an inode, following symbolic links
+    fn find(&mut self, path: &str) -> Result<u32, FsError> {
+        Ok(*self.resolve(path, true)?.last().unwrap())
+    }
+
+    /// resolve a path like find, except that a symbolic link at the end of it is not followed
+    fn find_entry(&mut self, path: &str) -> Result<u32, FsError> {
+        Ok(*self.resolve(path, false)?.last().unwrap())
+    }
+
+    /// resolve the directory a path is in, returning it along with the final name
+    fn parent_of<'a>(&mut self, path: &'a str) -> Result<(u32, &'a str), FsError> {
+        let (parent, name) = split_parent(path)?;
+        let parent = self.find(parent)?;
+        if self.read_inode(parent)?.kind() != FileKind::Directory {
+            return Err(FsError::NotADirectory);
+        }
+
+        Ok((parent, name))
+    }
+
+    /// write a directory entry into a block
+    fn put_entry(
+        &self,
+        data: &mut [u8],
+        offset: usize,
+        ino: u32,
+        rec_len: usize,
+        name: &str,
+        file_type: u8,
+    ) {
+        set_u32(data, offset, ino);
+        set_u16(data, offset + 4, rec_len as u16);
+        data[offset + 6] = name.len() as u8;
+        data[offset + 7] = if self.has_file_type { file_type } else { 0 };
+        data[offset + 8..offset + 8 + name.len()].copy_from_slice(name.as_bytes());
+    }
+
+    /// note a change to a directory, which also invalidates any hashed index it has
+    fn touch_dir(&mut self, ino: u32, inode: &mut Inode) -> Result<(), FsError> {
+        let now = clk::get_unix_time();
+        inode.set_flags(inode.flags() & !INDEX_FL);
+        inode.set_time(12, now);
+        inode.set_time(16, now);
+        self.write_inode(ino, inode)
+    }
+
+    /// add an entry to a directory, in the first gap big enough for it or in a new block at the end
+    fn add_entry(&mut self, dir: u32, name: &str, ino: u32, file_type: u8) -> Result<(), FsError> {
+        let mut inode = self.read_inode(dir)?;
+        let needed = entry_size(name.len());
+        let blocks = inode.size() / self.block_size as u64;
+
+        for index in 0..blocks {
+            let Some(block) = self.map_block(&mut inode, index, false)? else {
+                continue;
+            };
+            let mut data = self.read_block(block)?;
+
+            let mut offset = 0;
+            while offset + 8 <= self.block_size {
+                let rec_len = u16_at(&data, offset + 4) as usize;
+                if rec_len < 8 {
+                    return Err(FsError::InvalidMetadata);
+                }
+
+                // an unused entry can be taken over, and a used one split if it has room to spare
+                let used = match u32_at(&data, offset) {
+                    0 => 0,
+                    _ => entry_size(data[offset + 6] as usize),
+                };
+                if rec_len >= used + needed {
+                    if used > 0 {
+                        set_u16(&mut data, offset + 4, used as u16);
+                    }
+                    self.put_entry(
+                        &mut data,
+                        offset + used,
+                        ino,
+                        rec_len - used,
+                        name,
+                        file_type,
+                    );
+                    self.write_block(block, &data)?;
+                    return self.touch_dir(dir, &mut inode);
+                }
+                offset += rec_len;
+            }
+        }
+
+        let block = self.map_block(&mut inode, blocks, true)?.unwrap();
+        let mut data = vec![0; self.block_size];
+        self.put_entry(&mut data, 0, ino, self.block_size, name, file_type);
+        self.write_block(block, &data)?;
+        inode.set_size(inode.size() + self.block_size as u64);
+        self.touch_dir(dir, &mut inode)
+    }
+
+    /// find the entry for a name in a directory, returning its block and offset along with the offset of the entry before it in the block
+    fn entry_location(
+        &mut self,
+        dir: u32,
+        name: &str,
+    ) -> Result<(u32, usize, Option<usize>), FsError> {
+        let mut inode = self.read_inode(dir)?;
+        for index in 0..inode.size() / self.block_size as u64 {
+            let Some(block) = self.map_block(&mut inode, index, false)? else {
+                continue;
+            };
+            let data = self.read_block(block)?;
+
+            let mut previous = None;
+            let mut offset = 0;
+            while offset + 8 <= self.block_size {
+                let rec_len = u16_at(&data, offset + 4) as usize;
+                let name_len = data[offset + 6] as usize;
+                if rec_len < 8 || 8 + name_len > rec_len {
+                    return Err(FsError::InvalidMetadata);
+                }
+
+                if u32_at(&data, offset) != 0
+                    && &data[offset + 8..offset + 8 + name_len] == name.as_bytes()
+                {
+                    return Ok((block, offset, previous));
+                }
+                previous = Some(offset);
+                offset += rec_len;
+            }
+        }
+
+        Err(FsError::FileNotFound)
+    }
+
+    /// remove an entry from a directory, merging its space into the entry before it
+    fn remove_entry(&mut self, dir: u32, name: &str) -> Result<(), FsError> {
+        let (block, offset, previous) = self.entry_location(dir, name)?;
+        let mut data = self.read_block(block)?;
+
+        match previous {
+            Some(previous) => {
+                let merged = u16_at(&data, previous + 4) + u16_at(&data, offset + 4);
+                set_u16(&mut data, previous + 4, merged);
+            }
+            None => set_u32(&mut data, offset, 0),
+        }
+        self.write_block(block, &data)?;
+
+        let mut inode = self.read_inode(dir)?;
+        self.touch_dir(dir, &mut inode)
+    }
+
+    /// point an existing entry in a directory at another inode
+    fn replace_entry(
+        &mut self,
+        dir: u32,
+        name: &str,
+        ino: u32,
+        file_type: u8,
+    ) -> Result<(), FsError> {
+        let (block, offset, _) = self.entry_location(dir, name)?;
+        let mut data = self.read_block(block)?;
+        set_u32(&mut data, offset, ino);
+        data[offset + 7] = if self.has_file_type { file_type } else { 0 };
+        self.write_block(block, &data)?;
+
+        let mut inode = self.read_inode(dir)?;
+        self.touch_dir(dir, &mut inode)
+    }
+
+    fn adjust_links(&mut self, ino: u32, delta: i16) -> Result<(), FsError> {
+        let mut inode = self.read_inode(ino)?;
+        inode.set_links((inode.links() as i16 + delta) as u16);
+        inode.set_time(12, clk::get_unix_time());
+        self.write_inode(ino, &inode)
+    }
+
+    /// create an inode and link it into its parent directory, giving a directory its . and .. entries
+    fn create(&mut self, path: &str, mode: u16, uid: u32) -> Result<u32, FsError> {
+        let (parent, name) = self.parent_of(path)?;
+        if self.lookup(parent, name)?.is_some() {
+            return Err(FsError::FileExists);
+        }
+
+        let is_dir = mode & S_IFMT == S_IFDIR;
+        let ino = self.alloc_inode(is_dir)?;
+        let now = clk::get_unix_time();
+
+        let mut inode = Inode {
+            raw: vec![0; self.inode_size],
+        };
+        inode.set_mode(mode);
+        inode.set_uid(uid);
+        for offset in [8, 12, 16] {
+            inode.set_time(offset, now);
+        }
+        inode.set_links(if is_dir { 2 } else { 1 });
+        // the extra fields of large inodes are sized as the superblock asks
+        if self.inode_size > 128 {
+            let extra = u16_at(&self.superblock, 0x15E);
+            set_u16(&mut inode.raw, 128, extra);
+        }
+
+        if is_dir {
+            let block = self.map_block(&mut inode, 0, true)?.unwrap();
+            let mut data = vec![0; self.block_size];
+            self.put_entry(&mut data, 0, ino, entry_size(1), ".", FT_DIR);
+            self.put_entry(
+                &mut data,
+                entry_size(1),
+                parent,
+                self.block_size - entry_size(1),
+                "..",
+                FT_DIR,
+            );
+            self.write_block(block, &data)?;
+            inode.set_size(self.block_size as u64);
+            self.adjust_links(parent, 1)?;
+        }
+        self.write_inode(ino, &inode)?;
+
+        self.add_entry(parent, name, ino, inode.file_type())?;
+        Ok(ino)
+    }
+
+    /// drop one link to an inode, freeing it and its blocks once the last is gone
+    fn drop_link(&mut self, ino: u32) -> Result<(), FsError> {
+        let mut inode = self.read_inode(ino)?;
+        let is_dir = inode.kind() == FileKind::Directory;
+
+        // a directory's own . entry is one of its links
+        let links = inode.links().saturating_sub(if is_dir { 2 } else { 1 });
+        if links > 0 {
+            inode.set_links(links);
+            inode.set_time(12, clk::get_unix_time());
+            return self.write_inode(ino, &inode);
+        }
+
+        self.set_len(ino, &mut inode, 0)?;
+        inode.set_links(0);
+        inode.set_time(20, clk::get_unix_time());
+        self.write_inode(ino, &inode)?;
+        self.free_inode(ino, is_dir)
+    }
+
+    fn stat(&mut self, ino: u32) -> Result<FileStat, FsError> {
+        let inode = self.read_inode(ino)?;
+        let mode = inode.mode();
+        Ok(FileStat {
+            inode: ino as u64,
+            kind: inode.kind(),
+            size: inode.size(),
+            blocks: inode.sectors() as u64,
+            links: inode.links() as u64,
+            owner: inode.uid() as u64,
+            permissions: [
+                ((mode >> 6) & 7) as u8,
+                ((mode >> 3) & 7) as u8,
+                (mode & 7) as u8,
+            ],
+            // ext2 keeps the time the inode last changed instead of when it was created
+            creation_time: inode.time(12),
+            modification_time: inode.time(16),
+            access_time: inode.time(8),
+        })
+    }
+
+    fn check_writable(&self) -> Result<(), FsError> {
+        match self.read_only {
+            true => Err(FsError::ReadOnly),
+            false => Ok(()),
+        }
+    }
+}
+
+/// an ext2 volume
+#[derive(Debug)]
+pub struct Ext2Fs<D: BlockDevice> {
+    volume: Arc<Mutex<Volume<D>>>,
+}
+
+impl<D: BlockDevice> Ext2Fs<D> {
+    /// read an ext2 volume from a device, failing with InvalidSuperblock if it does not hold one this driver can use
+    pub fn new(device: D) -> Result<Self, FsError> {
+        Ok(Ext2Fs {
+            volume: Arc::new(Mutex::new(Volume::read(device)?)),
+        })
+    }
+
+    /// get the block and inode counts of the volume
+    pub fn statfs(&self) -> FsStats {
+        let volume = self.volume.lock();
+        FsStats {
+            block_size: volume.block_size as u64,
+            total_blocks: volume.blocks_count as u64,
+            free_blocks: u32_at(&volume.superblock, 12) as u64,
+            total_inodes: volume.inodes_count as u64,
+            free_inodes: u32_at(&volume.superblock, 16) as u64,
+        }
+    }
+}
+
+/// a file open on an ext2 volume, which implements Stream
+#[derive(Debug)]
+pub struct Ext2File<D: BlockDevice> {
+    volume: Arc<Mutex<Volume<D>>>,
+    ino: u32,
+    flags: u8,
+    file_pos: usize,
+}
+
+impl<D: BlockDevice> Stream for Ext2File<D> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, FileError> {
+        if !FileFlags::Read.is_set(self.flags) {
+            return Err(FileError::PermissionError(FsError::ReadError.into()));
+        }
+
+        let len = self
+            .volume
+            .lock()
+            .read_at(self.ino, self.file_pos as u64, buf)?;
+        self.file_pos += len;
+        Ok(len)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, FileError> {
+        if !FileFlags::Write.is_set(self.flags) {
+            return Err(FileError::PermissionError(FsError::WriteError.into()));
+        }
+
+        let len = self
+            .volume
+            .lock()
+            .write_at(self.ino, self.file_pos as u64, buf)?;
+        self.file_pos += len;
+        Ok(len)
+    }
+
+    fn close(&mut self) -> Result<(), FileError> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), FileError> {
+        Ok(())
+    }
+
+    fn poll(&mut self, event: IOEvent) -> bool {
+        match event {
+            IOEvent::Read => FileFlags::Read.is_set(self.flags),
+            IOEvent::Write => FileFlags::Write.is_set(self.flags),
+        }
+    }
+
+    fn seek(&mut self, pos: usize) -> Result<usize, FileError> {
+        self.file_pos = pos;
+        Ok(pos)
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), FileError> {
+        if !FileFlags::Write.is_set(self.flags) {
+            return Err(FileError::PermissionError(FsError::WriteError.into()));
+        }
+
+        let mut volume = self.volume.lock();
+        let mut inode = volume.read_inode(self.ino)?;
+        Ok(volume.set_len(self.ino, &mut inode, len)?)
+    }
+}
+
+impl<D: BlockDevice + Send + 'static> FileSystem for Ext2Fs<D> {
+    fn open(&mut self, path: &str, flags: u8) -> Result<Box<dyn Stream + Send>, FileError> {
+        let mut volume = self.volume.lock();
+        let writes = FileFlags::Write as u8
+            | FileFlags::Append as u8
+            | FileFlags::Create as u8
+            | FileFlags::Truncate as u8;
+        if flags & writes != 0 {
+            volume.check_writable()?;
+        }
+
+        let ino = match volume.find(path) {
+            Ok(ino) => {
+                if volume.read_inode(ino)?.kind() == FileKind::Directory {
+                    return Err(FsError::IsADirectory.into());
+                }
+                ino
+            }
+            Err(FsError::FileNotFound) if FileFlags::Create.is_set(flags) => {
+                volume.create(path, S_IFREG | mode_bits([6, 6, 6]), 0)?
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut inode = volume.read_inode(ino)?;
+        if FileFlags::Truncate.is_set(flags) {
+            volume.set_len(ino, &mut inode, 0)?;
+        }
+
+        // if the append flag is set, start at the end of the file
+        let file_pos = if FileFlags::Append.is_set(flags) {
+            inode.size() as usize
+        } else {
+            0
+        };
+
+        Ok(Box::new(Ext2File {
+            volume: self.volume.clone(),
+            ino,
+            flags,
+            file_pos,
+        }))
+    }
+
+    fn create(&mut self, path: &str, perms: [u8; 3], owner: u64) -> Result<(), FileError> {
+        let mut volume = self.volume.lock();
+        volume.check_writable()?;
+        volume.create(path, S_IFREG | mode_bits(perms), owner as u32)?;
+        Ok(())
+    }
+
+    fn delete(&mut self, path: &str) -> Result<(), FileError> {
+        let mut volume = self.volume.lock();
+        volume.check_writable()?;
+
+        let ino = volume.find_entry(path)?;
+        if volume.read_inode(ino)?.kind() == FileKind::Directory {
+            return Err(FsError::IsADirectory.into());
+        }
+
+        let (parent, name) = volume.parent_of(path)?;
+        volume.remove_entry(parent, name)?;
+        Ok(volume.drop_link(ino)?)
+    }
+
+    fn exists(&mut self, path: &str) -> bool {
+        self.volume.lock().find(path).is_ok()
+    }
+
+    fn chmod(&mut self, path: &str, perms: [u8; 3]) -> Result<(), FileError> {
+        let mut volume = self.volume.lock();
+        volume.check_writable()?;
+
+        let ino = volume.find(path)?;
+        let mut inode = volume.read_inode(ino)?;
+        inode.set_mode(inode.mode() & !0o777 | mode_bits(perms));
+        inode.set_time(12, clk::get_unix_time());
+        Ok(volume.write_inode(ino, &inode)?)
+    }
+
+    fn chown(&mut self, path: &str, owner: u64) -> Result<(), FileError> {
+        let mut volume = self.volume.lock();
+        volume.check_writable()?;
+
+        let ino = volume.find(path)?;
+        let mut inode = volume.read_inode(ino)?;
+        inode.set_uid(owner as u32);
+        inode.set_time(12, clk::get_unix_time());
+        Ok(volume.write_inode(ino, &inode)?)
+    }
+
+    fn list(&mut self, path: &str) -> Result<Vec<String>, FileError> {
+        let mut volume = self.volume.lock();
+        let ino = volume.find(path)?;
+        Ok(volume
+            .read_dir(ino)?
+            .into_iter()
+            .map(|(name, _, _)| name)
+            .collect())
+    }
+
+    fn mkdir(&mut self, path: &str, perms: [u8; 3]) -> Result<(), FileError> {
+        let mut volume = self.volume.lock();
+        volume.check_writable()?;
+        volume.create(path, S_IFDIR | mode_bits(perms), 0)?;
+        Ok(())
+    }
+
+    fn rmdir(&mut self, path: &str) -> Result<(), FileError> {
+        let mut volume = self.volume.lock();
+        volume.check_writable()?;
+
+        let ino = volume.find_entry(path)?;
+        if ino == ROOT_INO {
+            return Err(FsError::InvalidPath.into());
+        }
+        if volume.read_inode(ino)?.kind() != FileKind::Directory {
+            return Err(FsError::NotADirectory.into());
+        }
+        if !volume.read_dir(ino)?.is_empty() {
+            return Err(FsError::DirectoryNotEmpty.into());
+        }
+
+        let (parent, name) = volume.parent_of(path)?;
+        volume.remove_entry(parent, name)?;
+        volume.adjust_links(parent, -1)?;
+        Ok(volume.drop_link(ino)?)
+    }
+
+    fn get_owner(&mut self, path: &str) -> Result<u64, FileError> {
+        Ok(self.stat(path)?.owner)
+    }
+
+    fn get_perms(&mut self, path: &str) -> Result<[u8; 3], FileError> {
+        Ok(self.stat(path)?.permissions)
+    }
+
+    fn stat(&mut self, path: &str) -> Result<FileStat, FileError> {
+        let mut volume = self.volume.lock();
+        let ino = volume.find(path)?;
+        Ok(volume.stat(ino)?)
+    }
+
+    fn rename(&mut self, old: &str, new: &str) -> Result<(), FileError> {
+        let mut volume = self.volume.lock();
+        volume.check_writable()?;
+
+        let (old_parent, old_name) = volume.parent_of(old)?;
+        let ino = volume
+            .lookup(old_parent, old_name)?
+            .ok_or(FsError::FileNotFound)?;
+        let inode = volume.read_inode(ino)?;
+        let is_dir = inode.kind() == FileKind::Directory;
+
+        // a directory cannot be moved inside itself
+        let (new_parent_path, new_name) = split_parent(new)?;
+        let new_parents = volume.resolve(new_parent_path, true)?;
+        if is_dir && new_parents.contains(&ino) {
+            return Err(FsError::InvalidPath.into());
+        }
+        let new_parent = *new_parents.last().unwrap();
+        if volume.read_inode(new_parent)?.kind() != FileKind::Directory {
+            return Err(FsError::NotADirectory.into());
+        }
+
+        match volume.lookup(new_parent, new_name)? {
+            Some(existing) => {
+                let existing_kind = volume.read_inode(existing)?.kind();
+                if !path::check_replace((ino, inode.kind()), (existing, existing_kind), || {
+                    Ok(volume.read_dir(existing)?.is_empty())
+                })? {
+                    return Ok(());
+                }
+
+                let existing_dir = existing_kind == FileKind::Directory;
+                volume.replace_entry(new_parent, new_name, ino, inode.file_type())?;
+                if existing_dir {
+                    // the replaced directory's .. no longer links to the new parent
+                    volume.adjust_links(new_parent, -1)?;
+                }
+                volume.drop_link(existing)?;
+            }
+            None => volume.add_entry(new_parent, new_name, ino, inode.file_type())?,
+        }
+        volume.remove_entry(old_parent, old_name)?;
+
+        // a directory moved to another parent takes its .. link with it
+        if is_dir && old_parent != new_parent {
+            volume.replace_entry(ino, "..", new_parent, FT_DIR)?;
+            volume.adjust_links(old_parent, -1)?;
+            volume.adjust_links(new_parent, 1)?;
+        }
+        Ok(())
+    }
+
+    fn truncate(&mut self, path: &str, len: u64) -> Result<(), FileError> {
+        let mut volume = self.volume.lock();
+        volume.check_writable()?;
+
+        let ino = volume.find(path)?;
+        let mut inode = volume.read_inode(ino)?;
+        match inode.kind() {
+            FileKind::Directory => Err(FsError::IsADirectory.into()),
+            FileKind::Symlink => Err(FsError::InvalidPath.into()),
+            FileKind::File => Ok(volume.set_len(ino, &mut inode, len)?),
+        }
+    }
+
+    fn link(&mut self, old: &str, new: &str) -> Result<(), FileError> {
+        let mut volume = self.volume.lock();
+        volume.check_writable()?;
+
+        let ino = volume.find(old)?;
+        let inode = volume.read_inode(ino)?;
+        if inode.kind() == FileKind::Directory {
+            return Err(FsError::IsADirectory.into());
+        }
+
+        let (parent, name) = volume.parent_of(new)?;
+        if volume.lookup(parent, name)?.is_some() {
+            return Err(FsError::FileExists.into());
+        }
+
+        volume.add_entry(parent, name, ino, inode.file_type())?;
+        Ok(volume.adjust_links(ino, 1)?)
+    }
+
+    fn symlink(&mut self, target: &str, path: &str, owner: u64) -> Result<(), FileError> {
+        let mut volume = self.volume.lock();
+        volume.check_writable()?;
+
+        let ino = volume.create(path, S_IFLNK | 0o777, owner as u32)?;
+        if target.len() < INLINE_SYMLINK_LEN {
+            let mut inode = volume.read_inode(ino)?;
+            inode.raw[40..40 + target.len()].copy_from_slice(target.as_bytes());
+            inode.set_size(target.len() as u64);
+            Ok(volume.write_inode(ino, &inode)?)
+        } else {
+            volume.write_at(ino, 0, target.as_bytes())?;
+            Ok(())
+        }
+    }
+
+    fn readlink(&mut self, path: &str) -> Result<String, FileError> {
+        let mut volume = self.volume.lock();
+        let ino = volume.find_entry(path)?;
+        Ok(volume.read_link(ino)?)
+    }
+
+    fn sync(&mut self) -> Result<(), FileError> {
+        // every change has already been written through
+        Ok(())
+    }
+}
+
+/// build an empty ext2 volume with 1024 byte blocks, 256 blocks and 32 inodes in a single group, for testing
+#[cfg(test)]
+fn test_volume() -> RamDisk {
+    const BLOCKS: usize = 256;
+    let mut image = vec![0u8; BLOCKS * 1024];
+
+    // blocks 1 to 9 hold the superblock, descriptors, bitmaps, inode table and root directory
+    let superblock = &mut image[1024..2048];
+    for (offset, value) in [
+        (0, 32),
+        (4, BLOCKS as u32),
+        (12, BLOCKS as u32 - 10),
+        (16, 22),
+        (20, 1),
+        (32, 8192),
+        (36, 8192),
+        (40, 32),
+        (76, 1),
+        (84, 11),
+        (96, INCOMPAT_FILETYPE),
+    ] {
+        set_u32(superblock, offset, value);
+    }
+    set_u16(superblock, 56, EXT2_MAGIC);
+    set_u16(superblock, 58, 1);
+    set_u16(superblock, 88, 128);
+
+    let group = &mut image[2048..2048 + GROUP_DESC_SIZE];
+    set_u32(group, 0, 3);
+    set_u32(group, 4, 4);
+    set_u32(group, 8, 5);
+    set_u16(group, 12, BLOCKS as u16 - 10);
+    set_u16(group, 14, 22);
+    set_u16(group, 16, 1);
+
+    // bits past the end of the group are set, as mkfs.ext2 leaves them
+    let block_bitmap = &mut image[3 * 1024..4 * 1024];
+    for bit in (0..9).chain(BLOCKS - 1..8192) {
+        block_bitmap[bit / 8] |= 1 << (bit % 8);
+    }
+    let inode_bitmap = &mut image[4 * 1024..5 * 1024];
+    for bit in (0..10).chain(32..8192) {
+        inode_bitmap[bit / 8] |= 1 << (bit % 8);
+    }
+
+    let root = &mut image[5 * 1024 + 128..5 * 1024 + 256];
+    set_u16(root, 0, S_IFDIR | 0o755);
+    set_u32(root, 4, 1024);
+    set_u16(root, 26, 2);
+    set_u32(root, 28, 2);
+    set_u32(root, 40, 9);
+
+    let dir = &mut image[9 * 1024..10 * 1024];
+    for (offset, name, rec_len) in [(0, ".", 12), (12, "..", 1012)] {
+        set_u32(dir, offset, ROOT_INO);
+        set_u16(dir, offset + 4, rec_len);
+        dir[offset + 6] = name.len() as u8;
+        dir[offset + 7] = FT_DIR;
+        dir[offset + 8..offset + 8 + name.len()].copy_from_slice(name.as_bytes());
+    }
+
+    let mut disk = RamDisk::new(image.len() as u64);
+    for (sector, chunk) in image.chunks_exact(BLOCK_SIZE).enumerate() {
+        disk.write(sector as u64, chunk.try_into().unwrap())
+            .unwrap();
+    }
+    disk
+}
+
+/// test writing and reading a file big enough to need an indirect block, then truncating and deleting it
+#[test_case]
+fn test_ext2_files() {
+    let mut fs = Ext2Fs::new(test_volume()).unwrap();
+    let free = fs.statfs().free_blocks;
+
+    let data: Vec<u8> = (0..20 * 1024).map(|i| (i % 251) as u8).collect();
+    let mut file = fs.open("/big", ALL_FLAGS).unwrap();
+    assert_eq!(file.write(&data).unwrap(), data.len());
+
+    // 20 data blocks and the indirect block, counted in sectors
+    let stat = fs.stat("/big").unwrap();
+    assert_eq!(stat.size, 20 * 1024);
+    assert_eq!(stat.blocks, 21 * 2);
+    assert_eq!(fs.statfs().free_blocks, free - 21);
+
+    let mut buf = vec![0; data.len()];
+    file.seek(0).unwrap();
+    assert_eq!(file.read(&mut buf).unwrap(), data.len());
+    assert_eq!(buf, data);
+
+    // truncating frees the indirect block, and growing again reads zeroes past the old end
+    file.truncate(1000).unwrap();
+    assert_eq!(fs.stat("/big").unwrap().blocks, 2);
+    fs.truncate("/big", 2000).unwrap();
+    let mut buf = [1; 1000];
+    file.seek(1000).unwrap();
+    assert_eq!(file.read(&mut buf).unwrap(), 1000);
+    assert!(buf.iter().all(|&byte| byte == 0));
+
+    fs.open("/big", FileFlags::Write | FileFlags::Append)
+        .unwrap()
+        .write(b"end")
+        .unwrap();
+    assert_eq!(fs.stat("/big").unwrap().size, 2003);
+
+    fs.chmod("/big", [6, 4, 0]).unwrap();
+    fs.chown("/big", 1000).unwrap();
+    assert_eq!(fs.get_perms("/big"), Ok([6, 4, 0]));
+    assert_eq!(fs.get_owner("/big"), Ok(1000));
+
+    fs.delete("/big").unwrap();
+    assert!(!fs.exists("/big"));
+    assert_eq!(fs.statfs().free_blocks, free);
+    assert_eq!(fs.statfs().free_inodes, 22);
+}
+
+/// test that a superblock whose counts do not add up is turned down rather than trusted
+#[test_case]
+fn test_ext2_corrupt_superblock() {
+    // the superblock starts at sector 2, with the inode count then the block count
+    for (offset, value) in [(4, 0), (4, 1), (0, 0), (0, 33)] {
+        let mut disk = test_volume();
+        let mut sector = [0; BLOCK_SIZE];
+        disk.read(2, &mut sector).unwrap();
+        set_u32(&mut sector, offset, value);
+        disk.write(2, &sector).unwrap();
+        assert_eq!(Ext2Fs::new(disk).err(), Some(FsError::InvalidSuperblock));
+    }
+}
+
+/// test directories, renames between them and the link counts that go with them
+#[test_case]
+fn test_ext2_directories() {
+    let mut fs = Ext2Fs::new(test_volume()).unwrap();
+
+    fs.mkdir("/a", [7, 5, 5]).unwrap();
+    fs.mkdir("/b", [7, 5, 5]).unwrap();
+    fs.mkdir("/a/inner", [7, 0, 0]).unwrap();
+    fs.open("/a/inner/file", ALL_FLAGS)
+        .unwrap()
+        .write(b"hi")
+        .unwrap();
+
+    assert_eq!(fs.list("/").unwrap(), ["a".to_string(), "b".to_string()]);
+    assert_eq!(fs.stat("/").unwrap().links, 4);
+    assert_eq!(fs.stat("/a").unwrap().links, 3);
+    assert_eq!(fs.rmdir("/a/inner"), Err(FsError::DirectoryNotEmpty.into()));
+    assert_eq!(
+        fs.rename("/a", "/a/inner/a"),
+        Err(FsError::InvalidPath.into())
+    );
+
+    // the moved directory's .. follows it to its new parent
+    fs.rename("/a/inner", "/b/moved").unwrap();
+    assert_eq!(fs.stat("/a").unwrap().links, 2);
+    assert_eq!(fs.stat("/b").unwrap().links, 3);
+    assert_eq!(
+        fs.stat("/b/moved/..").unwrap().inode,
+        fs.stat("/b").unwrap().inode
+    );
+    assert!(fs.exists("/b/moved/file"));
+
+    // enough hard links to need a second block in the directory
+    for i in 0..40 {
+        fs.link(
+            "/b/moved/file",
+            &alloc::format!("/a/link-with-a-long-name-{}", i),
+        )
+        .unwrap();
+    }
+    assert_eq!(fs.stat("/a").unwrap().size, 2048);
+    assert_eq!(fs.list("/a").unwrap().len(), 40);
+    assert_eq!(fs.stat("/b/moved/file").unwrap().links, 41);
+    for i in 0..40 {
+        fs.delete(&alloc::format!("/a/link-with-a-long-name-{}", i))
+            .unwrap();
+    }
+
+    fs.delete("/b/moved/file").unwrap();
+    fs.rmdir("/b/moved").unwrap();
+    fs.rmdir("/a").unwrap();
+    assert_eq!(fs.stat("/b").unwrap().links, 2);
+    assert_eq!(fs.statfs().free_inodes, 21);
+}
+
+/// test short symbolic links kept in the inode, and longer ones kept in a block
+#[test_case]
+fn test_ext2_symlinks() {
+    let mut fs = Ext2Fs::new(test_volume()).unwrap();
+
+    fs.mkdir("/dir", [7, 5, 5]).unwrap();
+    fs.open("/dir/target", ALL_FLAGS)
+        .unwrap()
+        .write(b"hello")
+        .unwrap();
+
+    let long = "/dir/../dir/./../dir/../dir/./../dir/../dir/./../dir/../dir/target";
+    fs.symlink("dir/target", "/short", 0).unwrap();
+    fs.symlink(long, "/long", 0).unwrap();
+    assert_eq!(fs.readlink("/short"), Ok("dir/target".to_string()));
+    assert_eq!(fs.readlink("/long"), Ok(long.to_string()));
+    assert_eq!(fs.stat("/long").unwrap().size, 5);
+    assert_eq!(fs.readlink("/dir/target"), Err(FsError::InvalidPath.into()));
+
+    let mut buf = [0; 5];
+    fs.open("/short", FileFlags::Read as u8)
+        .unwrap()
+        .read(&mut buf)
+        .unwrap();
+    assert_eq!(&buf, b"hello");
+
+    // removing a link leaves what it points to alone
+    let free = fs.statfs().free_blocks;
+    fs.delete("/short").unwrap();
+    fs.delete("/long").unwrap();
+    assert_eq!(fs.statfs().free_blocks, free + 1);
+    assert!(fs.exists("/dir/target"));
+}
@@ -0,0 +1,800 @@
+/*
+ * FAT32, mounted read-only
+ * the boot sector gives the layout of the volume: reserved sectors, then the FATs, then the data area split into clusters.
+ * each file and directory is a chain of clusters, linked through the first FAT. Directories are arrays of 32 byte entries,
+ * with any long file name stored in the entries just before the 8.3 one it belongs to.
+ * names are matched without regard to case, like on DOS and Windows. Nothing is ever written back.
+ */
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use rustnix_fs_core::{BLOCK_SIZE, BlockDevice};
+use spin::Mutex;
+
+use crate::internal::{
+    clk,
+    file::{FileError, FileFlags, FileSystem, IOEvent, Stream},
+    fs::{FileKind, FileStat, FsError},
+};
+
+#[cfg(test)]
+use rustnix_fs_core::RamDisk;
+
+/// the entry is the volume label, not a file
+const ATTR_VOLUME_ID: u8 = 0x08;
+/// the entry is a directory
+const ATTR_DIRECTORY: u8 = 0x10;
+/// the attributes that mark an entry as part of a long file name
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+/// the size of a directory entry
+const DIR_ENTRY_SIZE: usize = 32;
+/// the number of UCS-2 characters in each long file name entry
+const LFN_CHARS: usize = 13;
+/// the flag on the sequence number of the last long file name entry, which comes first
+const LFN_LAST: u8 = 0x40;
+/// the first byte of an entry that has been deleted
+const DELETED: u8 = 0xE5;
+
+/// only the low 28 bits of a FAT32 entry are used
+const CLUSTER_MASK: u32 = 0x0FFF_FFFF;
+/// entries at or above this end a chain
+const END_OF_CHAIN: u32 = 0x0FFF_FFF8;
+
+/// a file or directory, as described by its directory entry
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    attributes: u8,
+    /// the first cluster of the file, 0 if it is empty
+    cluster: u32,
+    size: u32,
+    creation_time: u64,
+    modification_time: u64,
+    access_time: u64,
+}
+
+impl Entry {
+    fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+}
+
+/// read a little-endian u16 from a buffer
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+/// read a little-endian u32 from a buffer
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+/// convert a FAT date and time to Unix time, with 0 for a date that was never set
+fn unix_time(date: u16, time: u16) -> u64 {
+    if date == 0 {
+        return 0;
+    }
+
+    clk::to_unix_time(
+        1980 + (date >> 9) as i64,
+        ((date >> 5) & 0xF).max(1) as i64,
+        (date & 0x1F).max(1) as i64,
+        (time >> 11) as u64,
+        ((time >> 5) & 0x3F) as u64,
+        (time & 0x1F) as u64 * 2,
+    )
+}
+
+/// the checksum of an 8.3 name, which its long file name entries carry
+fn checksum(short_name: &[u8]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// turn the 11 bytes of an 8.3 name into a name like README.TXT, lowercasing the parts flagged in the case byte
+fn short_name(raw: &[u8], case: u8) -> String {
+    let part = |bytes: &[u8], lower: bool| -> String {
+        bytes
+            .iter()
+            .enumerate()
+            // a leading 0x05 stands for 0xE5, which would otherwise mark the entry as deleted
+            .map(|(i, &byte)| {
+                if i == 0 && byte == 0x05 {
+                    DELETED
+                } else {
+                    byte
+                }
+            })
+            .map(|byte| {
+                if lower {
+                    byte.to_ascii_lowercase()
+                } else {
+                    byte
+                }
+            })
+            .map(char::from)
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    };
+
+    let base = part(&raw[..8], case & 0x08 != 0);
+    let ext = part(&raw[8..11], case & 0x10 != 0);
+    if ext.is_empty() {
+        base
+    } else {
+        base + "." + &ext
+    }
+}
+
+/// the layout of a FAT32 volume, and the device it is on
+#[derive(Debug)]
+struct Volume<D: BlockDevice> {
+    device: D,
+    sectors_per_cluster: u64,
+    /// the first sector of the first FAT
+    fat_start: u64,
+    /// the first sector of cluster 2, the first in the data area
+    data_start: u64,
+    root_cluster: u32,
+    cluster_count: u32,
+}
+
+impl<D: BlockDevice> Volume<D> {
+    /// read the boot sector, failing with InvalidSuperblock if it does not describe a FAT32 volume
+    fn read(mut device: D) -> Result<Self, FsError> {
+        let mut boot = [0; BLOCK_SIZE];
+        device.read(0, &mut boot)?;
+
+        let bytes_per_sector = u16_at(&boot, 11) as usize;
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved_sectors = u16_at(&boot, 14) as u64;
+        let fat_count = boot[16] as u64;
+        let total_sectors = u32_at(&boot, 32) as u64;
+        let fat_size = u32_at(&boot, 36) as u64;
+
+        // FAT12 and FAT16 have a fixed root directory and a 16 bit FAT size instead
+        if boot[510..512] != [0x55, 0xAA]
+            || bytes_per_sector != BLOCK_SIZE
+            || !sectors_per_cluster.is_power_of_two()
+            || fat_count == 0
+            || u16_at(&boot, 17) != 0
+            || u16_at(&boot, 22) != 0
+            || fat_size == 0
+        {
+            return Err(FsError::InvalidSuperblock);
+        }
+
+        let data_start = reserved_sectors + fat_count * fat_size;
+        let cluster_count = total_sectors
+            .checked_sub(data_start)
+            .ok_or(FsError::InvalidSuperblock)?
+            / sectors_per_cluster;
+        // the FAT must have an entry for every cluster
+        let cluster_count = cluster_count.min(fat_size * BLOCK_SIZE as u64 / 4 - 2) as u32;
+
+        let volume = Volume {
+            device,
+            sectors_per_cluster,
+            fat_start: reserved_sectors,
+            data_start,
+            root_cluster: u32_at(&boot, 44),
+            cluster_count,
+        };
+        if !volume.is_cluster(volume.root_cluster) {
+            return Err(FsError::InvalidSuperblock);
+        }
+
+        Ok(volume)
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * BLOCK_SIZE
+    }
+
+    /// whether a number is that of a cluster in the data area
+    fn is_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster - 2 < self.cluster_count
+    }
+
+    fn read_sector(&mut self, sector: u64) -> Result<[u8; BLOCK_SIZE], FsError> {
+        let mut buf = [0; BLOCK_SIZE];
+        self.device.read(sector, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// read a sector within a cluster
+    fn read_cluster_sector(
+        &mut self,
+        cluster: u32,
+        sector: u64,
+    ) -> Result<[u8; BLOCK_SIZE], FsError> {
+        if !self.is_cluster(cluster) {
+            return Err(FsError::InvalidDataBlock);
+        }
+
+        self.read_sector(self.data_start + (cluster - 2) as u64 * self.sectors_per_cluster + sector)
+    }
+
+    /// look up the cluster after this one in its chain, or None at the end of it
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, FsError> {
+        let offset = cluster as u64 * 4;
+        let sector = self.read_sector(self.fat_start + offset / BLOCK_SIZE as u64)?;
+        let next = u32_at(&sector, offset as usize % BLOCK_SIZE) & CLUSTER_MASK;
+
+        match next {
+            next if next >= END_OF_CHAIN => Ok(None),
+            // free and bad clusters cannot be part of a chain
+            next if !self.is_cluster(next) => Err(FsError::InvalidDataBlock),
+            next => Ok(Some(next)),
+        }
+    }
+
+    /// list the clusters of a chain, failing if it loops
+    fn chain(&mut self, start: u32) -> Result<Vec<u32>, FsError> {
+        let mut chain = Vec::new();
+        let mut cluster = Some(start);
+
+        while let Some(current) = cluster {
+            if chain.len() > self.cluster_count as usize {
+                return Err(FsError::InvalidDataBlock);
+            }
+
+            chain.push(current);
+            cluster = self.next_cluster(current)?;
+        }
+
+        Ok(chain)
+    }
+
+    /// the entry for the root directory, which has none of its own
+    fn root(&self) -> Entry {
+        Entry {
+            name: String::new(),
+            attributes: ATTR_DIRECTORY,
+            cluster: self.root_cluster,
+            size: 0,
+            creation_time: 0,
+            modification_time: 0,
+            access_time: 0,
+        }
+    }
+
+    /// read the entries of a directory, leaving out . and .. and the volume label
+    fn read_dir(&mut self, dir: &Entry) -> Result<Vec<Entry>, FsError> {
+        let mut entries = Vec::new();
+        // the parts of the long file name for the next 8.3 entry, last part first, and its checksum
+        let mut long_name: Vec<[u16; LFN_CHARS]> = Vec::new();
+        let mut long_checksum = 0;
+
+        for cluster in self.chain(dir.cluster)? {
+            for sector in 0..self.sectors_per_cluster {
+                let data = self.read_cluster_sector(cluster, sector)?;
+
+                for raw in data.chunks_exact(DIR_ENTRY_SIZE) {
+                    match raw[0] {
+                        // the rest of the directory is unused
+                        0x00 => return Ok(entries),
+                        DELETED => {
+                            long_name.clear();
+                            continue;
+                        }
+                        _ => {}
+                    }
+
+                    let attributes = raw[11];
+                    if attributes & 0x3F == ATTR_LONG_NAME {
+                        if raw[0] & LFN_LAST != 0 {
+                            long_name.clear();
+                            long_checksum = raw[13];
+                        }
+
+                        let mut part = [0; LFN_CHARS];
+                        let offsets = (1..11)
+                            .step_by(2)
+                            .chain((14..26).step_by(2))
+                            .chain((28..32).step_by(2));
+                        for (unit, offset) in part.iter_mut().zip(offsets) {
+                            *unit = u16_at(raw, offset);
+                        }
+                        long_name.push(part);
+                        continue;
+                    }
+
+                    let name = &raw[..11];
+                    if attributes & ATTR_VOLUME_ID != 0 || name[0] == b'.' {
+                        long_name.clear();
+                        continue;
+                    }
+
+                    // a long file name only belongs to this entry if its checksum matches
+                    let name = if !long_name.is_empty() && long_checksum == checksum(name) {
+                        let units = long_name
+                            .iter()
+                            .rev()
+                            .flatten()
+                            .copied()
+                            .take_while(|&unit| unit != 0x0000 && unit != 0xFFFF);
+                        char::decode_utf16(units)
+                            .map(|result| result.unwrap_or(char::REPLACEMENT_CHARACTER))
+                            .collect()
+                    } else {
+                        short_name(name, raw[12])
+                    };
+                    long_name.clear();
+
+                    entries.push(Entry {
+                        name,
+                        attributes,
+                        cluster: (u16_at(raw, 20) as u32) << 16 | u16_at(raw, 26) as u32,
+                        size: u32_at(raw, 28),
+                        creation_time: unix_time(u16_at(raw, 16), u16_at(raw, 14)),
+                        modification_time: unix_time(u16_at(raw, 24), u16_at(raw, 22)),
+                        // only the date of the last access is kept
+                        access_time: unix_time(u16_at(raw, 18), 0),
+                    });
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// find the entry for a path, matching names without regard to case
+    fn find(&mut self, path: &str) -> Result<Entry, FsError> {
+        let mut stack = Vec::from([self.root()]);
+
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            match component {
+                "." => {}
+                ".." => {
+                    if stack.len() > 1 {
+                        stack.pop();
+                    }
+                }
+                name => {
+                    let dir = stack.last().unwrap();
+                    if !dir.is_dir() {
+                        return Err(FsError::NotADirectory);
+                    }
+
+                    let entry = self
+                        .read_dir(dir)?
+                        .into_iter()
+                        .find(|entry| entry.name.eq_ignore_ascii_case(name))
+                        .ok_or(FsError::FileNotFound)?;
+                    stack.push(entry);
+                }
+            }
+        }
+
+        Ok(stack.pop().unwrap())
+    }
+
+    fn stat(&mut self, entry: &Entry) -> Result<FileStat, FsError> {
+        // directories have no size of their own, so they take up the clusters they are in
+        let size = if entry.is_dir() {
+            (self.chain(entry.cluster)?.len() * self.cluster_size()) as u64
+        } else {
+            entry.size as u64
+        };
+
+        // nothing can be written, so the permissions only say what can be read
+        Ok(FileStat {
+            inode: entry.cluster as u64,
+            kind: if entry.is_dir() {
+                FileKind::Directory
+            } else {
+                FileKind::File
+            },
+            size,
+            blocks: size.div_ceil(BLOCK_SIZE as u64),
+            links: 1,
+            owner: 0,
+            permissions: if entry.is_dir() { [5, 5, 5] } else { [4, 4, 4] },
+            creation_time: entry.creation_time,
+            modification_time: entry.modification_time,
+            access_time: entry.access_time,
+        })
+    }
+}
+
+/// a FAT32 volume, mounted read-only
+#[derive(Debug)]
+pub struct FatFs<D: BlockDevice> {
+    volume: Arc<Mutex<Volume<D>>>,
+}
+
+impl<D: BlockDevice> FatFs<D> {
+    /// read a FAT32 volume from a device, failing with InvalidSuperblock if it does not hold one
+    pub fn new(device: D) -> Result<Self, FsError> {
+        Ok(FatFs {
+            volume: Arc::new(Mutex::new(Volume::read(device)?)),
+        })
+    }
+}
+
+/// a file open on a FAT32 volume, which implements Stream
+#[derive(Debug)]
+pub struct FatFile<D: BlockDevice> {
+    volume: Arc<Mutex<Volume<D>>>,
+    first_cluster: u32,
+    size: u32,
+    flags: u8,
+    file_pos: usize,
+    /// the last cluster read and its index in the chain, so that reading on from it does not walk the chain again
+    cursor: Option<(usize, u32)>,
+}
+
+impl<D: BlockDevice> FatFile<D> {
+    /// find the cluster at an index in the file's chain
+    fn cluster_at(&mut self, volume: &mut Volume<D>, index: usize) -> Result<u32, FsError> {
+        let (mut current, mut cluster) = match self.cursor {
+            Some((current, cluster)) if current <= index => (current, cluster),
+            _ => (0, self.first_cluster),
+        };
+
+        while current < index {
+            cluster = volume
+                .next_cluster(cluster)?
+                .ok_or(FsError::InvalidDataBlock)?;
+            current += 1;
+        }
+
+        self.cursor = Some((index, cluster));
+        Ok(cluster)
+    }
+}
+
+impl<D: BlockDevice> Stream for FatFile<D> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, FileError> {
+        if !FileFlags::Read.is_set(self.flags) {
+            return Err(FileError::PermissionError(FsError::ReadError.into()));
+        }
+
+        let volume = self.volume.clone();
+        let mut volume = volume.lock();
+        let cluster_size = volume.cluster_size();
+        let end = (self.size as usize).min(self.file_pos + buf.len());
+
+        let mut read = 0;
+        while self.file_pos < end {
+            let cluster = self.cluster_at(&mut volume, self.file_pos / cluster_size)?;
+            let offset = self.file_pos % cluster_size;
+            let sector = volume.read_cluster_sector(cluster, (offset / BLOCK_SIZE) as u64)?;
+
+            let start = offset % BLOCK_SIZE;
+            let len = (BLOCK_SIZE - start).min(end - self.file_pos);
+            buf[read..read + len].copy_from_slice(&sector[start..start + len]);
+            read += len;
+            self.file_pos += len;
+        }
+
+        Ok(read)
+    }
+
+    fn write(&mut self, _buf: &[u8]) -> Result<usize, FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn close(&mut self) -> Result<(), FileError> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), FileError> {
+        Ok(())
+    }
+
+    fn poll(&mut self, event: IOEvent) -> bool {
+        match event {
+            IOEvent::Read => FileFlags::Read.is_set(self.flags),
+            IOEvent::Write => false,
+        }
+    }
+
+    fn seek(&mut self, pos: usize) -> Result<usize, FileError> {
+        self.file_pos = pos;
+        Ok(pos)
+    }
+
+    fn truncate(&mut self, _len: u64) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+}
+
+impl<D: BlockDevice + Send + 'static> FileSystem for FatFs<D> {
+    fn open(&mut self, path: &str, flags: u8) -> Result<Box<dyn Stream + Send>, FileError> {
+        let writes = FileFlags::Write as u8
+            | FileFlags::Append as u8
+            | FileFlags::Create as u8
+            | FileFlags::Truncate as u8;
+        if flags & writes != 0 {
+            return Err(FsError::ReadOnly.into());
+        }
+
+        let entry = self.volume.lock().find(path)?;
+        if entry.is_dir() {
+            return Err(FsError::IsADirectory.into());
+        }
+
+        Ok(Box::new(FatFile {
+            volume: self.volume.clone(),
+            first_cluster: entry.cluster,
+            size: entry.size,
+            flags,
+            file_pos: 0,
+            cursor: None,
+        }))
+    }
+
+    fn create(&mut self, _path: &str, _perms: [u8; 3], _owner: u64) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn delete(&mut self, _path: &str) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn exists(&mut self, path: &str) -> bool {
+        self.volume.lock().find(path).is_ok()
+    }
+
+    fn chmod(&mut self, _path: &str, _perms: [u8; 3]) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn chown(&mut self, _path: &str, _owner: u64) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn list(&mut self, path: &str) -> Result<Vec<String>, FileError> {
+        let mut volume = self.volume.lock();
+        let entry = volume.find(path)?;
+        if !entry.is_dir() {
+            return Err(FsError::NotADirectory.into());
+        }
+
+        Ok(volume
+            .read_dir(&entry)?
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect())
+    }
+
+    fn mkdir(&mut self, _path: &str, _perms: [u8; 3]) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn rmdir(&mut self, _path: &str) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn get_owner(&mut self, path: &str) -> Result<u64, FileError> {
+        Ok(self.stat(path)?.owner)
+    }
+
+    fn get_perms(&mut self, path: &str) -> Result<[u8; 3], FileError> {
+        Ok(self.stat(path)?.permissions)
+    }
+
+    fn stat(&mut self, path: &str) -> Result<FileStat, FileError> {
+        let mut volume = self.volume.lock();
+        let entry = volume.find(path)?;
+        Ok(volume.stat(&entry)?)
+    }
+
+    fn rename(&mut self, _old: &str, _new: &str) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn truncate(&mut self, _path: &str, _len: u64) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn link(&mut self, _old: &str, _new: &str) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn symlink(&mut self, _target: &str, _path: &str, _owner: u64) -> Result<(), FileError> {
+        Err(FsError::ReadOnly.into())
+    }
+
+    fn readlink(&mut self, path: &str) -> Result<String, FileError> {
+        // there are no symbolic links
+        self.stat(path)?;
+        Err(FsError::InvalidPath.into())
+    }
+
+    fn sync(&mut self) -> Result<(), FileError> {
+        Ok(())
+    }
+}
+
+/// write a directory entry into a buffer, for testing
+#[cfg(test)]
+fn put_entry(
+    buf: &mut [u8],
+    index: usize,
+    name: &[u8; 11],
+    attributes: u8,
+    case: u8,
+    cluster: u32,
+    size: u32,
+) {
+    let raw = &mut buf[index * DIR_ENTRY_SIZE..(index + 1) * DIR_ENTRY_SIZE];
+    raw[..11].copy_from_slice(name);
+    raw[11] = attributes;
+    raw[12] = case;
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    // modified at 03:04:06 on 2024-01-02
+    raw[22..24].copy_from_slice(&((3 << 11) | (4 << 5) | 3u16).to_le_bytes());
+    raw[24..26].copy_from_slice(&((44 << 9) | (1 << 5) | 2u16).to_le_bytes());
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    raw[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+/// build a small FAT32 volume, one sector per cluster, for testing
+///
+/// / holds "A long file name.txt" (600 bytes over clusters 4 and 5), readme.txt and DOCS, which holds an empty NOTES.TXT
+#[cfg(test)]
+fn test_volume() -> RamDisk {
+    const RESERVED: usize = 4;
+    const SECTORS: usize = 22;
+    let mut image = [[0u8; BLOCK_SIZE]; SECTORS];
+
+    let boot = &mut image[0];
+    boot[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+    boot[13] = 1;
+    boot[14..16].copy_from_slice(&(RESERVED as u16).to_le_bytes());
+    boot[16] = 2;
+    boot[32..36].copy_from_slice(&(SECTORS as u32).to_le_bytes());
+    boot[36..40].copy_from_slice(&1u32.to_le_bytes());
+    boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+    boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    // both FATs: the two reserved entries, then the root, DOCS, the long file and readme.txt
+    let fat: [u32; 7] = [
+        0x0FFF_FFF8,
+        CLUSTER_MASK,
+        CLUSTER_MASK,
+        CLUSTER_MASK,
+        5,
+        CLUSTER_MASK,
+        CLUSTER_MASK,
+    ];
+    for copy in 0..2 {
+        for (i, entry) in fat.iter().enumerate() {
+            image[RESERVED + copy][i * 4..i * 4 + 4].copy_from_slice(&entry.to_le_bytes());
+        }
+    }
+
+    // cluster n is in sector data + n, as the data area starts with cluster 2 after the reserved sectors and both FATs
+    let data = RESERVED + 2 - 2;
+    let short = b"ALONGF~1TXT";
+    let root = &mut image[data + 2];
+    put_entry(root, 0, b"RUSTNIX    ", ATTR_VOLUME_ID, 0, 0, 0);
+
+    // the long name takes two entries, the second part (with the last flag) first
+    let long_name: Vec<u16> = "A long file name.txt".encode_utf16().collect();
+    for (index, sequence) in [(1, 2u8), (2, 1u8)] {
+        let raw = &mut root[index * DIR_ENTRY_SIZE..(index + 1) * DIR_ENTRY_SIZE];
+        raw[0] = if sequence == 2 {
+            sequence | LFN_LAST
+        } else {
+            sequence
+        };
+        raw[11] = ATTR_LONG_NAME;
+        raw[13] = checksum(short);
+        let offsets = (1..11)
+            .step_by(2)
+            .chain((14..26).step_by(2))
+            .chain((28..32).step_by(2));
+        for (i, offset) in offsets.enumerate() {
+            let unit = match long_name.get((sequence as usize - 1) * LFN_CHARS + i) {
+                Some(&unit) => unit,
+                None if (sequence as usize - 1) * LFN_CHARS + i == long_name.len() => 0,
+                None => 0xFFFF,
+            };
+            raw[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+    put_entry(root, 3, short, 0x20, 0, 4, 600);
+    put_entry(root, 4, b"GONE    TXT", 0x20, 0, 0, 0);
+    root[4 * DIR_ENTRY_SIZE] = DELETED;
+    put_entry(root, 5, b"README  TXT", 0x20, 0x18, 6, 5);
+    put_entry(root, 6, b"DOCS       ", ATTR_DIRECTORY, 0, 3, 0);
+
+    let docs = &mut image[data + 3];
+    put_entry(docs, 0, b".          ", ATTR_DIRECTORY, 0, 3, 0);
+    put_entry(docs, 1, b"..         ", ATTR_DIRECTORY, 0, 0, 0);
+    put_entry(docs, 2, b"NOTES   TXT", 0x20, 0, 0, 0);
+
+    for (i, byte) in image[data + 4..data + 6].iter_mut().flatten().enumerate() {
+        *byte = (i % 251) as u8;
+    }
+    image[data + 6][..5].copy_from_slice(b"hello");
+
+    let mut disk = RamDisk::new((SECTORS * BLOCK_SIZE) as u64);
+    for (sector, buf) in image.iter().enumerate() {
+        disk.write(sector as u64, buf).unwrap();
+    }
+    disk
+}
+
+/// test listing directories and reading files, including one with a long name spread over two clusters
+#[test_case]
+fn test_fat_read() {
+    let mut fat = FatFs::new(test_volume()).unwrap();
+
+    assert_eq!(fat.list("/").unwrap(), [
+        "A long file name.txt".to_string(),
+        "readme.txt".to_string(),
+        "DOCS".to_string()
+    ]);
+    assert_eq!(fat.list("/docs").unwrap(), ["NOTES.TXT".to_string()]);
+    assert!(fat.exists("/a LONG file name.TXT"));
+    assert!(!fat.exists("/gone.txt"));
+
+    let mut file = fat
+        .open("/A long file name.txt", FileFlags::Read as u8)
+        .unwrap();
+    let mut buf = [0; 700];
+    assert_eq!(file.read(&mut buf).unwrap(), 600);
+    assert!(
+        buf[..600]
+            .iter()
+            .enumerate()
+            .all(|(i, &byte)| byte == (i % 251) as u8)
+    );
+
+    // reading from the middle of the second cluster
+    file.seek(550).unwrap();
+    assert_eq!(file.read(&mut buf[..10]).unwrap(), 10);
+    assert_eq!(buf[0], (550 % 251) as u8);
+
+    let mut buf = [0; 5];
+    fat.open("/DOCS/../README.TXT", FileFlags::Read as u8)
+        .unwrap()
+        .read(&mut buf)
+        .unwrap();
+    assert_eq!(&buf, b"hello");
+
+    let stat = fat.stat("/readme.txt").unwrap();
+    assert_eq!(stat.size, 5);
+    assert_eq!(stat.modification_time, 1704164646);
+    assert_eq!(fat.stat("/docs").unwrap().kind, FileKind::Directory);
+    assert_eq!(fat.stat("/docs/notes.txt").unwrap().size, 0);
+}
+
+/// test that nothing can be changed, and that other volumes are not taken for FAT32
+#[test_case]
+fn test_fat_read_only() {
+    let mut fat = FatFs::new(test_volume()).unwrap();
+
+    assert_eq!(
+        fat.open("/readme.txt", FileFlags::Write as u8).err(),
+        Some(FsError::ReadOnly.into())
+    );
+    assert_eq!(fat.delete("/readme.txt"), Err(FsError::ReadOnly.into()));
+    assert_eq!(fat.mkdir("/new", [7, 5, 5]), Err(FsError::ReadOnly.into()));
+    assert_eq!(
+        fat.open("/docs", FileFlags::Read as u8).err(),
+        Some(FsError::IsADirectory.into())
+    );
+
+    assert_eq!(
+        FatFs::new(RamDisk::new(64 * BLOCK_SIZE as u64)).err(),
+        Some(FsError::InvalidSuperblock)
+    );
+}
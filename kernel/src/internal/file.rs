@@ -7,7 +7,10 @@ use core::{
 
 use alloc::{boxed::Box, string::String, vec::Vec};
 
-use super::{fs::FsError, syscall::Error};
+use super::{
+    fs::{FileStat, FsError},
+    syscall::Error,
+};
 
 /// FileInner is a struct that contains the error and an optional message
 #[derive(Debug, PartialEq)]
@@ -63,6 +66,14 @@ impl From<FsError> for FileError {
             FsError::FilesystemNotFound => FileError::NotFoundError(fs_error.into()),
             FsError::FilesystemExists => FileError::WriteError(fs_error.into()),
             FsError::InvalidFileDescriptor => FileError::PermissionError(fs_error.into()),
+            FsError::NotADirectory => FileError::NotFoundError(fs_error.into()),
+            FsError::IsADirectory => FileError::PermissionError(fs_error.into()),
+            FsError::DirectoryNotEmpty => FileError::WriteError(fs_error.into()),
+            FsError::NameTooLong => FileError::PermissionError(fs_error.into()),
+            FsError::TooManySymlinks => FileError::NotFoundError(fs_error.into()),
+            FsError::CrossDevice => FileError::WriteError(fs_error.into()),
+            FsError::Busy => FileError::WriteError(fs_error.into()),
+            FsError::ReadOnly => FileError::PermissionError(fs_error.into()),
         }
     }
 }
@@ -117,6 +128,14 @@ impl From<FsError> for Error {
             FsError::FilesystemNotFound => Error::ENOENT,
             FsError::FilesystemExists => Error::EEXIST,
             FsError::InvalidFileDescriptor => Error::EBADF,
+            FsError::NotADirectory => Error::ENOTDIR,
+            FsError::IsADirectory => Error::EISDIR,
+            FsError::DirectoryNotEmpty => Error::ENOTEMPTY,
+            FsError::NameTooLong => Error::ENAMETOOLONG,
+            FsError::TooManySymlinks => Error::ELOOP,
+            FsError::CrossDevice => Error::EXDEV,
+            FsError::Busy => Error::EBUSY,
+            FsError::ReadOnly => Error::EROFS,
         }
     }
 }
@@ -162,6 +181,11 @@ pub trait Stream {
 
     /// seek to a position in the file
     fn seek(&mut self, pos: usize) -> Result<usize, FileError>;
+
+    /// cut the file down or grow it to the given length - only regular files have a length to change, so anything else is an invalid argument
+    fn truncate(&mut self, _len: u64) -> Result<(), FileError> {
+        Err(FsError::InvalidPath.into())
+    }
 }
 
 /// FileFlags is an enum that contains the possible flags that can be set when opening a file
@@ -202,8 +226,11 @@ impl BitOr for FileFlags {
 
 /// FileSystem is a trait that contains the functions that need to be implemented when working with a filesystem
 pub trait FileSystem {
-    /// open a file
-    fn open(&mut self, path: &str, flags: u8) -> Result<Box<dyn Stream>, FileError>;
+    /// open a file, making it for root with rw-rw-rw- if the create flag is set and it does not exist yet
+    fn open(&mut self, path: &str, flags: u8) -> Result<Box<dyn Stream + Send>, FileError>;
+
+    /// make an empty file with the given permissions and owner
+    fn create(&mut self, path: &str, perms: [u8; 3], owner: u64) -> Result<(), FileError>;
 
     /// delete a file
     fn delete(&mut self, path: &str) -> Result<(), FileError>;
@@ -220,11 +247,47 @@ pub trait FileSystem {
     /// list the contents of a directory
     fn list(&mut self, path: &str) -> Result<Vec<String>, FileError>;
 
+    /// create a directory
+    fn mkdir(&mut self, path: &str, perms: [u8; 3]) -> Result<(), FileError>;
+
+    /// remove an empty directory
+    fn rmdir(&mut self, path: &str) -> Result<(), FileError>;
+
     /// get the owner of a file
     fn get_owner(&mut self, path: &str) -> Result<u64, FileError>;
 
     /// get the permissions of a file
     fn get_perms(&mut self, path: &str) -> Result<[u8; 3], FileError>;
+
+    /// get the size, owner, permissions and timestamps of a file
+    fn stat(&mut self, path: &str) -> Result<FileStat, FileError>;
+
+    /// move a file or directory to a new path, replacing a file (or empty directory) already there
+    fn rename(&mut self, old: &str, new: &str) -> Result<(), FileError>;
+
+    /// cut a file down or grow it to the given length in bytes, with any new space reading as zeroes
+    fn truncate(&mut self, path: &str, len: u64) -> Result<(), FileError>;
+
+    /// give an existing file a second name
+    fn link(&mut self, old: &str, new: &str) -> Result<(), FileError>;
+
+    /// make a symbolic link at path pointing at target
+    fn symlink(&mut self, target: &str, path: &str, owner: u64) -> Result<(), FileError>;
+
+    /// read where a symbolic link points
+    fn readlink(&mut self, path: &str) -> Result<String, FileError>;
+
+    /// write any changes still held in memory back to the disk
+    fn sync(&mut self) -> Result<(), FileError>;
+}
+
+/// split the permission bits of a mode into user, group and other
+pub fn perms(mode: u32) -> [u8; 3] {
+    [
+        ((mode >> 6) & 7) as u8,
+        ((mode >> 3) & 7) as u8,
+        (mode & 7) as u8,
+    ]
 }
 
 /// turn a relative path into an absolute path
@@ -66,14 +66,25 @@ lazy_static! {
 /// Segment selectors for the GDT
 pub struct Selectors {
     tss: SegmentSelector,
-    code: SegmentSelector,
-    data: SegmentSelector,
+    /// Kernel code segment
+    pub code: SegmentSelector,
+    /// Kernel data segment
+    pub data: SegmentSelector,
     /// User code segment
     pub user_code: SegmentSelector,
     /// User data segment
     pub user_data: SegmentSelector,
 }
 
+/// Set the stack the CPU switches to when an interrupt or syscall arrives from user mode
+pub fn set_kernel_stack(addr: VirtAddr) {
+    // the TSS is only read by the CPU, which picks up the new stack on the next switch to ring 0
+    unsafe {
+        let tss = addr_of!(*TSS) as *mut TaskStateSegment;
+        (*tss).privilege_stack_table[0] = addr;
+    }
+}
+
 /// Initialize the GDT and load it into the CPU
 pub fn init() {
     GDT.0.load();
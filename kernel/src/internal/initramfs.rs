@@ -0,0 +1,255 @@
+/*
+ * initramfs, a cpio archive built into the kernel image
+ * when the kernel is built with the initramfs feature, `make initramfs` packs the disk directory into a cpio (newc) archive, which is included in the kernel.
+ * at boot it is unpacked into a tmpfs that is mounted at /, so userspace can start without a second ATA drive. The drive, if there is one, is then mounted at /mnt.
+ */
+
+use alloc::{format, string::String, vec::Vec};
+
+use crate::internal::{
+    file::{perms, FileError, FileFlags, FileSystem},
+    fs::FsError,
+};
+
+#[cfg(feature = "initramfs")]
+use alloc::boxed::Box;
+#[cfg(feature = "initramfs")]
+use log::{info, warn};
+
+#[cfg(feature = "initramfs")]
+use crate::internal::{tmpfs::TMP_SIZE, vfs};
+
+#[cfg(any(test, feature = "initramfs"))]
+use crate::internal::tmpfs::TmpFs;
+
+#[cfg(test)]
+use crate::internal::fs::FileKind;
+
+/// the archive built by `make initramfs`
+#[cfg(feature = "initramfs")]
+static ARCHIVE: &[u8] = include_bytes!("../../initramfs.cpio");
+
+/// the size of a newc header: the magic number, then 13 fields of 8 hex digits
+const HEADER_LEN: usize = 110;
+
+/// the name of the entry that ends the archive
+const TRAILER: &str = "TRAILER!!!";
+
+/// the file type bits of a mode
+const S_IFMT: u32 = 0o170000;
+/// the file type bits of a directory
+const S_IFDIR: u32 = 0o040000;
+/// the file type bits of a regular file
+const S_IFREG: u32 = 0o100000;
+/// the file type bits of a symbolic link
+const S_IFLNK: u32 = 0o120000;
+
+/// an entry in a cpio archive
+struct Entry<'a> {
+    name: &'a str,
+    mode: u32,
+    uid: u64,
+    data: &'a [u8],
+}
+
+/// round an offset up to the next multiple of 4, which newc pads headers and file data to
+fn align(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// read one of the hex fields of a header
+fn field(header: &[u8], index: usize) -> Result<u32, FsError> {
+    let start = 6 + index * 8;
+    let digits =
+        core::str::from_utf8(&header[start..start + 8]).map_err(|_| FsError::InvalidMetadata)?;
+    u32::from_str_radix(digits, 16).map_err(|_| FsError::InvalidMetadata)
+}
+
+/// parse a newc archive into its entries, stopping at the trailer
+fn parse(archive: &[u8]) -> Result<Vec<Entry<'_>>, FsError> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let header = archive
+            .get(offset..offset + HEADER_LEN)
+            .ok_or(FsError::InvalidMetadata)?;
+        // 070702 is the same format with a checksum, which is not checked
+        if !matches!(&header[..6], b"070701" | b"070702") {
+            return Err(FsError::InvalidMetadata);
+        }
+
+        let mode = field(header, 1)?;
+        let uid = field(header, 2)? as u64;
+        let size = field(header, 6)? as usize;
+        let name_size = field(header, 11)? as usize;
+
+        // the name is null terminated, and padded along with the header
+        let name_start = offset + HEADER_LEN;
+        let name = archive
+            .get(name_start..name_start + name_size.saturating_sub(1))
+            .ok_or(FsError::InvalidMetadata)?;
+        let name = core::str::from_utf8(name).map_err(|_| FsError::InvalidMetadata)?;
+        if name == TRAILER {
+            return Ok(entries);
+        }
+
+        let data_start = align(name_start + name_size);
+        let data = archive
+            .get(data_start..data_start + size)
+            .ok_or(FsError::InvalidMetadata)?;
+        offset = align(data_start + size);
+
+        entries.push(Entry {
+            name,
+            mode,
+            uid,
+            data,
+        });
+    }
+}
+
+/// unpack a newc archive into a filesystem, returning how many entries were created
+///
+/// entries are created in the order they appear, so directories must come before what is in them, as `find` lists them
+pub fn unpack(archive: &[u8], fs: &mut dyn FileSystem) -> Result<usize, FileError> {
+    let mut count = 0;
+
+    for entry in parse(archive)? {
+        // names are relative to the root the archive was made from, and may start with ./
+        let name = entry.name.trim_start_matches("./").trim_matches('/');
+        if name.is_empty() || name == "." {
+            continue;
+        }
+        let path = format!("/{}", name);
+
+        // symbolic links are created with their owner, as chown would follow them
+        match entry.mode & S_IFMT {
+            S_IFDIR => {
+                match fs.mkdir(&path, perms(entry.mode)) {
+                    Ok(()) => {}
+                    // the directory may already be there, in which case it only takes the permissions
+                    Err(FileError::WriteError(_)) if fs.exists(&path) => {
+                        fs.chmod(&path, perms(entry.mode))?
+                    }
+                    Err(err) => return Err(err),
+                }
+                fs.chown(&path, entry.uid)?;
+            }
+            S_IFREG => {
+                let flags =
+                    FileFlags::Create as u8 | FileFlags::Write as u8 | FileFlags::Truncate as u8;
+                let mut file = fs.open(&path, flags)?;
+                file.write(entry.data)?;
+                file.close()?;
+                fs.chmod(&path, perms(entry.mode))?;
+                fs.chown(&path, entry.uid)?;
+            }
+            S_IFLNK => {
+                let target =
+                    String::from_utf8(entry.data.to_vec()).map_err(|_| FsError::InvalidMetadata)?;
+                fs.symlink(&target, &path, entry.uid)?;
+            }
+            // device nodes and pipes have no meaning here
+            _ => continue,
+        }
+
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// unpack the built-in archive and mount it at /, returning whether there was one to mount
+#[cfg(feature = "initramfs")]
+pub fn init() -> bool {
+    // the archive is read-only, but the root it becomes can grow as much as /tmp can
+    let mut root = TmpFs::new(ARCHIVE.len() as u64 + TMP_SIZE);
+
+    match unpack(ARCHIVE, &mut root) {
+        Ok(count) => info!("Unpacked {} files from the initramfs", count),
+        Err(err) => {
+            warn!("Failed to unpack the initramfs: {:?}", err);
+            return false;
+        }
+    }
+
+    if let Err(err) = vfs::mount("/", Box::new(root)) {
+        warn!("Failed to mount the initramfs: {:?}", err);
+        return false;
+    }
+
+    true
+}
+
+/// unpack the built-in archive and mount it at /, returning whether there was one to mount
+#[cfg(not(feature = "initramfs"))]
+pub fn init() -> bool {
+    false
+}
+
+/// build a newc entry, for testing
+#[cfg(test)]
+fn entry(name: &str, mode: u32, data: &[u8]) -> Vec<u8> {
+    let mut entry = format!(
+        "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+        0,
+        mode,
+        1000,
+        1000,
+        1,
+        0,
+        data.len(),
+        0,
+        0,
+        0,
+        0,
+        name.len() + 1,
+        0
+    )
+    .into_bytes();
+    entry.extend_from_slice(name.as_bytes());
+    entry.push(0);
+    entry.resize(align(entry.len()), 0);
+    entry.extend_from_slice(data);
+    entry.resize(align(entry.len()), 0);
+    entry
+}
+
+/// test unpacking an archive into a tmpfs
+#[test_case]
+fn test_unpack() {
+    let mut archive = Vec::new();
+    archive.extend(entry(".", S_IFDIR | 0o755, b""));
+    archive.extend(entry("./bin", S_IFDIR | 0o755, b""));
+    archive.extend(entry("./bin/init", S_IFREG | 0o750, b"\x7fELF"));
+    archive.extend(entry("./etc", S_IFDIR | 0o700, b""));
+    archive.extend(entry("./etc/motd", S_IFREG | 0o644, b"Hello!\n"));
+    archive.extend(entry("./sbin", S_IFLNK | 0o777, b"bin"));
+    archive.extend(entry(TRAILER, 0, b""));
+    // anything after the trailer is padding
+    archive.extend([0; 512]);
+
+    let mut fs = TmpFs::new(4096);
+    assert_eq!(unpack(&archive, &mut fs), Ok(5));
+
+    let init = fs.stat("/sbin/init").unwrap();
+    assert_eq!(init.size, 4);
+    assert_eq!(init.permissions, [7, 5, 0]);
+    assert_eq!(init.owner, 1000);
+    assert_eq!(fs.stat("/etc").unwrap().kind, FileKind::Directory);
+    assert_eq!(fs.get_perms("/etc"), Ok([7, 0, 0]));
+
+    let mut buf = [0; 7];
+    fs.open("/etc/motd", FileFlags::Read as u8)
+        .unwrap()
+        .read(&mut buf)
+        .unwrap();
+    assert_eq!(&buf, b"Hello!\n");
+
+    // a truncated archive is rejected rather than half unpacked
+    assert_eq!(
+        unpack(&archive[..200], &mut TmpFs::new(4096)),
+        Err(FsError::InvalidMetadata.into())
+    );
+}
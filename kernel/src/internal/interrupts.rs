@@ -3,7 +3,9 @@ use crate::internal::memory::physical_memory_offset;
 use crate::internal::{interrupts, syscall};
 use lazy_static::lazy_static;
 use log::{error, trace, warn};
+use x86_64::VirtAddr;
 use x86_64::registers::control::Cr2;
+use x86_64::registers::rflags::RFlags;
 use x86_64::structures::paging::OffsetPageTable;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, InterruptStackFrameValue};
 
@@ -26,12 +28,20 @@ lazy_static! {
 
             let f = wrapped_syscall_handler as *mut fn();
             idt[0x80].
-                set_handler_fn(core::mem::transmute(f)).
+                set_handler_fn(core::mem::transmute::<
+                    *mut fn(),
+                    extern "x86-interrupt" fn(InterruptStackFrame),
+                >(f)).
                 set_privilege_level(x86_64::PrivilegeLevel::Ring3);
+
+            // the timer is IRQ 0, and needs every register of the process it interrupts to switch to another
+            let f = wrapped_timer_interrupt_handler as *mut fn();
+            idt[InterruptIndex::Timer.as_u8()].set_handler_fn(core::mem::transmute::<
+                *mut fn(),
+                extern "x86-interrupt" fn(InterruptStackFrame),
+            >(f));
         }
-        idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
         idt.page_fault.set_handler_fn(page_fault_handler);
-        idt[PIC_1_OFFSET].set_handler_fn(irq0_handler);
         idt[PIC_1_OFFSET + 1].set_handler_fn(irq1_handler);
         idt[PIC_1_OFFSET + 2].set_handler_fn(irq2_handler);
         idt[PIC_1_OFFSET + 3].set_handler_fn(irq3_handler);
@@ -85,7 +95,6 @@ macro_rules! irq_handler {
     };
 }
 
-irq_handler!(irq0_handler, 0);
 irq_handler!(irq1_handler, 1);
 irq_handler!(irq2_handler, 2);
 irq_handler!(irq3_handler, 3);
@@ -106,7 +115,7 @@ use crate::hlt_loop;
 use x86_64::structures::idt::PageFaultErrorCode;
 
 extern "x86-interrupt" fn page_fault_handler(
-    _stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     let addr = Cr2::read().unwrap().as_u64();
@@ -116,6 +125,13 @@ extern "x86-interrupt" fn page_fault_handler(
         OffsetPageTable::new(page_table, physical_memory_offset())
     };
 
+    // a page shared with a forked process is copied on the first write, by the process or by the kernel for it
+    if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+        && crate::internal::memory::copy_on_write(&mut mapper, addr)
+    {
+        return;
+    }
+
     if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
         if crate::internal::memory::alloc_pages(&mut mapper, addr, 1).is_err() {
             if error_code.contains(PageFaultErrorCode::USER_MODE) {
@@ -124,7 +140,7 @@ extern "x86-interrupt" fn page_fault_handler(
                     addr,
                     error_code.bits()
                 );
-                crate::internal::process::exit();
+                exit_after_fault(&mut stack_frame);
             } else {
                 error!(
                     "Error: Could not allocate page at {:#X}\n",
@@ -144,6 +160,13 @@ extern "x86-interrupt" fn page_fault_handler(
                     core::ptr::copy_nonoverlapping(src, dst, 4096);
                 }
             }
+        } else {
+            warn!(
+                "User Mode Error (exiting): Page fault at {:#X} with error code {:#X}\n",
+                addr,
+                error_code.bits()
+            );
+            exit_after_fault(&mut stack_frame);
         }
     } else {
         panic!(
@@ -154,11 +177,53 @@ extern "x86-interrupt" fn page_fault_handler(
     }
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+/// make a process that faulted exit with PageFaultError, by returning from the fault into a call to EXIT in ring 0, on the process's kernel stack
+fn exit_after_fault(stack_frame: &mut InterruptStackFrame) {
+    let stack = process::kernel_stack_top().expect("user processes have a kernel stack");
+
+    let mut frame = **stack_frame;
+    frame.instruction_pointer = VirtAddr::new(exit_faulted_process as usize as u64);
+    frame.code_segment = gdt::GDT.1.code;
+    frame.cpu_flags = RFlags::empty(); // interrupts stay off, as in any syscall
+    // aligned as if the function had been called
+    frame.stack_pointer = VirtAddr::new((stack.as_u64() & !0xF) - 8);
+    frame.stack_segment = gdt::GDT.1.data;
+    unsafe {
+        let inner = stack_frame.as_mut().extract_inner();
+        let ptr = inner as *mut InterruptStackFrameValue;
+        core::ptr::write_volatile(ptr, frame);
+    }
+}
+
+/// where a process that faulted goes to exit, which switches to the next process and never returns
+extern "C" fn exit_faulted_process() -> ! {
+    crate::syscall!(syscall::EXIT, process::ExitCode::PageFaultError as usize);
+    unreachable!();
+}
+
+extern "sysv64" fn timer_interrupt_handler(
+    stack_frame: &mut InterruptStackFrame,
+    regs: &mut process::Registers,
+) {
+    IRQ_HANDLERS.lock()[0]();
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
     }
+
+    // a process in the middle of a syscall has state on its kernel stack, so only one running its own code is switched out
+    if stack_frame.code_segment.rpl() != x86_64::PrivilegeLevel::Ring3 {
+        return;
+    }
+
+    if let Some((sf, next_regs)) = process::schedule(**stack_frame, *regs) {
+        unsafe {
+            let inner = stack_frame.as_mut().extract_inner();
+            let ptr = inner as *mut InterruptStackFrameValue;
+            core::ptr::write_volatile(ptr, sf);
+            core::ptr::write_volatile(regs, next_regs);
+        }
+    }
 }
 
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
@@ -218,7 +283,7 @@ impl InterruptIndex {
     }
 }
 
-// Naked function wrapper saving all scratch registers to the stack
+// Naked function wrapper saving all general purpose registers to the stack, in the order of process::Registers
 // See: https://os.phil-opp.com/returning-from-exceptions/
 macro_rules! wrap {
     ($fn: ident => $w:ident) => {
@@ -227,26 +292,38 @@ macro_rules! wrap {
         pub unsafe extern "sysv64" fn $w() {
             unsafe {core::arch::naked_asm!(
                 "push rax",
+                "push rbx",
                 "push rcx",
                 "push rdx",
                 "push rsi",
                 "push rdi",
+                "push rbp",
                 "push r8",
                 "push r9",
                 "push r10",
                 "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
                 "mov rsi, rsp", // Arg #2: register list
                 "mov rdi, rsp", // Arg #1: interupt frame
-                "add rdi, 9 * 8", // 9 registers * 8 bytes
+                "add rdi, 15 * 8", // 15 registers * 8 bytes
                 "call {}",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
                 "pop r11",
                 "pop r10",
                 "pop r9",
                 "pop r8",
+                "pop rbp",
                 "pop rdi",
                 "pop rsi",
                 "pop rdx",
                 "pop rcx",
+                "pop rbx",
                 "pop rax",
                 "iretq",
                 sym $fn
@@ -256,6 +333,7 @@ macro_rules! wrap {
 }
 
 wrap!(syscall_handler => wrapped_syscall_handler);
+wrap!(timer_interrupt_handler => wrapped_timer_interrupt_handler);
 
 
 fn syscall_name(n: usize) -> &'static str {
@@ -285,6 +363,15 @@ fn syscall_name(n: usize) -> &'static str {
         syscall::BOOTTIME => "boot_time",
         syscall::TIME => "unix_time",
         syscall::SEEK => "seek",
+        syscall::STAT => "stat",
+        syscall::LINK => "link",
+        syscall::SYMLINK => "symlink",
+        syscall::READLINK => "readlink",
+        syscall::RENAME => "rename",
+        syscall::FTRUNCATE => "ftruncate",
+        syscall::UNLINK => "unlink",
+        syscall::MOUNT => "mount",
+        syscall::UMOUNT => "umount",
         _ => "<unknown>",
     }
 }
@@ -301,8 +388,11 @@ extern "sysv64" fn syscall_handler(
     let arg3 = regs.rdx;
     let arg4 = regs.r8;
 
-    // backup CPU context
-    if n == syscall::EXEC {
+    // a syscall that blocks is run again from the start, with the registers it was made with
+    let saved_regs = *regs;
+
+    // backup CPU context, which a forked child also starts from
+    if n == syscall::EXEC || n == syscall::FORK {
         process::set_stack_frame(**stack_frame);
         process::set_registers(*regs);
     }
@@ -329,6 +419,23 @@ extern "sysv64" fn syscall_handler(
             core::ptr::write_volatile(ptr, sf);
             core::ptr::write_volatile(regs, crate::internal::process::get_registers());
         }
+    } else if process::get_state() == process::State::Blocked {
+        let mut sf = **stack_frame;
+        let regs_to_save = if n == syscall::EXEC {
+            // EXEC is over once the child exits, which hands the parent its exit code
+            *regs
+        } else {
+            // step back over the `int 0x80` (2 bytes), so the process makes the syscall again once woken
+            sf.instruction_pointer -= 2u64;
+            saved_regs
+        };
+        let (sf, next_regs) = process::switch_from_blocked(sf, regs_to_save);
+        unsafe {
+            let inner = stack_frame.as_mut().extract_inner();
+            let ptr = inner as *mut InterruptStackFrameValue;
+            core::ptr::write_volatile(ptr, sf);
+            core::ptr::write_volatile(regs, next_regs);
+        }
     }
 
     unsafe { PICS.lock().notify_end_of_interrupt(0x80) };
@@ -11,7 +11,7 @@ use crate::{internal::{
     file::Stream,
 }, kprint};
 
-use super::{console::Console, devices::{null::Null, proc::ProcInfo}, fs::FileHandle};
+use super::{console::Console, devices::null::Null, vfs::OpenFile};
 
 /// File table
 pub static FILES: Mutex<BTreeMap<usize, File>> = Mutex::new(BTreeMap::new());
@@ -125,6 +125,9 @@ pub const ZERO: u8 = 4;
 /// random device number and file descriptor
 pub const RAND: u8 = 5;
 
+/// the name of each device under /dev, indexed by device number
+pub const DEVICE_NAMES: [&str; NUM_DEVICES] = ["stdin", "stdout", "stderr", "null", "zero", "random"];
+
 /// (device number, flags)
 impl TryFrom<(u8, u8)> for Device {
     type Error = String;
@@ -142,15 +145,13 @@ impl TryFrom<(u8, u8)> for Device {
     }
 }
 
-/// a file, which could be a normal file, a stream, or a device
+/// a file, which could be anything opened through the mount table, or a device
 #[derive(Debug, Clone)]
 pub enum File {
-    /// A normal file
-    File(FileHandle),
+    /// A file opened through the mount table
+    File(OpenFile),
     /// A device
     Device(Device),
-    /// proc info
-    ProcInfo(ProcInfo),
 }
 
 impl Stream for Device {
@@ -226,7 +227,6 @@ impl Stream for File {
         match self {
             File::File(file) => file.read(buf),
             File::Device(device) => device.read(buf),
-            File::ProcInfo(proc_info) => proc_info.read(buf),
         }
     }
 
@@ -234,7 +234,6 @@ impl Stream for File {
         match self {
             File::File(file) => file.write(buf),
             File::Device(device) => device.write(buf),
-            File::ProcInfo(proc_info) => proc_info.write(buf),
         }
     }
 
@@ -242,7 +241,6 @@ impl Stream for File {
         match self {
             File::File(file) => file.close(),
             File::Device(device) => device.close(),
-            File::ProcInfo(proc_info) => proc_info.close(),
         }
     }
 
@@ -250,7 +248,6 @@ impl Stream for File {
         match self {
             File::File(file) => file.flush(),
             File::Device(device) => device.flush(),
-            File::ProcInfo(proc_info) => proc_info.flush(),
         }
     }
 
@@ -258,7 +255,6 @@ impl Stream for File {
         match self {
             File::File(file) => file.poll(event),
             File::Device(device) => device.poll(event),
-            File::ProcInfo(proc_info) => proc_info.poll(event),
         }
     }
 
@@ -266,7 +262,13 @@ impl Stream for File {
         match self {
             File::File(file) => file.seek(pos),
             File::Device(device) => device.seek(pos),
-            File::ProcInfo(proc_info) => proc_info.seek(pos),
+        }
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), super::file::FileError> {
+        match self {
+            File::File(file) => file.truncate(len),
+            File::Device(device) => device.truncate(len),
         }
     }
 }
@@ -1,7 +1,11 @@
+use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::btree_set::BTreeSet;
+use alloc::vec::Vec;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 use log::warn;
-use spin::Once;
+use spin::{Mutex, Once};
+use x86_64::structures::paging::mapper::{MappedFrame, Translate, TranslateResult};
 use x86_64::structures::paging::page_table::FrameError;
 use x86_64::structures::paging::{Mapper, OffsetPageTable, PageTable, PageTableFlags};
 use x86_64::{PhysAddr, VirtAddr};
@@ -10,6 +14,15 @@ use x86_64::{PhysAddr, VirtAddr};
 static PHYSICAL_MEMORY_OFFSET: Once<u64> = Once::new(); // will get overwritten by init
 static MEMORY_MAP: Once<&'static bootloader::bootinfo::MemoryMap> = Once::new(); // will get overwritten by init
 static ALLOCATED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+/// the last frame given back, which holds the address of the one given back before it in its first 8 bytes (0 for none)
+static FREE_FRAMES: Mutex<Option<PhysFrame>> = Mutex::new(None);
+/// the frames shared between forked processes, with the number of page tables mapping each
+static SHARED_FRAMES: Mutex<BTreeMap<PhysFrame, usize>> = Mutex::new(BTreeMap::new());
+/// the page table the kernel booted with, whose tables every process shares outside of its own ranges
+static KERNEL_PAGE_TABLE: Once<PhysFrame> = Once::new();
+
+/// marks a page shared read-only between forked processes, which is copied by the first write to it
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
 
 /// fetch the physical memory offset )
 pub fn physical_memory_offset() -> VirtAddr {
@@ -91,7 +104,7 @@ unsafe fn init_page_table(physical_memory_offset: VirtAddr) -> OffsetPageTable<'
     unsafe { OffsetPageTable::new(level_4_table, physical_memory_offset) }
 }
 
-use x86_64::structures::paging::{FrameAllocator, Page, PhysFrame, Size4KiB};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Page, PhysFrame, Size4KiB};
 
 /// Create a mapping in the page table that maps the given page to the given frame
 pub fn create_example_mapping(
@@ -135,6 +148,17 @@ impl BootInfoFrameAllocator {
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        // frames that were given back are used first, wiped of whatever their last owner left in them
+        let mut free = FREE_FRAMES.lock();
+        if let Some(frame) = *free {
+            let ptr = reverse_translate(frame.start_address()).as_mut_ptr::<u64>();
+            let next = unsafe { ptr.read() };
+            *free = (next != 0).then(|| PhysFrame::containing_address(PhysAddr::new(next)));
+            unsafe { core::ptr::write_bytes(ptr as *mut u8, 0, 4096) };
+            return Some(frame);
+        }
+        drop(free);
+
         let next = ALLOCATED_FRAMES.fetch_add(1, Ordering::SeqCst);
         // FIXME: When the heap is larger than a few megabytes,
         // creating an iterator for each allocation become very slow.
@@ -142,6 +166,15 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     }
 }
 
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let mut free = FREE_FRAMES.lock();
+        let next = free.map_or(0, |next| next.start_address().as_u64());
+        unsafe { reverse_translate(frame.start_address()).as_mut_ptr::<u64>().write(next) };
+        *free = Some(frame);
+    }
+}
+
 /// Initialize the memory system
 pub fn init(boot_info: &'static bootloader::bootinfo::BootInfo) {
     crate::internal::vga::trace("Initializing memory");
@@ -150,6 +183,7 @@ pub fn init(boot_info: &'static bootloader::bootinfo::BootInfo) {
     let mut frame_allocator = BootInfoFrameAllocator::init(&boot_info.memory_map);
 
     MEMORY_MAP.call_once(|| &boot_info.memory_map);
+    KERNEL_PAGE_TABLE.call_once(|| x86_64::registers::control::Cr3::read().0);
 
     crate::internal::allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("heap initialization failed");
@@ -204,7 +238,7 @@ pub fn alloc_pages(mapper: &mut OffsetPageTable, addr: u64, size: usize) -> Resu
     Ok(())
 }
 
-/// Free a range of pages
+/// Free a range of pages, giving back the frames no other page table maps
 pub fn free_pages(mapper: &mut OffsetPageTable, addr: u64, size: usize) {
     let size = size.saturating_sub(1) as u64;
 
@@ -215,9 +249,318 @@ pub fn free_pages(mapper: &mut OffsetPageTable, addr: u64, size: usize) {
     };
 
     for page in pages {
-        let res =  mapper.unmap(page);
-        if let Err(_err) = res {
-            // we don't warn here because the process exiter will unmap all MAX_PROC_SIZE
+        match mapper.unmap(page) {
+            Ok((frame, flush)) => {
+                flush.flush();
+                release_frame(frame);
+            }
+            Err(_err) => {
+                // we don't warn here because the process exiter will unmap all MAX_PROC_SIZE
+            }
         }
     }
 }
+
+/// give back a frame that a page table has stopped mapping, unless it is still shared with another page table
+fn release_frame(frame: PhysFrame) {
+    let mut shared = SHARED_FRAMES.lock();
+    match shared.get(&frame).copied() {
+        Some(count) if count > 1 => {
+            shared.insert(frame, count - 1);
+        }
+        _ => {
+            shared.remove(&frame);
+            unsafe { frame_allocator().deallocate_frame(frame) };
+        }
+    }
+}
+
+/// copy a page table into a new frame
+fn copy_table(frame: PhysFrame) -> Option<PhysFrame> {
+    let copy = frame_allocator().allocate_frame()?;
+    *create_page_table(copy) = create_page_table(frame).clone();
+    Some(copy)
+}
+
+/// give a page table its own copies of the tables on the way to a range, with nothing mapped in the range, so what is mapped there later is only seen through this page table
+///
+/// the range must be made of whole 2 MB blocks, the size mapped by a level 2 entry
+pub fn unshare_range(frame: PhysFrame, addr: u64, size: usize) -> Option<()> {
+    let mut copies = Vec::new();
+
+    'blocks: for block in (addr..addr + size as u64).step_by(512 * 4096) {
+        let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(block));
+        let mut table = create_page_table(frame);
+        for index in [page.p4_index(), page.p3_index()] {
+            let entry = &mut table[index];
+            let Ok(next) = entry.frame() else {
+                // nothing is mapped here yet, and whatever is mapped later goes in tables only this one leads to
+                continue 'blocks;
+            };
+            if !copies.contains(&next) {
+                let copy = copy_table(next)?;
+                entry.set_frame(copy, entry.flags());
+                copies.push(copy);
+            }
+            table = create_page_table(entry.frame().ok()?);
+        }
+
+        table[page.p2_index()].set_unused();
+    }
+
+    Some(())
+}
+
+/// copy the page table of a process for a child forked from it
+///
+/// the tables mapping the given ranges are copied too, so the two can map them differently, and the writable pages in them are shared by both as copy-on-write
+pub fn fork_page_table(frame: PhysFrame, ranges: &[(u64, usize)]) -> Option<PhysFrame> {
+    let child = copy_table(frame)?;
+
+    for (shared, page) in range_pages(ranges).enumerate() {
+        if share_page(frame, child, page).is_none() {
+            undo_fork(frame, child, ranges, shared);
+            return None;
+        }
+    }
+
+    // the parent's pages have just been made read-only
+    x86_64::instructions::tlb::flush_all();
+    Some(child)
+}
+
+/// the pages in the given ranges, in order
+fn range_pages(ranges: &[(u64, usize)]) -> impl Iterator<Item = Page> + '_ {
+    ranges.iter().flat_map(|&(addr, size)| {
+        let start_page = Page::containing_address(VirtAddr::new(addr));
+        let end = addr + size.saturating_sub(1) as u64;
+        let end_page = Page::containing_address(VirtAddr::new(end));
+        Page::range_inclusive(start_page, end_page)
+    })
+}
+
+/// undo a fork that ran out of frames after sharing the given number of pages, giving back the child's page table and letting the parent write again to the pages it no longer shares
+fn undo_fork(parent: PhysFrame, child: PhysFrame, ranges: &[(u64, usize)], shared: usize) {
+    let mapper = |frame| unsafe {
+        OffsetPageTable::new(create_page_table(frame), physical_memory_offset())
+    };
+    let (mut parent_mapper, mut child_mapper) = (mapper(parent), mapper(child));
+
+    // the pages after these may still be mapped through tables copied with them, but were never counted as shared
+    for page in range_pages(ranges).take(shared) {
+        if let Ok((frame, flush)) = child_mapper.unmap(page) {
+            flush.ignore();
+            release_frame(frame);
+        }
+    }
+    // whatever the child had not copied yet is still the parent's
+    free_own_tables(child, parent, ranges);
+
+    for page in range_pages(ranges).take(shared) {
+        let Ok(frame) = parent_mapper.translate_page(page) else {
+            continue;
+        };
+        // the parent takes back the pages it is the last to map, as its next write to them would
+        if SHARED_FRAMES.lock().get(&frame) == Some(&1) {
+            copy_on_write(&mut parent_mapper, page.start_address().as_u64());
+        }
+    }
+    x86_64::instructions::tlb::flush_all();
+}
+
+/// give back a page table and the tables of its own on the way to the given ranges, once nothing is mapped in them
+///
+/// the tables it shares with the kernel's page table are left alone
+pub fn free_page_tables(frame: PhysFrame, ranges: &[(u64, usize)]) {
+    let kernel = *KERNEL_PAGE_TABLE.get().expect("memory is initialized");
+    free_own_tables(frame, kernel, ranges);
+}
+
+/// give back a page table and the tables on the way to the given ranges that another page table does not also lead to
+fn free_own_tables(frame: PhysFrame, other: PhysFrame, ranges: &[(u64, usize)]) {
+    let mut own = BTreeSet::from([frame]);
+
+    for &(addr, size) in ranges {
+        let block_size = 512 * 4096;
+        let start = addr - addr % block_size;
+        for block in (start..addr + size as u64).step_by(block_size as usize) {
+            let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(block));
+            let (mut table, mut other) = (frame, Some(other));
+            for index in [page.p4_index(), page.p3_index(), page.p2_index()] {
+                let Ok(next) = create_page_table(table)[index].frame() else {
+                    break;
+                };
+                let other_next = other.and_then(|other| create_page_table(other)[index].frame().ok());
+                if other_next == Some(next) {
+                    // shared, and so is everything below it
+                    break;
+                }
+                own.insert(next);
+                (table, other) = (next, other_next);
+            }
+        }
+    }
+
+    let mut frame_allocator = frame_allocator();
+    for frame in own {
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    }
+}
+
+/// share a page between a page table and a copy of it, copying the tables on the way to it that the two still share
+fn share_page(parent: PhysFrame, child: PhysFrame, page: Page) -> Option<()> {
+    let mut parent = create_page_table(parent);
+    let mut child = create_page_table(child);
+
+    for index in [page.p4_index(), page.p3_index(), page.p2_index()] {
+        let entry = &parent[index];
+        let Ok(frame) = entry.frame() else {
+            // nothing is mapped here (processes are never given huge pages), and whatever is mapped later goes in the tables of whoever maps it
+            return Some(());
+        };
+        if child[index].addr() == entry.addr() {
+            child[index].set_frame(copy_table(frame)?, entry.flags());
+        }
+        let child_frame = child[index].frame().ok()?;
+        parent = create_page_table(frame);
+        child = create_page_table(child_frame);
+    }
+
+    let entry = &mut parent[page.p1_index()];
+    let flags = entry.flags();
+    let Ok(frame) = entry.frame() else {
+        return Some(());
+    };
+    // counted whether or not it is writable, so the frame is only given back once neither maps it
+    *SHARED_FRAMES.lock().entry(frame).or_insert(1) += 1;
+    if !flags.intersects(PageTableFlags::WRITABLE | COPY_ON_WRITE) {
+        // read-only pages can be shared as they are
+        return Some(());
+    }
+
+    let flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+    entry.set_flags(flags);
+    child[page.p1_index()].set_flags(flags);
+    Some(())
+}
+
+/// give a process its own copy of a copy-on-write page it has written to, returning false if the page is not copy-on-write
+pub fn copy_on_write(mapper: &mut OffsetPageTable, addr: u64) -> bool {
+    let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(addr));
+    let TranslateResult::Mapped {
+        frame: MappedFrame::Size4KiB(frame),
+        flags,
+        ..
+    } = mapper.translate(page.start_address())
+    else {
+        return false;
+    };
+    if !flags.contains(COPY_ON_WRITE) {
+        return false;
+    }
+
+    let flags = (flags | PageTableFlags::WRITABLE) - COPY_ON_WRITE;
+    let mut shared = SHARED_FRAMES.lock();
+    match shared.get(&frame).copied() {
+        Some(count) if count > 1 => {
+            let mut frame_allocator = frame_allocator();
+            let Some(copy) = frame_allocator.allocate_frame() else {
+                return false;
+            };
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    reverse_translate(frame.start_address()).as_ptr::<u8>(),
+                    reverse_translate(copy.start_address()).as_mut_ptr::<u8>(),
+                    4096,
+                );
+            }
+            shared.insert(frame, count - 1);
+
+            let Ok((_, flush)) = mapper.unmap(page) else {
+                return false;
+            };
+            flush.flush();
+            match unsafe { mapper.map_to(page, copy, flags, &mut frame_allocator) } {
+                Ok(flush) => flush.flush(),
+                Err(_) => return false,
+            }
+        }
+        // the last process sharing the page just takes it back
+        _ => {
+            shared.remove(&frame);
+            match unsafe { mapper.update_flags(page, flags) } {
+                Ok(flush) => flush.flush(),
+                Err(_) => return false,
+            }
+        }
+    }
+    true
+}
+
+/// test that the frames of a forked page are only given back once both page tables have let go of them, and are wiped before being used again
+#[test_case]
+fn test_free_shared_pages() {
+    // somewhere the kernel maps nothing
+    let addr = 0x5555_0000_0000;
+    let parent = copy_table(x86_64::registers::control::Cr3::read().0).unwrap();
+    let mapper = |frame| unsafe {
+        OffsetPageTable::new(create_page_table(frame), physical_memory_offset())
+    };
+
+    let mut parent_mapper = mapper(parent);
+    alloc_pages(&mut parent_mapper, addr, 4096).unwrap();
+    let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(addr));
+    let frame = parent_mapper.translate_page(page).unwrap();
+    let data = reverse_translate(frame.start_address()).as_mut_ptr::<u8>();
+    unsafe { data.write_bytes(0xAA, 4096) };
+
+    let child = fork_page_table(parent, &[(addr, 4096)]).unwrap();
+    assert_eq!(SHARED_FRAMES.lock().get(&frame), Some(&2));
+
+    free_pages(&mut mapper(child), addr, 4096);
+    assert_eq!(SHARED_FRAMES.lock().get(&frame), Some(&1));
+    assert_ne!(*FREE_FRAMES.lock(), Some(frame));
+
+    free_pages(&mut parent_mapper, addr, 4096);
+    assert!(!SHARED_FRAMES.lock().contains_key(&frame));
+    assert_eq!(*FREE_FRAMES.lock(), Some(frame));
+
+    let mut frame_allocator = frame_allocator();
+    assert_eq!(frame_allocator.allocate_frame(), Some(frame));
+    let data = unsafe { core::slice::from_raw_parts(data, 4096) };
+    assert!(data.iter().all(|&byte| byte == 0));
+    unsafe { frame_allocator.deallocate_frame(frame) };
+}
+
+/// test that a fork undone part way gives back only what the child took, and lets the parent write to its pages again
+#[test_case]
+fn test_undo_fork() {
+    // somewhere the kernel maps nothing, away from test_free_shared_pages
+    let addr = 0x5556_0000_0000;
+    let ranges = [(addr, 2 * 4096)];
+    let parent = copy_table(x86_64::registers::control::Cr3::read().0).unwrap();
+    let mut parent_mapper = unsafe {
+        OffsetPageTable::new(create_page_table(parent), physical_memory_offset())
+    };
+    alloc_pages(&mut parent_mapper, addr, 2 * 4096).unwrap();
+    let pages: Vec<Page<Size4KiB>> = range_pages(&ranges).collect();
+    let frames: Vec<PhysFrame> = pages.iter().map(|&page| parent_mapper.translate_page(page).unwrap()).collect();
+
+    // the fork runs out of frames after the first page
+    let child = copy_table(parent).unwrap();
+    share_page(parent, child, pages[0]).unwrap();
+    undo_fork(parent, child, &ranges, 1);
+
+    for (&page, frame) in pages.iter().zip(frames) {
+        assert_eq!(parent_mapper.translate_page(page).ok(), Some(frame));
+        assert!(!SHARED_FRAMES.lock().contains_key(&frame));
+        let TranslateResult::Mapped { flags, .. } = parent_mapper.translate(page.start_address()) else {
+            panic!("the parent lost its page");
+        };
+        assert!(flags.contains(PageTableFlags::WRITABLE));
+        assert!(!flags.contains(COPY_ON_WRITE));
+    }
+
+    free_pages(&mut parent_mapper, addr, 2 * 4096);
+    free_page_tables(parent, &ranges);
+}
@@ -6,18 +6,26 @@ pub mod acpi;
 pub mod allocator;
 /// ata module, handles ata devices
 pub mod ata;
+/// blockdev module, handles the block devices filesystems are loaded from (drives, RAM disks and loopback files)
+pub mod blockdev;
 /// clk module, handles clock and related interrupts
 pub mod clk;
 /// console module, handles console input
 pub mod console;
 /// devices module, handles devices
 pub mod devices;
+/// ext2 module, handles ext2 volumes
+pub mod ext2;
+/// fat module, handles read-only FAT32 volumes
+pub mod fat;
 /// file module, handles file types and trait definitions
 pub mod file;
 /// fs module, handles file system operations
 pub mod fs;
 /// gdt module, handles global descriptor table
 pub mod gdt;
+/// initramfs module, unpacks the archive built into the kernel into the root filesystem
+pub mod initramfs;
 /// interrupts module, handles interrupt handling
 pub mod interrupts;
 /// io module, handles io operations
@@ -34,6 +42,10 @@ pub mod serial;
 pub mod syscall;
 /// task module, handles task scheduling and execution
 pub mod task;
+/// tmpfs module, handles filesystems held in memory
+pub mod tmpfs;
+/// vfs module, handles the mount table and routes paths to mounted filesystems
+pub mod vfs;
 /// vga module, handles vga output
 pub mod vga;
 /// user handling module
@@ -1,18 +1,21 @@
 use alloc::boxed::Box;
+use alloc::collections::VecDeque;
 use alloc::collections::btree_map::BTreeMap;
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::alloc::{GlobalAlloc, Layout};
-use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::ops::{Index, IndexMut};
 use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use lazy_static::lazy_static;
 use linked_list_allocator::LockedHeap;
 use log::{debug, warn};
 use object::{Object, ObjectSegment};
-use spin::RwLock;
+use spin::{Mutex, RwLock};
 use x86_64::VirtAddr;
 use x86_64::registers::control::Cr3;
+use x86_64::registers::rflags::RFlags;
 use x86_64::structures::idt::InterruptStackFrameValue;
 use x86_64::structures::paging::{
     FrameAllocator, OffsetPageTable, PageTable, PageTableFlags, PhysFrame, Translate,
@@ -23,13 +26,16 @@ use super::console::Console;
 use super::devices::null::Null;
 use super::file::FileFlags;
 use super::io::{Device, File, Stderr, Stdout};
+use super::syscall::Error;
 
 const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
 const BIN_MAGIC: [u8; 4] = [0x7F, b'B', b'I', b'N'];
 
 const MAX_HANDLES: usize = 64;
-const MAX_PROCS: usize = 4; // TODO: Increase this
+const DEFAULT_MAX_PROCS: usize = 64;
+const DEFAULT_MAX_PID: usize = 32768;
 const MAX_PROC_SIZE: usize = 10 << 20; // 10 MB
+const KERNEL_STACK_SIZE: usize = 64 << 10; // 64 KB
 
 // TODO: Remove this when the kernel is no longer at 0x200000 in userspace.
 // Currently this address must be used by the linker for user programs that
@@ -39,36 +45,194 @@ static USER_ADDR: u64 = 0x800000;
 static CODE_ADDR: AtomicU64 = AtomicU64::new(0);
 /// The current process ID
 pub static PID: AtomicUsize = AtomicUsize::new(0);
-/// The maximum process ID
-pub static MAX_PID: AtomicUsize = AtomicUsize::new(1);
+/// The most processes that can exist at once, besides the kernel, which root can change by writing to /proc/sys/kernel/max_procs
+pub static MAX_PROCS: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_PROCS);
+/// The highest process ID handed out before going back round to the lowest, which root can change by writing to /proc/sys/kernel/pid_max
+pub static MAX_PID: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_PID);
+/// The process ID given to the next process, if it is free
+static NEXT_PID: AtomicUsize = AtomicUsize::new(1);
+/// The kernel stack of the process that exited last, which the kernel is still running on until it switches to the next process
+static EXITED_STACK: Mutex<Option<Arc<KernelStack>>> = Mutex::new(None);
 
 lazy_static! {
-    /// The process table
-    pub static ref PROCESS_TABLE: RwLock<[Box<Process>; MAX_PROCS]> = RwLock::new([(); MAX_PROCS].map(|_| Box::new(Process::new())));
+    /// The process table, which starts with just the kernel
+    pub static ref PROCESS_TABLE: RwLock<ProcessTable> = RwLock::new(ProcessTable::new());
+    /// The processes waiting for their turn on the CPU, in the order they get it
+    pub static ref RUN_QUEUE: Mutex<VecDeque<usize>> = Mutex::new(VecDeque::new());
 }
 
+/// The processes waiting for one of their children to exit, woken by every exit so each can check its own
+pub static CHILD_EXITS: WaitQueue = WaitQueue::new();
+
 /// Called during kernel heap initialization
 pub fn init_process_addr(addr: u64) {
     CODE_ADDR.store(addr, Ordering::SeqCst);
 }
 
-/// represents the registers saved by the kernel
+/// represents the registers saved by the kernel, in the order the interrupt wrappers push them
 #[repr(align(8), C)]
 #[derive(Debug, Clone, Copy, Default)]
 #[allow(missing_docs)] // not needed
 pub struct Registers {
+    // Saved preserved registers, which a process switch would otherwise clobber
+    pub r15: usize,
+    pub r14: usize,
+    pub r13: usize,
+    pub r12: usize,
     // Saved scratch registers
     pub r11: usize,
     pub r10: usize,
     pub r9: usize,
     pub r8: usize,
+    pub rbp: usize,
     pub rdi: usize,
     pub rsi: usize,
     pub rdx: usize,
     pub rcx: usize,
+    pub rbx: usize,
     pub rax: usize,
 }
 
+/// what a process is doing
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    /// on the CPU
+    Running,
+    /// in the run queue, waiting for its turn on the CPU
+    Ready,
+    /// waiting for something to happen, such as a key being typed or a child exiting
+    Blocked,
+    /// exited, and waiting for its parent to collect its exit code
+    Zombie,
+}
+
+/*
+ * wait queues, the processes blocked until something happens
+ * a blocking syscall parks the process on a queue and returns as usual, then the syscall handler switches to the next process ready to run.
+ * when woken, the process goes back in the run queue and runs the syscall again from the start, so the syscall checks for what it was waiting on itself.
+ * queues are woken from interrupt handlers, which only ever find processes in them while every process is in a syscall or in its own code, so no kernel code is holding the locks below.
+ * the locks are always taken in the order PROCESS_TABLE, then RUN_QUEUE.
+ */
+
+/// a queue of processes blocked until it is woken
+#[derive(Debug, Default)]
+pub struct WaitQueue(Mutex<VecDeque<usize>>);
+
+impl WaitQueue {
+    /// create an empty wait queue
+    pub const fn new() -> Self {
+        WaitQueue(Mutex::new(VecDeque::new()))
+    }
+
+    /// block the current process until the queue is woken, returning whether it was parked
+    ///
+    /// the kernel is never parked, and has to wait by itself
+    pub fn wait(&self) -> bool {
+        if !block() {
+            return false;
+        }
+        self.0.lock().push_back(pid());
+        true
+    }
+
+    /// wake every process in the queue
+    pub fn wake_all(&self) {
+        while let Some(id) = self.0.lock().pop_front() {
+            wake(id);
+        }
+    }
+}
+
+/// the processes that exist, keyed by process ID
+pub struct ProcessTable(BTreeMap<usize, Box<Process>>);
+
+impl ProcessTable {
+    fn new() -> Self {
+        ProcessTable(BTreeMap::from([(0, Box::new(Process::new()))]))
+    }
+
+    /// get a process
+    pub fn get(&self, id: usize) -> Option<&Process> {
+        self.0.get(&id).map(|proc| &**proc)
+    }
+
+    fn get_mut(&mut self, id: usize) -> Option<&mut Process> {
+        self.0.get_mut(&id).map(|proc| &mut **proc)
+    }
+
+    /// the IDs of every process, in order
+    pub fn pids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.keys().copied()
+    }
+
+    fn insert(&mut self, proc: Process) {
+        self.0.insert(proc.pid, Box::new(proc));
+    }
+
+    fn remove(&mut self, id: usize) -> Option<Box<Process>> {
+        self.0.remove(&id)
+    }
+
+    /// hand out an ID for a new process, unless there are already MAX_PROCS of them
+    fn alloc_pid(&self) -> Option<usize> {
+        if self.0.len() > MAX_PROCS.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        let mut next = NEXT_PID.load(Ordering::SeqCst);
+        let id = next_free_pid(&mut next, MAX_PID.load(Ordering::SeqCst), |id| {
+            self.0.contains_key(&id)
+        });
+        NEXT_PID.store(next, Ordering::SeqCst);
+        id
+    }
+}
+
+impl Index<usize> for ProcessTable {
+    type Output = Process;
+
+    fn index(&self, id: usize) -> &Process {
+        self.get(id).expect("no such process")
+    }
+}
+
+impl IndexMut<usize> for ProcessTable {
+    fn index_mut(&mut self, id: usize) -> &mut Process {
+        self.get_mut(id).expect("no such process")
+    }
+}
+
+/// take the first free process ID from next on, going back round to 2 after max_pid, so an ID is only reused once all the others have been handed out
+///
+/// 0 is the kernel, and 1 the first process, init
+fn next_free_pid(next: &mut usize, max_pid: usize, in_use: impl Fn(usize) -> bool) -> Option<usize> {
+    for _ in 0..max_pid {
+        let id = *next;
+        *next = if id >= max_pid { 2 } else { id + 1 };
+        if id <= max_pid && !in_use(id) {
+            return Some(id);
+        }
+    }
+    None
+}
+
+/// The stack the kernel runs on while a process is in a syscall or interrupted
+struct KernelStack(Box<[UnsafeCell<u8>]>);
+
+// the stack is only ever written by the CPU and the kernel code running on it, never through this value
+unsafe impl Sync for KernelStack {}
+
+impl KernelStack {
+    fn new() -> Self {
+        Self((0..KERNEL_STACK_SIZE).map(|_| UnsafeCell::new(0)).collect())
+    }
+
+    /// the address just past the end of the stack, as it grows down
+    fn top(&self) -> VirtAddr {
+        VirtAddr::from_ptr(self.0.as_ptr_range().end)
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(u8)]
 #[allow(missing_docs)] // not needed
@@ -299,20 +463,231 @@ pub fn is_userspace(addr: u64) -> bool {
     USER_ADDR <= addr && addr <= USER_ADDR + MAX_PROC_SIZE as u64
 }
 
-/// wrap up and exit the current process, surrendering control to the parent
-pub fn exit() {
+/// get the state of the current process
+pub fn get_state() -> State {
     let table = PROCESS_TABLE.read();
     let proc = &table[pid()];
+    proc.state
+}
+
+fn set_state(id: usize, state: State) {
+    let mut table = PROCESS_TABLE.write();
+    table[id].state = state;
+}
+
+/// get the PIT tick the current process is sleeping until
+pub fn get_wake_at() -> Option<usize> {
+    let table = PROCESS_TABLE.read();
+    let proc = &table[pid()];
+    proc.wake_at
+}
+
+/// set the PIT tick the current process is sleeping until, kept between the runs of its SLEEP syscall
+pub fn set_wake_at(tick: Option<usize>) {
+    let mut table = PROCESS_TABLE.write();
+    let proc = &mut table[pid()];
+    proc.wake_at = tick;
+}
+
+/// block the current process, so it is switched out once its syscall returns, and runs the syscall again when woken
+///
+/// returns false for the kernel, which has no syscall to run again and is never switched out
+pub fn block() -> bool {
+    if pid() == 0 {
+        return false;
+    }
+    set_state(pid(), State::Blocked);
+    true
+}
+
+/// put a blocked process back in the run queue
+pub fn wake(id: usize) {
+    let mut table = PROCESS_TABLE.write();
+    if table.get(id).is_some_and(|proc| proc.state == State::Blocked) {
+        enqueue(&mut table, id);
+    }
+}
+
+/// make a process ready to run, at the back of the run queue
+fn enqueue(table: &mut ProcessTable, id: usize) {
+    table[id].state = State::Ready;
+    RUN_QUEUE.lock().push_back(id);
+}
+
+/// get the top of the kernel stack of the current process, which the kernel itself does not have
+pub fn kernel_stack_top() -> Option<VirtAddr> {
+    let table = PROCESS_TABLE.read();
+    let proc = &table[pid()];
+    proc.kernel_stack.as_ref().map(|stack| stack.top())
+}
+
+/// collect the exit code of a child of the current process that has exited, or of the given child, removing it from the process table
+///
+/// returns None if no child has exited yet, and ECHILD if there is no child to wait for
+pub fn reap_child(target: Option<usize>) -> Result<Option<(usize, ExitCode)>, Error> {
+    let parent = pid();
+    let mut table = PROCESS_TABLE.write();
+
+    let mut found = false;
+    let mut zombie = None;
+
+    for proc in table.0.values() {
+        // children started by EXEC are collected by the EXEC itself, and the orphans the kernel takes in as they exit
+        if proc.pid == 0
+            || proc.ppid != parent
+            || proc.resumes_parent
+            || proc.ppid == 0
+            || target.is_some_and(|target| target != proc.pid)
+        {
+            continue;
+        }
+
+        found = true;
+        if proc.state == State::Zombie {
+            zombie = Some((proc.pid, proc.exit_code.unwrap_or(ExitCode::Failure)));
+            break;
+        }
+    }
+
+    match zombie {
+        Some((id, code)) => {
+            table.remove(id);
+            Ok(Some((id, code)))
+        }
+        None if found => Ok(None),
+        None => Err(Error::ECHILD),
+    }
+}
+
+/// hand the children of a process that is exiting to PID 1, which collects them once they exit in turn
+///
+/// once init is gone they go to the kernel instead, which collects them itself: those that have exited already at once, and the others as they exit
+fn reparent_children(table: &mut ProcessTable, id: usize) {
+    let init_lives = id != 1 && table.get(1).is_some_and(|init| init.state != State::Zombie);
+    let ppid = if init_lives { 1 } else { 0 };
+
+    let mut reaped = Vec::new();
+    for proc in table.0.values_mut() {
+        if proc.pid != 0 && proc.pid != id && proc.ppid == id {
+            proc.ppid = ppid;
+            if ppid == 0 && proc.state == State::Zombie {
+                reaped.push(proc.pid);
+            }
+        }
+    }
+    for id in reaped {
+        table.remove(id);
+    }
+}
+
+/// whether any process other than the kernel and the given one has yet to exit
+fn others_alive(table: &ProcessTable, id: usize) -> bool {
+    table.0.values().any(|proc| proc.pid != 0 && proc.pid != id && proc.state != State::Zombie)
+}
+
+/// wrap up and exit the current process, surrendering control to the parent if it is waiting in EXEC, or to the next process in the run queue
+///
+/// the process is left a zombie holding its exit code, until EXEC returns the code to the parent or the parent collects it with WAITPID. Its own children are handed to PID 1.
+/// the kernel never gives the CPU back once it has it, so it is only resumed from its EXEC once every other process has exited
+pub fn exit(code: ExitCode) {
+    let id = pid();
+    let (next, collected) = {
+        let mut table = PROCESS_TABLE.write();
+        table[id].state = State::Zombie;
+        table[id].exit_code = Some(code);
+        // the stack is freed once the next process to exit takes its place
+        *EXITED_STACK.lock() = table[id].kernel_stack.take();
+        reparent_children(&mut table, id);
+
+        let (parent, resumes_parent) = (table[id].ppid, table[id].resumes_parent);
+        if resumes_parent {
+            table[parent].registers.rax = code as usize;
+        }
+        let next = if resumes_parent && parent != 0 {
+            Some(parent)
+        } else if table[0].state == State::Blocked && !others_alive(&table, id) {
+            Some(0)
+        } else {
+            None
+        };
+        // EXEC hands the exit code to the parent, and the kernel has no use for those of its orphans, which leaves nothing to collect
+        (next, resumes_parent || parent == 0)
+    };
 
-    MAX_PID.fetch_sub(1, Ordering::SeqCst);
-    set_pid(proc.ppid);
+    RUN_QUEUE.lock().retain(|&queued| queued != id);
+    CHILD_EXITS.wake_all();
+    switch_to(next.unwrap_or_else(next_ready));
+
+    PROCESS_TABLE.read()[id].free_pages();
+    if collected {
+        PROCESS_TABLE.write().remove(id);
+    }
+}
+
+/// take the next process from the run queue, putting the current one at the back
+fn next_in_queue(queue: &mut VecDeque<usize>, current: usize) -> Option<usize> {
+    let next = queue.pop_front()?;
+    queue.push_back(current);
+    Some(next)
+}
+
+/// save the context of the running process and switch to the next one in the run queue, returning the context to resume it with
+///
+/// nothing happens if no other process is waiting to run. Must only be called when the process was interrupted in user mode
+pub fn schedule(
+    stack_frame: InterruptStackFrameValue,
+    registers: Registers,
+) -> Option<(InterruptStackFrameValue, Registers)> {
+    let next = next_in_queue(&mut RUN_QUEUE.lock(), pid())?;
+
+    set_stack_frame(stack_frame);
+    set_registers(registers);
+    set_state(pid(), State::Ready);
+    switch_to(next);
+    Some((get_stack_frame(), get_registers()))
+}
+
+/// save the context of the current process, which has just blocked, and switch to the next one in the run queue, returning the context to resume it with
+///
+/// the CPU idles until a process is woken if none is ready to run
+pub fn switch_from_blocked(
+    stack_frame: InterruptStackFrameValue,
+    registers: Registers,
+) -> (InterruptStackFrameValue, Registers) {
+    set_stack_frame(stack_frame);
+    set_registers(registers);
+    switch_to(next_ready());
+    (get_stack_frame(), get_registers())
+}
+
+/// take the next process from the run queue, idling until one is woken if it is empty
+fn next_ready() -> usize {
+    loop {
+        if let Some(next) = RUN_QUEUE.lock().pop_front() {
+            return next;
+        }
+        // processes are woken by interrupt handlers, which syscalls otherwise run without
+        x86_64::instructions::interrupts::enable_and_hlt();
+        x86_64::instructions::interrupts::disable();
+    }
+}
+
+/// make a process the running one, switching to its address space and kernel stack
+fn switch_to(id: usize) {
+    set_pid(id);
+    set_state(id, State::Running);
+    let table = PROCESS_TABLE.read();
+    let proc = &table[id];
+
+    // the kernel itself runs on the stack it booted with
+    if let Some(stack) = &proc.kernel_stack {
+        crate::internal::gdt::set_kernel_stack(stack.top());
+    }
 
     unsafe {
         let (_, flags) = Cr3::read();
-        Cr3::write(page_table_frame(), flags);
+        Cr3::write(proc.page_table_frame, flags);
     }
-
-    proc.free_pages();
 }
 
 unsafe fn page_table_frame() -> PhysFrame {
@@ -365,6 +740,12 @@ pub struct Process {
     page_table_frame: PhysFrame,
     stack_frame: Option<InterruptStackFrameValue>,
     registers: Registers,
+    kernel_stack: Option<Arc<KernelStack>>,
+    /// what the process is doing
+    pub state: State,
+    wake_at: Option<usize>,
+    resumes_parent: bool,
+    exit_code: Option<ExitCode>,
     /// process data (environment, working directory, user, file handles)
     pub data: ProcessData,
     /// memory allocator
@@ -383,29 +764,91 @@ impl Process {
             stack_frame: None,
             page_table_frame: Cr3::read().0,
             registers: Registers::default(),
+            kernel_stack: None,
+            state: State::Running,
+            wake_at: None,
+            resumes_parent: false,
+            exit_code: None,
             data: ProcessData::new("/", None),
             allocator: Arc::new(LockedHeap::empty()),
         }
     }
 
-    /// Spawn a new process
+    /// Spawn a new process, which waits for its turn in the run queue
+    ///
+    /// the current process is blocked until the child exits, and then switched back to with the child's exit code
     pub fn spawn(bin: &[u8], args_ptr: usize, args_len: usize) -> Result<(), ExitCode> {
-        if let Ok(id) = Self::create(bin) {
-            let proc = {
-                let table = PROCESS_TABLE.read();
-                table[id].clone()
-            };
-            proc.exec(args_ptr, args_len);
-            unreachable!(); // The kernel switched to the child process
-        } else {
-            Err(ExitCode::ExecError)
+        let id = Self::create(bin).map_err(|()| ExitCode::ExecError)?;
+        let proc = PROCESS_TABLE.read()[id].clone();
+        let (stack_frame, registers) = proc.entry_context(args_ptr, args_len);
+
+        let mut table = PROCESS_TABLE.write();
+        table[id].stack_frame = Some(stack_frame);
+        table[id].registers = registers;
+        table[pid()].state = State::Blocked;
+        enqueue(&mut table, id);
+        Ok(())
+    }
+
+    /// Fork the current process, returning the PID of the child
+    ///
+    /// the child gets a copy of the parent's data, heap and address space, whose pages the two share until either writes to them, and returns from the same FORK syscall with 0
+    ///
+    /// fails with EINVAL in the kernel, EAGAIN when there are already MAX_PROCS processes, and ENOMEM when there are no frames left for the child's page tables
+    pub fn fork() -> Result<usize, Error> {
+        // the kernel has no address space of its own to copy
+        if pid() == 0 {
+            return Err(Error::EINVAL);
         }
+        let id = PROCESS_TABLE.read().alloc_pid().ok_or(Error::EAGAIN)?;
+
+        let parent = {
+            let process_table = PROCESS_TABLE.read();
+            process_table[pid()].clone()
+        };
+
+        let page_table_frame =
+            crate::internal::memory::fork_page_table(parent.page_table_frame, &parent.ranges())
+                .ok_or(Error::ENOMEM)?;
+
+        // the heap keeps its free list in its own pages, which the child has at the same addresses
+        let allocator = Arc::new(LockedHeap::empty());
+        // SAFETY: a heap is only its bounds and a pointer to the first hole of that free list, all inside the ranges just forked.
+        // the copy is read under the parent's lock, so it is not caught part way through a change, and from then on it only reaches the child's copies of those pages.
+        // a heap has no drop, so the two copies never give anything back twice
+        *allocator.lock() = unsafe { core::ptr::read(&*parent.allocator.lock()) };
+
+        // saved by the syscall handler before FORK
+        let mut registers = parent.registers;
+        registers.rax = 0;
+
+        let proc = Process {
+            pid: id,
+            ppid: parent.pid,
+            code_addr: parent.code_addr,
+            stack_addr: parent.stack_addr,
+            entry_point_addr: parent.entry_point_addr,
+            page_table_frame,
+            stack_frame: parent.stack_frame,
+            registers,
+            kernel_stack: Some(Arc::new(KernelStack::new())),
+            state: State::Ready,
+            wake_at: None,
+            resumes_parent: false,
+            exit_code: None,
+            data: parent.data,
+            allocator,
+        };
+
+        let mut table = PROCESS_TABLE.write();
+        table.insert(proc);
+        enqueue(&mut table, id);
+
+        Ok(id)
     }
 
     fn create(bin: &[u8]) -> Result<usize, ()> {
-        if MAX_PID.load(Ordering::SeqCst) >= MAX_PROCS {
-            return Err(());
-        }
+        let id = PROCESS_TABLE.read().alloc_pid().ok_or(())?;
 
         let page_table_frame = crate::internal::memory::frame_allocator()
             .allocate_frame()
@@ -421,6 +864,10 @@ impl Process {
             *user_page = kernel_page.clone();
         }
 
+        // programs linked to run at USER_ADDR each get their own pages there, rather than those of whoever faulted them in first
+        crate::internal::memory::unshare_range(page_table_frame, USER_ADDR, MAX_PROC_SIZE)
+            .ok_or(())?;
+
         let mut mapper = unsafe {
             OffsetPageTable::new(
                 page_table,
@@ -477,7 +924,6 @@ impl Process {
 
         let allocator = Arc::new(LockedHeap::empty());
 
-        let id = MAX_PID.fetch_add(1, Ordering::SeqCst);
         let parent_id = parent.pid;
         let proc = Process {
             pid: id,
@@ -489,24 +935,26 @@ impl Process {
             data,
             stack_frame,
             registers,
+            kernel_stack: Some(Arc::new(KernelStack::new())),
+            state: State::Ready,
+            wake_at: None,
+            resumes_parent: true,
+            exit_code: None,
             allocator,
         };
 
-        let mut process_table = PROCESS_TABLE.write();
-        process_table[id] = Box::new(proc);
+        PROCESS_TABLE.write().insert(proc);
 
         Ok(id)
     }
 
-    // Switch to user mode and execute the program
-    fn exec(&self, args_ptr: usize, args_len: usize) {
-        let page_table = unsafe { page_table() };
-        let mut mapper = unsafe {
-            OffsetPageTable::new(
-                page_table,
-                crate::internal::memory::physical_memory_offset(),
-            )
-        };
+    /// copy the args into the process's memory and set up its heap, returning the context to enter the program in user mode with
+    fn entry_context(
+        &self,
+        args_ptr: usize,
+        args_len: usize,
+    ) -> (InterruptStackFrameValue, Registers) {
+        let mut mapper = self.mapper();
 
         // Copy args to user memory
         let args_addr = self.code_addr + (self.stack_addr - self.code_addr) / 2;
@@ -543,28 +991,19 @@ impl Process {
             self.allocator.lock().init(heap_addr as *mut u8, heap_size);
         }
 
-        set_pid(self.pid); // Change PID
-
-        unsafe {
-            let (_, flags) = Cr3::read();
-            Cr3::write(self.page_table_frame, flags);
-
-            asm!(
-                "cli",        // Disable interrupts
-                "push {:r}",  // Stack segment (SS)
-                "push {:r}",  // Stack pointer (RSP)
-                "push 0x200", // RFLAGS with interrupts enabled
-                "push {:r}",  // Code segment (CS)
-                "push {:r}",  // Instruction pointer (RIP)
-                "iretq",
-                in(reg) crate::internal::gdt::GDT.1.user_data.0,
-                in(reg) self.stack_addr,
-                in(reg) crate::internal::gdt::GDT.1.user_code.0,
-                in(reg) self.code_addr + self.entry_point_addr,
-                in("rdi") args_ptr,
-                in("rsi") args_len,
-            );
-        }
+        let stack_frame = InterruptStackFrameValue::new(
+            VirtAddr::new(self.code_addr + self.entry_point_addr),
+            crate::internal::gdt::GDT.1.user_code,
+            RFlags::INTERRUPT_FLAG,
+            VirtAddr::new(self.stack_addr),
+            crate::internal::gdt::GDT.1.user_data,
+        );
+        let registers = Registers {
+            rdi: args_ptr as usize,
+            rsi: args_len,
+            ..Registers::default()
+        };
+        (stack_frame, registers)
     }
 
     fn mapper(&self) -> OffsetPageTable {
@@ -578,6 +1017,12 @@ impl Process {
         }
     }
 
+    /// the ranges of addresses where a process has memory of its own
+    fn ranges(&self) -> [(u64, usize); 2] {
+        [(self.code_addr, MAX_PROC_SIZE), (USER_ADDR, MAX_PROC_SIZE)]
+    }
+
+    /// give back the memory of a process, along with its page tables, which must not be the ones in use
     fn free_pages(&self) {
         let mut mapper = self.mapper();
 
@@ -597,6 +1042,8 @@ impl Process {
             }
             _ => {}
         }
+
+        crate::internal::memory::free_page_tables(self.page_table_frame, &self.ranges());
     }
 }
 
@@ -614,3 +1061,200 @@ fn load_binary(mapper: &mut OffsetPageTable, addr: u64, size: usize, buf: &[u8])
     }
     Ok(())
 }
+
+/// test that the run queue gives every process a turn in order
+#[test_case]
+fn test_run_queue() {
+    let mut queue = VecDeque::from([2, 3]);
+    assert_eq!(next_in_queue(&mut queue, 1), Some(2));
+    assert_eq!(next_in_queue(&mut queue, 2), Some(3));
+    assert_eq!(next_in_queue(&mut queue, 3), Some(1));
+    assert_eq!(queue, [2, 3]);
+
+    // a process alone keeps running
+    assert_eq!(next_in_queue(&mut VecDeque::new(), 1), None);
+}
+
+/// test that spawned processes wait their turn in the run queue, and then take turns on the CPU, each resumed with its own context
+#[test_case]
+fn test_spawned_processes_take_turns() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        // a flat binary that jumps to itself forever
+        let bin = [BIN_MAGIC.as_slice(), &[0xEB, 0xFE]].concat();
+        let args: [&str; 0] = [];
+        let queued = RUN_QUEUE.lock().len();
+        Process::spawn(&bin, args.as_ptr() as usize, 0).unwrap();
+        Process::spawn(&bin, args.as_ptr() as usize, 0).unwrap();
+
+        let (a, b) = {
+            let queue = RUN_QUEUE.lock();
+            assert_eq!(queue.len(), queued + 2);
+            (queue[queued], queue[queued + 1])
+        };
+        assert_eq!(PROCESS_TABLE.read()[0].state, State::Blocked);
+        assert_eq!(PROCESS_TABLE.read()[a].state, State::Ready);
+
+        // the kernel blocks in EXEC, and the first child starts at its entry point
+        let gdt = &crate::internal::gdt::GDT.1;
+        let kernel_frame =
+            InterruptStackFrameValue::new(VirtAddr::zero(), gdt.code, RFlags::empty(), VirtAddr::zero(), gdt.data);
+        let (mut frame, regs) = switch_from_blocked(kernel_frame, Registers::default());
+        assert_eq!(pid(), a);
+        assert_eq!(frame.instruction_pointer.as_u64(), PROCESS_TABLE.read()[a].code_addr);
+        assert_eq!(regs.rdi, args.as_ptr() as usize);
+
+        // then every tick hands the CPU to the other, which carries on from where it was switched out
+        for (tick, (running, next)) in [(a, b), (b, a), (a, b), (b, a)].into_iter().enumerate() {
+            let regs = Registers { rax: running, ..Registers::default() };
+            let (next_frame, next_regs) = schedule(frame, regs).unwrap();
+            assert_eq!(pid(), next);
+            assert_eq!(PROCESS_TABLE.read()[running].state, State::Ready);
+            // b has not run before the first tick
+            assert_eq!(next_regs.rax, if tick == 0 { 0 } else { next });
+            frame = next_frame;
+        }
+
+        switch_to(0);
+        RUN_QUEUE.lock().retain(|&id| id != a && id != b);
+        let mut table = PROCESS_TABLE.write();
+        for id in [a, b] {
+            table.remove(id).unwrap().free_pages();
+        }
+    });
+}
+
+/// test that waking a queue puts its blocked processes in the run queue
+#[test_case]
+fn test_wait_queue() {
+    let queue = WaitQueue::new();
+    // the kernel waits by itself
+    assert!(!queue.wait());
+
+    let state = State::Blocked;
+    PROCESS_TABLE.write().insert(Process { pid: 3, state, ..Process::new() });
+    queue.0.lock().push_back(3);
+    queue.wake_all();
+    assert_eq!(PROCESS_TABLE.read()[3].state, State::Ready);
+    assert_eq!(RUN_QUEUE.lock().pop_back(), Some(3));
+
+    // a process that is not blocked, or that is gone, is left alone
+    queue.0.lock().extend([3, 4]);
+    queue.wake_all();
+    assert!(RUN_QUEUE.lock().is_empty());
+    PROCESS_TABLE.write().remove(3);
+}
+
+/// test that only children that have exited are collected, and only once
+#[test_case]
+fn test_reap_child() {
+    assert_eq!(reap_child(None), Err(Error::ECHILD));
+
+    for (pid, state) in [(2, State::Ready), (3, State::Zombie)] {
+        let exit_code = (state == State::Zombie).then_some(ExitCode::DataError);
+        PROCESS_TABLE.write().insert(Process { pid, state, exit_code, ..Process::new() });
+    }
+
+    assert_eq!(reap_child(Some(2)), Ok(None));
+    assert_eq!(reap_child(None), Ok(Some((3, ExitCode::DataError))));
+    assert_eq!(reap_child(Some(3)), Err(Error::ECHILD));
+    assert!(PROCESS_TABLE.read().get(3).is_none());
+
+    PROCESS_TABLE.write().remove(2);
+    assert_eq!(reap_child(None), Err(Error::ECHILD));
+}
+
+/// test that a process whose parent exits first is handed to PID 1, which then reaps it
+#[test_case]
+fn test_reap_orphaned_grandchild() {
+    let init_existed = PROCESS_TABLE.read().get(1).is_some();
+    {
+        let mut table = PROCESS_TABLE.write();
+        if !init_existed {
+            table.insert(Process { pid: 1, ..Process::new() });
+        }
+        table.insert(Process { pid: 5, ppid: 1, ..Process::new() });
+        let exit_code = Some(ExitCode::DataError);
+        table.insert(Process { pid: 6, ppid: 5, state: State::Zombie, exit_code, ..Process::new() });
+
+        // 5 exits before collecting 6
+        reparent_children(&mut table, 5);
+        table[5].state = State::Zombie;
+        table[5].exit_code = Some(ExitCode::Success);
+    }
+
+    // 5 has no children left to wait for, and init has both
+    set_pid(5);
+    assert_eq!(reap_child(None), Err(Error::ECHILD));
+    set_pid(1);
+    assert_eq!(reap_child(Some(6)), Ok(Some((6, ExitCode::DataError))));
+    assert_eq!(reap_child(None), Ok(Some((5, ExitCode::Success))));
+    assert_eq!(reap_child(None), Err(Error::ECHILD));
+    set_pid(0);
+
+    if !init_existed {
+        PROCESS_TABLE.write().remove(1);
+    }
+}
+
+/// test that the children of init are the kernel's once init exits, which collects those that have exited and waits in EXEC for the others
+#[test_case]
+fn test_init_exits_before_its_child() {
+    let init_existed = PROCESS_TABLE.read().get(1).is_some();
+    {
+        let mut table = PROCESS_TABLE.write();
+        if !init_existed {
+            table.insert(Process { pid: 1, resumes_parent: true, ..Process::new() });
+        }
+        table.insert(Process { pid: 7, ppid: 1, state: State::Ready, ..Process::new() });
+        let exit_code = Some(ExitCode::DataError);
+        table.insert(Process { pid: 8, ppid: 1, state: State::Zombie, exit_code, ..Process::new() });
+
+        reparent_children(&mut table, 1);
+        assert!(table.get(8).is_none());
+        assert_eq!(table[7].ppid, 0);
+        assert!(others_alive(&table, 1));
+
+        // a process that exits once init is gone has nobody left to take its children but the kernel too
+        table.insert(Process { pid: 9, ppid: 7, ..Process::new() });
+        if !init_existed {
+            table.remove(1);
+        }
+        reparent_children(&mut table, 7);
+        assert_eq!(table[9].ppid, 0);
+    }
+
+    // the orphans are not the kernel's to wait for, as it collects them itself
+    assert_eq!(reap_child(None), Err(Error::ECHILD));
+
+    let mut table = PROCESS_TABLE.write();
+    table.remove(7);
+    table.remove(9);
+}
+
+/// test that no process IDs are handed out once there are MAX_PROCS processes, as the limit is now
+#[test_case]
+fn test_max_procs() {
+    let old = MAX_PROCS.load(Ordering::SeqCst);
+    let procs = PROCESS_TABLE.read().pids().count();
+
+    // the kernel does not count
+    MAX_PROCS.store(procs - 1, Ordering::SeqCst);
+    assert_eq!(PROCESS_TABLE.read().alloc_pid(), None);
+    MAX_PROCS.store(procs, Ordering::SeqCst);
+    assert!(PROCESS_TABLE.read().alloc_pid().is_some());
+
+    MAX_PROCS.store(old, Ordering::SeqCst);
+}
+
+/// test that process IDs go up, skip those in use, and only come back round once they run out
+#[test_case]
+fn test_next_free_pid() {
+    let mut next = 1;
+    assert_eq!(next_free_pid(&mut next, 5, |id| id == 2), Some(1));
+    assert_eq!(next_free_pid(&mut next, 5, |id| id == 2), Some(3));
+    assert_eq!(next_free_pid(&mut next, 5, |_| false), Some(4));
+    assert_eq!(next_free_pid(&mut next, 5, |_| false), Some(5));
+    // init keeps 1
+    assert_eq!(next_free_pid(&mut next, 5, |_| false), Some(2));
+    assert_eq!(next_free_pid(&mut next, 5, |_| true), None);
+}
@@ -1,14 +1,13 @@
 use core::arch::asm;
 
-use alloc::string::ToString;
 use log::{trace, warn};
 use spin::Mutex;
 
-use crate::{internal::{
+use crate::internal::{
     file::Stream,
-    fs::FileHandle,
+    fs::{FileKind, FileStat},
     io::{Device, File, FILES}, process::ExitCode,
-}, kprintln};
+};
 
 use super::{
     file::{self, IOEvent},
@@ -19,6 +18,7 @@ use super::{
 pub static ERRNO: Mutex<usize> = Mutex::new(0);
 
 /// Error codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Error {
     /// Not super-user
     EPERM = 1,
@@ -38,15 +38,27 @@ pub enum Error {
     /// Bad file number
     EBADF = 9,
 
+    /// No child processes
+    ECHILD = 10,
+
+    /// Try again
+    EAGAIN = 11,
+
     /// not enough core (memory)
     ENOMEM = 12,
 
     /// Permission denied
     EACCES = 13,
 
+    /// Device or resource busy
+    EBUSY = 16,
+
     /// file exists
     EEXIST = 17,
 
+    /// Cross-device link
+    EXDEV = 18,
+
     /// No such device
     ENODEV = 19,
 
@@ -71,6 +83,12 @@ pub enum Error {
     /// Read-only file system
     EROFS = 30,
 
+    /// Directory not empty
+    ENOTEMPTY = 39,
+
+    /// too many levels of symbolic links
+    ELOOP = 40,
+
     /// no csi structure available
     ENOCSI = 43,
 
@@ -84,6 +102,51 @@ pub enum Error {
     EOVERFLOW = 139,
 }
 
+/// the information about a file written by STAT, laid out as `struct stat` in syscall.h
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct Stat {
+    /// the inode index
+    pub inode: u64,
+    /// 0 for a regular file, 1 for a directory, 2 for a symbolic link
+    pub kind: u64,
+    /// the size in bytes
+    pub size: u64,
+    /// the number of blocks in use
+    pub blocks: u64,
+    /// the owner's user id
+    pub owner: u64,
+    /// the permissions as Unix mode bits, such as 0o755
+    pub mode: u64,
+    /// creation time, in seconds since the Unix epoch
+    pub ctime: u64,
+    /// last modification time, in seconds since the Unix epoch
+    pub mtime: u64,
+    /// last access time, in seconds since the Unix epoch
+    pub atime: u64,
+}
+
+impl From<FileStat> for Stat {
+    fn from(stat: FileStat) -> Self {
+        let [owner, group, other] = stat.permissions.map(|digit| digit as u64);
+        Stat {
+            inode: stat.inode,
+            kind: match stat.kind {
+                FileKind::File => 0,
+                FileKind::Directory => 1,
+                FileKind::Symlink => 2,
+            },
+            size: stat.size,
+            blocks: stat.blocks,
+            owner: stat.owner,
+            mode: owner << 6 | group << 3 | other,
+            ctime: stat.creation_time,
+            mtime: stat.modification_time,
+            atime: stat.access_time,
+        }
+    }
+}
+
 fn utf8_from_raw_parts(ptr: *mut u8, len: usize) -> &'static str {
     unsafe {
         let slice = core::slice::from_raw_parts(ptr, len);
@@ -95,7 +158,7 @@ fn utf8_from_raw_parts(ptr: *mut u8, len: usize) -> &'static str {
 pub const READ: usize = 0x1;
 /// write to a file descriptor - `write(fd, buf, len)`
 pub const WRITE: usize = 0x2;
-/// open a file and return a file descriptor - `open(path, path_len, flags)`
+/// open a file and return a file descriptor, with the permission bits to create it with (less the umask) in mode - `open(path, path_len, flags, mode)`
 pub const OPEN: usize = 0x3;
 /// close a file descriptor - `close(fd)`
 pub const CLOSE: usize = 0x4;
@@ -117,8 +180,10 @@ pub const FORK: usize = 0xB;
 pub const GETTID: usize = 0xC;
 /// stop the current process - `stop(type)`
 pub const STOP: usize = 0xD;
-/// wait for a child process to exit - `waitpid(pid, status)`
+/// wait for a child process to exit - `waitpid(pid, status, options)`
 pub const WAITPID: usize = 0xE;
+/// WAITPID option to return straight away if no child has exited yet
+pub const WNOHANG: usize = 1;
 /// connect to a socket - `connect(fd, addr, addr_len)`
 pub const CONNECT: usize = 0xF;
 /// accept a connection on a socket - `accept(fd, addr, addr_len)`
@@ -141,6 +206,24 @@ pub const BOOTTIME: usize = 0x17;
 pub const TIME: usize = 0x18;
 /// seek to a position in a file descriptor - `seek(fd, pos)`
 pub const SEEK: usize = 0x19;
+/// get the size, owner, permissions and timestamps of a file - `stat(path, path_len, buf)`
+pub const STAT: usize = 0x1A;
+/// make a new name for an existing file - `link(old, old_len, new, new_len)`
+pub const LINK: usize = 0x1B;
+/// make a symbolic link at path pointing at target - `symlink(target, target_len, path, path_len)`
+pub const SYMLINK: usize = 0x1C;
+/// read where a symbolic link points, returning the number of bytes written - `readlink(path, path_len, buf, buf_len)`
+pub const READLINK: usize = 0x1D;
+/// move a file or directory to a new path - `rename(old, old_len, new, new_len)`
+pub const RENAME: usize = 0x1E;
+/// cut a file down or grow it to a given length - `ftruncate(fd, len)`
+pub const FTRUNCATE: usize = 0x1F;
+/// remove a name for a file, freeing it once the last is gone - `unlink(path, path_len)`
+pub const UNLINK: usize = 0x20;
+/// mount the volume on a partition of an ATA drive at a directory, with the partition in the bits of dsk above the first 8 - `mount(bus, dsk | part << 8, path, path_len)`
+pub const MOUNT: usize = 0x21;
+/// unmount the filesystem mounted at a directory - `umount(path, path_len)`
+pub const UMOUNT: usize = 0x22;
 
 
 /// internal syscall module
@@ -169,8 +252,9 @@ pub fn dispatch(n: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize) ->
             let path_addr = crate::internal::process::ptr_from_addr(arg1 as u64);
             let path = utf8_from_raw_parts(path_addr, arg2);
             let flags = arg3;
+            let mode = arg4;
 
-            service::open(path, flags as u8)
+            service::open(path, flags as u8, mode)
         }
         CLOSE => {
             let fd = arg1;
@@ -205,7 +289,7 @@ pub fn dispatch(n: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize) ->
             service::spawn(path, arg3, arg4)
         }
         FORK => {
-            unimplemented!("FORK")
+            service::fork()
         }
         GETTID => {
             unimplemented!("GETTID")
@@ -215,7 +299,14 @@ pub fn dispatch(n: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize) ->
             service::stop(kind)
         }
         WAITPID => {
-            unimplemented!("WAITPID")
+            let pid = arg1 as isize;
+            let status = (arg2 != 0).then(|| {
+                let status_addr = crate::internal::process::ptr_from_addr(arg2 as u64);
+                unsafe { &mut *(status_addr as *mut usize) }
+            });
+            let options = arg3;
+
+            service::waitpid(pid, status, options)
         }
         CONNECT => {
             unimplemented!("CONNECT")
@@ -262,6 +353,73 @@ pub fn dispatch(n: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize) ->
 
             service::seek(fd, pos)
         }
+        STAT => {
+            let path_addr = crate::internal::process::ptr_from_addr(arg1 as u64);
+            let path = utf8_from_raw_parts(path_addr, arg2);
+            let buf_addr = crate::internal::process::ptr_from_addr(arg3 as u64);
+            let buf = unsafe { &mut *(buf_addr as *mut Stat) };
+
+            service::stat(path, buf)
+        }
+        LINK => {
+            let old_addr = crate::internal::process::ptr_from_addr(arg1 as u64);
+            let old = utf8_from_raw_parts(old_addr, arg2);
+            let new_addr = crate::internal::process::ptr_from_addr(arg3 as u64);
+            let new = utf8_from_raw_parts(new_addr, arg4);
+
+            service::link(old, new)
+        }
+        SYMLINK => {
+            let target_addr = crate::internal::process::ptr_from_addr(arg1 as u64);
+            let target = utf8_from_raw_parts(target_addr, arg2);
+            let path_addr = crate::internal::process::ptr_from_addr(arg3 as u64);
+            let path = utf8_from_raw_parts(path_addr, arg4);
+
+            service::symlink(target, path)
+        }
+        READLINK => {
+            let path_addr = crate::internal::process::ptr_from_addr(arg1 as u64);
+            let path = utf8_from_raw_parts(path_addr, arg2);
+            let buf_addr = crate::internal::process::ptr_from_addr(arg3 as u64);
+            let buf = unsafe { core::slice::from_raw_parts_mut(buf_addr, arg4) };
+
+            service::readlink(path, buf)
+        }
+        RENAME => {
+            let old_addr = crate::internal::process::ptr_from_addr(arg1 as u64);
+            let old = utf8_from_raw_parts(old_addr, arg2);
+            let new_addr = crate::internal::process::ptr_from_addr(arg3 as u64);
+            let new = utf8_from_raw_parts(new_addr, arg4);
+
+            service::rename(old, new)
+        }
+        FTRUNCATE => {
+            let fd = arg1;
+            let len = arg2;
+
+            service::ftruncate(fd, len)
+        }
+        UNLINK => {
+            let path_addr = crate::internal::process::ptr_from_addr(arg1 as u64);
+            let path = utf8_from_raw_parts(path_addr, arg2);
+
+            service::unlink(path)
+        }
+        MOUNT => {
+            let bus = arg1;
+            let dsk = arg2 & 0xFF;
+            let part = arg2 >> 8;
+            let path_addr = crate::internal::process::ptr_from_addr(arg3 as u64);
+            let path = utf8_from_raw_parts(path_addr, arg4);
+
+            service::mount(bus, dsk, part, path)
+        }
+        UMOUNT => {
+            let path_addr = crate::internal::process::ptr_from_addr(arg1 as u64);
+            let path = utf8_from_raw_parts(path_addr, arg2);
+
+            service::umount(path)
+        }
         _ => {
             warn!("Unknown syscall: {}", n);
             -1
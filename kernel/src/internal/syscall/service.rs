@@ -1,7 +1,11 @@
 use alloc::vec;
 
 use crate::internal::{
-    devices::proc::ProcInfo, file::FileFlags, fs::get_buffer_size, process::ExitCode,
+    ata,
+    file::FileFlags,
+    fs::mount_drive,
+    process::{self, ExitCode},
+    user, vfs,
 };
 
 use super::*;
@@ -44,70 +48,100 @@ pub fn init() {
     });
 }
 
-/// device path should be something like "/dev/sda" NOT "sda"
-fn open_block_device(device_path: &str) -> isize {
-    match device_path {
-        "/dev/stdin" => io::STDIN as isize,
-        "/dev/stdout" => io::STDOUT as isize,
-        "/dev/stderr" => io::STDERR as isize,
-        "/dev/null" => io::NULL as isize,
-        "/dev/zero" => io::ZERO as isize,
-        "/dev/random" => io::RAND as isize,
-        _ => {
-            warn!("Unknown device: {}, failing OPEN", device_path);
-            set_errno(Error::ENOENT);
-            -1
-        }
-    }
-}
+/// check that the process's user may open a file with the given flags, against the file's owner, group and other rwx bits
+fn check_access(path: &str, flags: u8) -> Result<(), Error> {
+    // processes without a user are run by the kernel itself, which is trusted like root
+    let Some(name) = process::get_user() else {
+        return Ok(());
+    };
 
-/// open a proc info file
-fn open_proc_file(proc_path: &str) -> isize {
-    // will be in the format /proc/<pid>/<route>
-    let pid = proc_path.split('/').nth(2);
-    if pid.is_none() {
-        warn!("Invalid proc path: {}, failing OPEN", proc_path);
-        set_errno(Error::ENOENT);
-        return -1;
+    let mut wanted = 0;
+    if FileFlags::Read.is_set(flags) {
+        wanted |= 4;
+    }
+    if FileFlags::Write.is_set(flags)
+        || FileFlags::Append.is_set(flags)
+        || FileFlags::Truncate.is_set(flags)
+    {
+        wanted |= 2;
     }
 
-    let pid = pid.unwrap().parse::<u32>();
-    if pid.is_err() {
-        warn!("Invalid proc path: {}, failing OPEN", proc_path);
-        set_errno(Error::ENOENT);
-        return -1;
+    let stat = vfs::stat(path)?;
+    if user::access_bits(&name, stat.owner, stat.permissions) & wanted == wanted {
+        Ok(())
+    } else {
+        Err(Error::EACCES)
     }
+}
 
-    let mut files = FILES.lock();
-    let fd = files.len();
+/// check that the process's user may add or remove names in the directory a path is in
+fn check_parent_access(path: &str) -> Result<(), Error> {
+    let Some(name) = process::get_user() else {
+        return Ok(());
+    };
 
-    files.insert(
-        fd,
-        File::ProcInfo(ProcInfo::new(pid.unwrap(), proc_path.to_string())),
-    );
-    kprintln!("{:?}", files.get(&fd));
-    fd as isize
-}
+    let parent = match path.trim_end_matches('/').rsplit_once('/') {
+        Some(("", _)) | None => "/",
+        Some((parent, _)) => parent,
+    };
 
-/// open a file (OPEN)
-pub fn open(path: &str, flags: u8) -> isize {
-    let path = &file::canonicalise(path);
-    if path.starts_with("/dev/") {
-        return open_block_device(path);
+    let stat = vfs::stat(parent)?;
+    if user::access_bits(&name, stat.owner, stat.permissions) & 3 == 3 {
+        Ok(())
+    } else {
+        Err(Error::EACCES)
     }
+}
+
+/// the uid of the process's user, or root for processes run by the kernel
+fn current_uid() -> u64 {
+    process::get_user()
+        .and_then(|name| user::get_uid(&name))
+        .unwrap_or(0)
+}
 
-    if path.starts_with("/proc/") {
-        return open_proc_file(path);
+/// check that the process's user may change the mount table, which only root may do
+fn check_root() -> Result<(), Error> {
+    match process::get_user() {
+        None => Ok(()),
+        Some(name) if user::get_uid(&name) == Some(0) => Ok(()),
+        Some(_) => Err(Error::EPERM),
     }
+}
 
-    let file_handle = FileHandle::new_with_likely_fs(path.to_string(), flags);
+/// the permission bits taken away from the mode files are created with. There is no UMASK syscall yet, so every process has the usual 022
+const UMASK: usize = 0o022;
 
-    if file_handle.is_err() {
-        set_errno(file_handle.unwrap_err().into());
+/// open a file (OPEN), on whichever mounted filesystem it is on
+///
+/// if the create flag is set and the file does not exist, it is made for the process's user with the permission bits of mode, less UMASK
+pub fn open(path: &str, flags: u8, mode: usize) -> isize {
+    let path = &file::canonicalise(path);
+
+    // a file that is about to be created is checked against its directory instead
+    let access = if FileFlags::Create.is_set(flags) && !vfs::exists(path) {
+        check_parent_access(path).and_then(|()| {
+            let perms = file::perms((mode & !UMASK) as u32);
+            Ok(vfs::create(path, perms, current_uid())?)
+        })
+    } else {
+        check_access(path, flags)
+    };
+
+    if let Err(errno) = access {
+        warn!("Cannot access {}, failing OPEN", path);
+        set_errno(errno);
         return -1;
     }
 
-    let resource = File::File(file_handle.unwrap());
+    // the filesystem truncates the file if the flags ask for it
+    let resource = match vfs::open(path, flags) {
+        Ok(file) => File::File(file),
+        Err(err) => {
+            set_errno(err.into());
+            return -1;
+        }
+    };
 
     let mut files = FILES.lock();
 
@@ -201,6 +235,26 @@ pub fn flush(fd: usize) -> isize {
     }
 }
 
+/// cut a file down or grow it to a given length, through a file descriptor open for writing (FTRUNCATE)
+pub fn ftruncate(fd: usize, len: usize) -> isize {
+    let mut files = FILES.lock();
+
+    match files.get_mut(&fd) {
+        // devices and proc files have no length to change, and fail with EINVAL
+        Some(resource) => match resource.truncate(len as u64) {
+            Ok(()) => 0,
+            Err(err) => {
+                set_errno(err.into());
+                -1
+            }
+        },
+        None => {
+            set_errno(Error::EBADF);
+            -1
+        }
+    }
+}
+
 /// stop the system (STOP)
 pub fn stop(stop_type: usize) -> isize {
     match stop_type {
@@ -240,11 +294,9 @@ pub fn poll(fd: usize, io_event: usize) -> isize {
     }
 }
 
-/// sleep for a number of nanoseconds (SLEEP)
+/// sleep for a number of nanoseconds (SLEEP), parking the process rather than halting the CPU
 pub fn sleep(nanos: usize) -> isize {
-    // sleep() accepts milliseconds, so convert nanoseconds to milliseconds
-    let millis = nanos as f64 / 1_000_000.0;
-    crate::internal::clk::sleep(millis);
+    crate::internal::clk::sleep_process(nanos as u64);
     0
 }
 
@@ -276,6 +328,139 @@ pub fn seek(fd: usize, pos: usize) -> isize {
     }
 }
 
+/// get information about a file (STAT)
+pub fn stat(path: &str, buf: &mut Stat) -> isize {
+    let path = file::canonicalise(path);
+
+    match vfs::stat(&path) {
+        Ok(stat) => {
+            *buf = stat.into();
+            0
+        }
+        Err(err) => {
+            set_errno(err.into());
+            -1
+        }
+    }
+}
+
+/// give an existing file a new name (LINK)
+pub fn link(old: &str, new: &str) -> isize {
+    let (old, new) = (file::canonicalise(old), file::canonicalise(new));
+
+    let result = check_parent_access(&new).and_then(|()| Ok(vfs::link(&old, &new)?));
+    match result {
+        Ok(()) => 0,
+        Err(errno) => {
+            set_errno(errno);
+            -1
+        }
+    }
+}
+
+/// remove a name for a file (UNLINK), the file itself is freed once its last name is gone
+pub fn unlink(path: &str) -> isize {
+    let path = file::canonicalise(path);
+
+    let result = check_parent_access(&path).and_then(|()| Ok(vfs::delete(&path)?));
+    match result {
+        Ok(()) => 0,
+        Err(errno) => {
+            set_errno(errno);
+            -1
+        }
+    }
+}
+
+/// make a symbolic link (SYMLINK), the target is kept as given, so a relative one is resolved from the link's directory
+pub fn symlink(target: &str, path: &str) -> isize {
+    let path = file::canonicalise(path);
+    let result = check_parent_access(&path)
+        .and_then(|()| Ok(vfs::symlink(target, &path, current_uid())?));
+    match result {
+        Ok(()) => 0,
+        Err(errno) => {
+            set_errno(errno);
+            -1
+        }
+    }
+}
+
+/// read where a symbolic link points (READLINK), truncating the target to fit the buffer
+pub fn readlink(path: &str, buf: &mut [u8]) -> isize {
+    let path = file::canonicalise(path);
+
+    match vfs::readlink(&path) {
+        Ok(target) => {
+            let len = target.len().min(buf.len());
+            buf[..len].copy_from_slice(&target.as_bytes()[..len]);
+            len as isize
+        }
+        Err(err) => {
+            set_errno(err.into());
+            -1
+        }
+    }
+}
+
+/// move a file or directory (RENAME)
+pub fn rename(old: &str, new: &str) -> isize {
+    let (old, new) = (file::canonicalise(old), file::canonicalise(new));
+
+    let result = check_parent_access(&old)
+        .and_then(|()| check_parent_access(&new))
+        .and_then(|()| Ok(vfs::rename(&old, &new)?));
+    match result {
+        Ok(()) => 0,
+        Err(errno) => {
+            set_errno(errno);
+            -1
+        }
+    }
+}
+
+/// mount the rustnix-fs, ext2 or FAT32 volume on a partition of an ATA drive at a directory, where partition 0 is the whole drive (MOUNT)
+pub fn mount(bus: usize, dsk: usize, part: usize, path: &str) -> isize {
+    let path = file::canonicalise(path);
+
+    let result = check_root().and_then(|()| {
+        if !ata::list()
+            .iter()
+            .any(|drive| (drive.bus as usize, drive.dsk as usize) == (bus, dsk))
+        {
+            return Err(Error::ENODEV);
+        }
+
+        // the root is the only mount point that needs nothing under it
+        if path != "/" && vfs::stat(&path)?.kind != FileKind::Directory {
+            return Err(Error::ENOTDIR);
+        }
+
+        Ok(mount_drive(bus, dsk, part, &path)?)
+    });
+    match result {
+        Ok(()) => 0,
+        Err(errno) => {
+            set_errno(errno);
+            -1
+        }
+    }
+}
+
+/// write back and unmount the filesystem mounted at a directory (UMOUNT)
+pub fn umount(path: &str) -> isize {
+    let path = file::canonicalise(path);
+
+    let result = check_root().and_then(|()| Ok(vfs::umount(&path)?));
+    match result {
+        Ok(()) => 0,
+        Err(errno) => {
+            set_errno(errno);
+            -1
+        }
+    }
+}
+
 /// get the number of nanoseconds since boot (NANOS)
 pub fn nanos() -> usize {
     crate::internal::clk::get_boot_time_ns() as usize // safe as we target x86_64
@@ -291,13 +476,13 @@ pub fn spawn(path: &str, args_ptr: usize, args_len: usize) -> isize {
     let path = crate::internal::file::canonicalise(path);
 
     // use open syscall to open the file
-    let fd = open(&path, FileFlags::Read as u8);
+    let fd = open(&path, FileFlags::Read as u8, 0);
 
     if fd < 0 {
         return -1;
     }
 
-    let buf_size = get_buffer_size(0, 1, &path);
+    let buf_size = vfs::stat(&path);
     if buf_size.is_err() {
         close(fd as usize);
         set_errno(Error::EINVAL);
@@ -305,7 +490,7 @@ pub fn spawn(path: &str, args_ptr: usize, args_len: usize) -> isize {
     }
 
     // read the file into a buffer
-    let mut buf = vec![0; buf_size.unwrap()];
+    let mut buf = vec![0; buf_size.unwrap().size as usize];
     let bytes_read = read(fd as usize, &mut buf);
     if bytes_read < 0 {
         close(fd as usize);
@@ -315,15 +500,90 @@ pub fn spawn(path: &str, args_ptr: usize, args_len: usize) -> isize {
     let buf = &buf[..bytes_read];
     close(fd as usize);
 
-    if let Err(code) = crate::internal::process::Process::spawn(&buf, args_ptr, args_len) {
-        code as isize
-    } else {
-        unreachable!(); // The kernel switched to the child process
+    // on success the caller is blocked, and gets the child's exit code instead once it exits
+    match crate::internal::process::Process::spawn(buf, args_ptr, args_len) {
+        Ok(()) => 0,
+        Err(code) => code as isize,
+    }
+}
+
+/// fork the current process (FORK), returning the child's PID to the parent and 0 to the child
+pub fn fork() -> isize {
+    match crate::internal::process::Process::fork() {
+        Ok(id) => id as isize,
+        Err(errno) => {
+            set_errno(errno);
+            -1
+        }
     }
 }
 
-/// exit the current process (EXIT)
+/// exit the current process (EXIT), leaving the code for its parent to collect
 pub fn exit(code: ExitCode) -> ExitCode {
-    crate::internal::process::exit();
-    code // this will be handled by the parent process, so we should return the value
+    crate::internal::process::exit(code);
+    code // the syscall returns to another process, so this only shows in the trace
+}
+
+/// wait for a child process to exit (WAITPID), returning its PID and writing its exit code to status
+///
+/// a pid of -1 waits for any child, and WNOHANG returns 0 rather than waiting if none has exited yet
+pub fn waitpid(pid: isize, status: Option<&mut usize>, options: usize) -> isize {
+    let target = match pid {
+        -1 => None,
+        pid if pid > 0 => Some(pid as usize),
+        _ => {
+            set_errno(Error::EINVAL);
+            return -1;
+        }
+    };
+
+    match process::reap_child(target) {
+        Ok(Some((id, code))) => {
+            if let Some(status) = status {
+                *status = code as usize;
+            }
+            id as isize
+        }
+        Ok(None) => {
+            // the syscall is made again when a child exits
+            if options & WNOHANG == 0 {
+                process::CHILD_EXITS.wait();
+            }
+            0
+        }
+        Err(errno) => {
+            set_errno(errno);
+            -1
+        }
+    }
+}
+
+/// test the file syscalls against /tmp, which needs no disk
+#[test_case]
+fn test_tmp_files() {
+    let fd = open(
+        "/tmp/service",
+        FileFlags::Create as u8 | FileFlags::Read as u8 | FileFlags::Write as u8,
+        0o666,
+    );
+    assert!(fd >= 0);
+    let fd = fd as usize;
+
+    assert_eq!(write(fd, b"Hello, world!"), 13);
+    assert_eq!(ftruncate(fd, 5), 0);
+    assert_eq!(seek(fd, 0), 0);
+    let mut buf = [0; 13];
+    assert_eq!(read(fd, &mut buf), 5);
+    assert_eq!(&buf[..5], b"Hello");
+    assert_eq!(close(fd), 0);
+
+    let mut stat_buf = Stat::default();
+    assert_eq!(rename("/tmp/service", "/tmp/renamed"), 0);
+    assert_eq!(stat("/tmp/renamed", &mut stat_buf), 0);
+    assert_eq!(stat_buf.size, 5);
+    // made by the kernel, so owned by root, with the umask taken off the mode
+    assert_eq!(stat_buf.owner, 0);
+    assert_eq!(stat_buf.mode, 0o644);
+    assert_eq!(unlink("/tmp/renamed"), 0);
+    assert_eq!(stat("/tmp/renamed", &mut stat_buf), -1);
 }
@@ -0,0 +1,715 @@
+/*
+ * tmpfs, a filesystem that lives entirely in memory
+ * nodes are kept in a table keyed by inode number, and directories map names to inode numbers, so a file can have several names (hard links) like on rustnix-fs.
+ * the filesystem and every file opened on it share the table, which is dropped along with the last of them.
+ * the total size of file contents and symbolic link targets is capped, so that filling a tmpfs cannot exhaust the kernel heap.
+ */
+
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use rustnix_fs_core::{
+    MAX_NAME_LEN, RELATIME_INTERVAL,
+    path::{self, Lookup},
+};
+use spin::Mutex;
+
+use crate::internal::{
+    clk,
+    file::{FileError, FileFlags, FileSystem, IOEvent, Stream},
+    fs::{FileKind, FileStat, FsError},
+};
+
+#[cfg(test)]
+use crate::internal::file::ALL_FLAGS;
+
+/// the size limit of the tmpfs mounted at /tmp (1 MB)
+pub const TMP_SIZE: u64 = 1024 * 1024;
+
+/// the inode number of the root directory
+const ROOT_INODE: u64 = 0;
+
+/// a file, directory or symbolic link
+#[derive(Debug, Clone)]
+struct Node {
+    kind: FileKind,
+    /// the contents of a file, or the target of a symbolic link
+    data: Vec<u8>,
+    /// the entries of a directory
+    children: BTreeMap<String, u64>,
+    links: u64,
+    /// how many open files hold the node, which is only freed once its last link is gone and the last of them is closed
+    open: usize,
+    owner: u64,
+    permissions: [u8; 3],
+    creation_time: u64,
+    modification_time: u64,
+    access_time: u64,
+}
+
+impl Node {
+    fn new(kind: FileKind, perms: [u8; 3], owner: u64) -> Self {
+        let now = clk::get_unix_time();
+        Node {
+            kind,
+            data: Vec::new(),
+            children: BTreeMap::new(),
+            links: 1,
+            open: 0,
+            owner,
+            permissions: perms,
+            creation_time: now,
+            modification_time: now,
+            access_time: now,
+        }
+    }
+}
+
+/// split a path into its parent directory and final name
+fn split_parent(path: &str) -> Result<(&str, &str), FsError> {
+    let path = path.trim_end_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+
+    match name {
+        "" | "." | ".." => Err(FsError::InvalidPath),
+        _ if name.len() > MAX_NAME_LEN || name.contains('\0') => Err(FsError::NameTooLong),
+        _ => Ok((parent, name)),
+    }
+}
+
+/// the nodes of a tmpfs, shared by the filesystem and its open files
+#[derive(Debug)]
+struct Tree {
+    nodes: BTreeMap<u64, Node>,
+    next_inode: u64,
+    /// bytes of file contents and link targets in use
+    used: u64,
+    max_size: u64,
+}
+
+impl Tree {
+    fn node(&self, inode: u64) -> Result<&Node, FsError> {
+        self.nodes.get(&inode).ok_or(FsError::FileNotFound)
+    }
+
+    fn node_mut(&mut self, inode: u64) -> Result<&mut Node, FsError> {
+        self.nodes.get_mut(&inode).ok_or(FsError::FileNotFound)
+    }
+
+    /// resolve a path to the chain of directories leading to it from the root, ending with the inode it names
+    ///
+    /// symbolic links are followed wherever they appear, except at the end of the path when follow_last is false
+    fn resolve(&self, path: &str, follow_last: bool) -> Result<Vec<u64>, FsError> {
+        path::resolve(ROOT_INODE, path, follow_last, |dir, name, follow| {
+            let dir = self.node(dir)?;
+            if dir.kind != FileKind::Directory {
+                return Err(FsError::NotADirectory);
+            }
+
+            let child = *dir.children.get(name).ok_or(FsError::FileNotFound)?;
+            let node = self.node(child)?;
+            if follow && node.kind == FileKind::Symlink {
+                let target = String::from_utf8(node.data.clone()).map_err(|_| FsError::InvalidMetadata)?;
+                return Ok(Lookup::Symlink(target));
+            }
+            Ok(Lookup::Inode(child))
+        })
+    }
+
+    /// resolve a path to an inode, following symbolic links
+    fn lookup(&self, path: &str) -> Result<u64, FsError> {
+        Ok(*self.resolve(path, true)?.last().unwrap())
+    }
+
+    /// resolve a path like lookup, except that a symbolic link at the end of it is not followed
+    fn lookup_entry(&self, path: &str) -> Result<u64, FsError> {
+        Ok(*self.resolve(path, false)?.last().unwrap())
+    }
+
+    /// resolve the directory a path is in, returning it along with the final name
+    fn parent_of<'a>(&self, path: &'a str) -> Result<(u64, &'a str), FsError> {
+        let (parent, name) = split_parent(path)?;
+        let parent = self.lookup(parent)?;
+        if self.node(parent)?.kind != FileKind::Directory {
+            return Err(FsError::NotADirectory);
+        }
+
+        Ok((parent, name))
+    }
+
+    /// make room for a change in the size of the contents, failing if it would go over the limit
+    fn reserve(&mut self, old_len: usize, new_len: usize) -> Result<(), FsError> {
+        let used = self.used - old_len as u64 + new_len as u64;
+        if new_len > old_len && used > self.max_size {
+            return Err(FsError::DiskFull);
+        }
+
+        self.used = used;
+        Ok(())
+    }
+
+    /// create a node and link it into its parent directory
+    fn create(&mut self, path: &str, mut node: Node) -> Result<u64, FsError> {
+        let (parent, name) = self.parent_of(path)?;
+        if self.node(parent)?.children.contains_key(name) {
+            return Err(FsError::FileExists);
+        }
+
+        self.reserve(0, node.data.len())?;
+        let inode = self.next_inode;
+        self.next_inode += 1;
+        node.links = 1;
+        self.nodes.insert(inode, node);
+
+        let parent = self.node_mut(parent)?;
+        parent.children.insert(name.to_string(), inode);
+        parent.modification_time = clk::get_unix_time();
+        Ok(inode)
+    }
+
+    /// remove the entry for a path from its parent directory, without following a symbolic link at the end of it
+    fn unlink_entry(&mut self, path: &str) -> Result<(), FsError> {
+        let (parent, name) = self.parent_of(path)?;
+        let parent = self.node_mut(parent)?;
+        parent.children.remove(name).ok_or(FsError::FileNotFound)?;
+        parent.modification_time = clk::get_unix_time();
+        Ok(())
+    }
+
+    /// drop one link to a node, freeing it once the last is gone and nothing holds it open
+    fn drop_link(&mut self, inode: u64) -> Result<(), FsError> {
+        let node = self.node_mut(inode)?;
+        node.links -= 1;
+        self.free_if_unused(inode)
+    }
+
+    /// let go of a node held open by a file, freeing it if that was the last hold and it has no links left
+    fn close(&mut self, inode: u64) -> Result<(), FsError> {
+        self.node_mut(inode)?.open -= 1;
+        self.free_if_unused(inode)
+    }
+
+    fn free_if_unused(&mut self, inode: u64) -> Result<(), FsError> {
+        let node = self.node(inode)?;
+        if node.links == 0 && node.open == 0 {
+            let len = node.data.len();
+            self.nodes.remove(&inode);
+            self.reserve(len, 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// cut a file down or grow it to the given length, with any new space reading as zeroes
+    fn set_len(&mut self, inode: u64, len: usize) -> Result<(), FsError> {
+        let node = self.node(inode)?;
+        match node.kind {
+            FileKind::Directory => return Err(FsError::IsADirectory),
+            FileKind::Symlink => return Err(FsError::InvalidPath),
+            FileKind::File => {}
+        }
+
+        let old_len = node.data.len();
+        self.reserve(old_len, len)?;
+        let node = self.node_mut(inode)?;
+        node.data.resize(len, 0);
+        node.modification_time = clk::get_unix_time();
+        Ok(())
+    }
+
+    /// read from a file at an offset, updating its access time like rustnix-fs's relatime policy
+    fn read_at(&mut self, inode: u64, pos: usize, buf: &mut [u8]) -> Result<usize, FsError> {
+        let node = self.node_mut(inode)?;
+        let start = pos.min(node.data.len());
+        let len = buf.len().min(node.data.len() - start);
+        buf[..len].copy_from_slice(&node.data[start..start + len]);
+
+        let now = clk::get_unix_time();
+        if now > node.access_time
+            && (node.access_time <= node.modification_time
+                || now - node.access_time >= RELATIME_INTERVAL)
+        {
+            node.access_time = now;
+        }
+
+        Ok(len)
+    }
+
+    /// write to a file at an offset, growing it if the write goes past the end
+    fn write_at(&mut self, inode: u64, pos: usize, buf: &[u8]) -> Result<usize, FsError> {
+        let end = pos + buf.len();
+        let len = self.node(inode)?.data.len();
+        if end > len {
+            self.set_len(inode, end)?;
+        }
+
+        let node = self.node_mut(inode)?;
+        node.data[pos..end].copy_from_slice(buf);
+        node.modification_time = clk::get_unix_time();
+        Ok(buf.len())
+    }
+
+    fn stat(&self, inode: u64) -> Result<FileStat, FsError> {
+        let node = self.node(inode)?;
+        Ok(FileStat {
+            inode,
+            kind: node.kind,
+            size: node.data.len() as u64,
+            // nothing is on a disk, so nothing is in blocks
+            blocks: 0,
+            links: node.links,
+            owner: node.owner,
+            permissions: node.permissions,
+            creation_time: node.creation_time,
+            modification_time: node.modification_time,
+            access_time: node.access_time,
+        })
+    }
+}
+
+/// a filesystem held in memory, up to a size limit
+#[derive(Debug, Clone)]
+pub struct TmpFs {
+    tree: Arc<Mutex<Tree>>,
+}
+
+impl TmpFs {
+    /// create an empty filesystem, holding at most max_size bytes of file contents
+    pub fn new(max_size: u64) -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(ROOT_INODE, Node::new(FileKind::Directory, [7, 7, 7], 0));
+
+        TmpFs {
+            tree: Arc::new(Mutex::new(Tree {
+                nodes,
+                next_inode: ROOT_INODE + 1,
+                used: 0,
+                max_size,
+            })),
+        }
+    }
+
+    /// the number of bytes of file contents and link targets in use
+    pub fn used(&self) -> u64 {
+        self.tree.lock().used
+    }
+}
+
+/// a file open on a tmpfs, which implements Stream
+#[derive(Debug, Clone)]
+pub struct TmpFile {
+    tree: Arc<Mutex<Tree>>,
+    inode: u64,
+    flags: u8,
+    file_pos: usize,
+    closed: bool,
+}
+
+impl Stream for TmpFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, FileError> {
+        if !FileFlags::Read.is_set(self.flags) {
+            return Err(FileError::PermissionError(FsError::ReadError.into()));
+        }
+
+        let len = self.tree.lock().read_at(self.inode, self.file_pos, buf)?;
+        self.file_pos += len;
+        Ok(len)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, FileError> {
+        if !FileFlags::Write.is_set(self.flags) {
+            return Err(FileError::PermissionError(FsError::WriteError.into()));
+        }
+
+        // seeking beyond the end of the file leaves a hole, which reads as zeroes
+        let len = self.tree.lock().write_at(self.inode, self.file_pos, buf)?;
+        self.file_pos += len;
+        Ok(len)
+    }
+
+    fn close(&mut self) -> Result<(), FileError> {
+        // closing twice must not let go of a hold taken by another file
+        if !self.closed {
+            self.closed = true;
+            self.tree.lock().close(self.inode)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), FileError> {
+        Ok(())
+    }
+
+    fn poll(&mut self, event: IOEvent) -> bool {
+        match event {
+            IOEvent::Read => FileFlags::Read.is_set(self.flags),
+            IOEvent::Write => FileFlags::Write.is_set(self.flags),
+        }
+    }
+
+    fn seek(&mut self, pos: usize) -> Result<usize, FileError> {
+        self.file_pos = pos;
+        Ok(pos)
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), FileError> {
+        if !FileFlags::Write.is_set(self.flags) {
+            return Err(FileError::PermissionError(FsError::WriteError.into()));
+        }
+
+        Ok(self.tree.lock().set_len(self.inode, len as usize)?)
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn open(&mut self, path: &str, flags: u8) -> Result<Box<dyn Stream + Send>, FileError> {
+        let mut tree = self.tree.lock();
+        let inode = match tree.lookup(path) {
+            Ok(inode) => {
+                if tree.node(inode)?.kind == FileKind::Directory {
+                    return Err(FsError::IsADirectory.into());
+                }
+                inode
+            }
+            Err(FsError::FileNotFound) if FileFlags::Create.is_set(flags) => {
+                tree.create(path, Node::new(FileKind::File, [6, 6, 6], 0))?
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        if FileFlags::Truncate.is_set(flags) {
+            tree.set_len(inode, 0)?;
+        }
+
+        // if the append flag is set, start at the end of the file
+        let file_pos = if FileFlags::Append.is_set(flags) {
+            tree.node(inode)?.data.len()
+        } else {
+            0
+        };
+
+        tree.node_mut(inode)?.open += 1;
+        Ok(Box::new(TmpFile {
+            tree: self.tree.clone(),
+            inode,
+            flags,
+            file_pos,
+            closed: false,
+        }))
+    }
+
+    fn create(&mut self, path: &str, perms: [u8; 3], owner: u64) -> Result<(), FileError> {
+        self.tree
+            .lock()
+            .create(path, Node::new(FileKind::File, perms, owner))?;
+        Ok(())
+    }
+
+    fn delete(&mut self, path: &str) -> Result<(), FileError> {
+        let mut tree = self.tree.lock();
+        let inode = tree.lookup_entry(path)?;
+        if tree.node(inode)?.kind == FileKind::Directory {
+            return Err(FsError::IsADirectory.into());
+        }
+
+        tree.unlink_entry(path)?;
+        Ok(tree.drop_link(inode)?)
+    }
+
+    fn exists(&mut self, path: &str) -> bool {
+        self.tree.lock().lookup(path).is_ok()
+    }
+
+    fn chmod(&mut self, path: &str, perms: [u8; 3]) -> Result<(), FileError> {
+        let mut tree = self.tree.lock();
+        let inode = tree.lookup(path)?;
+        tree.node_mut(inode)?.permissions = perms;
+        Ok(())
+    }
+
+    fn chown(&mut self, path: &str, owner: u64) -> Result<(), FileError> {
+        let mut tree = self.tree.lock();
+        let inode = tree.lookup(path)?;
+        tree.node_mut(inode)?.owner = owner;
+        Ok(())
+    }
+
+    fn list(&mut self, path: &str) -> Result<Vec<String>, FileError> {
+        let tree = self.tree.lock();
+        let node = tree.node(tree.lookup(path)?)?;
+        if node.kind != FileKind::Directory {
+            return Err(FsError::NotADirectory.into());
+        }
+
+        Ok(node.children.keys().cloned().collect())
+    }
+
+    fn mkdir(&mut self, path: &str, perms: [u8; 3]) -> Result<(), FileError> {
+        self.tree
+            .lock()
+            .create(path, Node::new(FileKind::Directory, perms, 0))?;
+        Ok(())
+    }
+
+    fn rmdir(&mut self, path: &str) -> Result<(), FileError> {
+        let mut tree = self.tree.lock();
+        let inode = tree.lookup_entry(path)?;
+        if inode == ROOT_INODE {
+            return Err(FsError::InvalidPath.into());
+        }
+
+        let node = tree.node(inode)?;
+        if node.kind != FileKind::Directory {
+            return Err(FsError::NotADirectory.into());
+        }
+        if !node.children.is_empty() {
+            return Err(FsError::DirectoryNotEmpty.into());
+        }
+
+        tree.unlink_entry(path)?;
+        Ok(tree.drop_link(inode)?)
+    }
+
+    fn get_owner(&mut self, path: &str) -> Result<u64, FileError> {
+        Ok(self.stat(path)?.owner)
+    }
+
+    fn get_perms(&mut self, path: &str) -> Result<[u8; 3], FileError> {
+        Ok(self.stat(path)?.permissions)
+    }
+
+    fn stat(&mut self, path: &str) -> Result<FileStat, FileError> {
+        let tree = self.tree.lock();
+        Ok(tree.stat(tree.lookup(path)?)?)
+    }
+
+    fn rename(&mut self, old: &str, new: &str) -> Result<(), FileError> {
+        let mut tree = self.tree.lock();
+        let (old_parent, old_name) = tree.parent_of(old)?;
+        let inode = *tree
+            .node(old_parent)?
+            .children
+            .get(old_name)
+            .ok_or(FsError::FileNotFound)?;
+        let kind = tree.node(inode)?.kind;
+
+        // a directory cannot be moved inside itself
+        let (new_parent_path, new_name) = split_parent(new)?;
+        let new_parents = tree.resolve(new_parent_path, true)?;
+        if kind == FileKind::Directory && new_parents.contains(&inode) {
+            return Err(FsError::InvalidPath.into());
+        }
+        let new_parent = *new_parents.last().unwrap();
+        if tree.node(new_parent)?.kind != FileKind::Directory {
+            return Err(FsError::NotADirectory.into());
+        }
+
+        if let Some(existing) = tree.node(new_parent)?.children.get(new_name).copied() {
+            let existing_node = tree.node(existing)?;
+            if !path::check_replace((inode, kind), (existing, existing_node.kind), || {
+                Ok(existing_node.children.is_empty())
+            })? {
+                return Ok(());
+            }
+
+            tree.node_mut(new_parent)?.children.remove(new_name);
+            tree.drop_link(existing)?;
+        }
+
+        let now = clk::get_unix_time();
+        let parent = tree.node_mut(old_parent)?;
+        parent.children.remove(old_name);
+        parent.modification_time = now;
+        let parent = tree.node_mut(new_parent)?;
+        parent.children.insert(new_name.to_string(), inode);
+        parent.modification_time = now;
+        Ok(())
+    }
+
+    fn truncate(&mut self, path: &str, len: u64) -> Result<(), FileError> {
+        let mut tree = self.tree.lock();
+        let inode = tree.lookup(path)?;
+        Ok(tree.set_len(inode, len as usize)?)
+    }
+
+    fn link(&mut self, old: &str, new: &str) -> Result<(), FileError> {
+        let mut tree = self.tree.lock();
+        let inode = tree.lookup(old)?;
+        if tree.node(inode)?.kind == FileKind::Directory {
+            return Err(FsError::IsADirectory.into());
+        }
+
+        let (parent, name) = tree.parent_of(new)?;
+        if tree.node(parent)?.children.contains_key(name) {
+            return Err(FsError::FileExists.into());
+        }
+
+        tree.node_mut(parent)?
+            .children
+            .insert(name.to_string(), inode);
+        tree.node_mut(inode)?.links += 1;
+        Ok(())
+    }
+
+    fn symlink(&mut self, target: &str, path: &str, owner: u64) -> Result<(), FileError> {
+        let mut node = Node::new(FileKind::Symlink, [7, 7, 7], owner);
+        node.data = target.as_bytes().to_vec();
+        self.tree.lock().create(path, node)?;
+        Ok(())
+    }
+
+    fn readlink(&mut self, path: &str) -> Result<String, FileError> {
+        let tree = self.tree.lock();
+        let node = tree.node(tree.lookup_entry(path)?)?;
+        if node.kind != FileKind::Symlink {
+            return Err(FsError::InvalidPath.into());
+        }
+
+        Ok(String::from_utf8(node.data.clone()).map_err(|_| FsError::InvalidMetadata)?)
+    }
+
+    fn sync(&mut self) -> Result<(), FileError> {
+        // there is nothing to write back to
+        Ok(())
+    }
+}
+
+/// test creating, writing, reading and truncating files
+#[test_case]
+fn test_tmpfs_files() {
+    let mut fs = TmpFs::new(4096);
+
+    let mut file = fs.open("/hello", ALL_FLAGS).unwrap();
+    assert_eq!(file.write(b"Hello, world!").unwrap(), 13);
+    file.seek(7).unwrap();
+    let mut buf = [0; 16];
+    assert_eq!(file.read(&mut buf).unwrap(), 6);
+    assert_eq!(&buf[..6], b"world!");
+
+    // appending starts at the end, and truncating on open empties the file
+    fs.open("/hello", FileFlags::Write | FileFlags::Append)
+        .unwrap()
+        .write(b"!!")
+        .unwrap();
+    assert_eq!(fs.stat("/hello").unwrap().size, 15);
+    fs.open("/hello", FileFlags::Write | FileFlags::Truncate)
+        .unwrap();
+    assert_eq!(fs.stat("/hello").unwrap().size, 0);
+
+    assert_eq!(
+        fs.open("/missing", FileFlags::Read as u8).err(),
+        Some(FsError::FileNotFound.into())
+    );
+    assert_eq!(
+        fs.open("/hello", FileFlags::Read as u8)
+            .unwrap()
+            .write(b"x"),
+        Err(FileError::PermissionError(FsError::WriteError.into()))
+    );
+
+    fs.chmod("/hello", [6, 4, 0]).unwrap();
+    fs.chown("/hello", 1000).unwrap();
+    assert_eq!(fs.get_perms("/hello"), Ok([6, 4, 0]));
+    assert_eq!(fs.get_owner("/hello"), Ok(1000));
+
+    fs.delete("/hello").unwrap();
+    assert!(!fs.exists("/hello"));
+
+    fs.create("/mine", [6, 0, 0], 1000).unwrap();
+    assert_eq!(fs.get_perms("/mine"), Ok([6, 0, 0]));
+    assert_eq!(fs.get_owner("/mine"), Ok(1000));
+    assert_eq!(fs.stat("/mine").unwrap().size, 0);
+}
+
+/// test directories, hard and symbolic links, and renames
+#[test_case]
+fn test_tmpfs_directories_and_links() {
+    let mut fs = TmpFs::new(4096);
+
+    fs.mkdir("/bin", [7, 5, 5]).unwrap();
+    fs.mkdir("/binaries", [7, 5, 5]).unwrap();
+    fs.open("/bin/hello", ALL_FLAGS)
+        .unwrap()
+        .write(b"hi")
+        .unwrap();
+
+    assert_eq!(fs.list("/").unwrap(), [
+        "bin".to_string(),
+        "binaries".to_string()
+    ]);
+    assert_eq!(fs.list("/bin").unwrap(), ["hello".to_string()]);
+    assert!(fs.exists("/bin/../bin/./hello"));
+    assert_eq!(
+        fs.open("/bin", FileFlags::Read as u8).err(),
+        Some(FsError::IsADirectory.into())
+    );
+    assert_eq!(fs.rmdir("/bin"), Err(FsError::DirectoryNotEmpty.into()));
+
+    fs.link("/bin/hello", "/hard").unwrap();
+    fs.symlink("bin/hello", "/soft", 0).unwrap();
+    assert_eq!(fs.stat("/hard").unwrap().links, 2);
+    assert_eq!(fs.readlink("/soft"), Ok("bin/hello".to_string()));
+    assert_eq!(
+        fs.stat("/soft").unwrap().inode,
+        fs.stat("/hard").unwrap().inode
+    );
+
+    fs.rename("/bin", "/sbin").unwrap();
+    assert!(fs.exists("/sbin/hello"));
+    assert_eq!(fs.stat("/soft"), Err(FsError::FileNotFound.into()));
+    assert_eq!(
+        fs.rename("/sbin", "/sbin/inner"),
+        Err(FsError::InvalidPath.into())
+    );
+
+    fs.delete("/hard").unwrap();
+    assert_eq!(fs.stat("/sbin/hello").unwrap().links, 1);
+    fs.delete("/sbin/hello").unwrap();
+    fs.rmdir("/sbin").unwrap();
+    assert_eq!(fs.list("/").unwrap(), [
+        "binaries".to_string(),
+        "soft".to_string()
+    ]);
+}
+
+/// test that the size limit is enforced and freed space can be used again
+#[test_case]
+fn test_tmpfs_size_limit() {
+    let mut fs = TmpFs::new(1024);
+
+    let mut file = fs.open("/big", ALL_FLAGS).unwrap();
+    assert_eq!(file.write(&[1; 1024]).unwrap(), 1024);
+    assert_eq!(file.write(&[1]), Err(FsError::DiskFull.into()));
+    assert_eq!(fs.truncate("/big", 2048), Err(FsError::DiskFull.into()));
+
+    fs.truncate("/big", 512).unwrap();
+    assert_eq!(fs.used(), 512);
+    fs.open("/other", ALL_FLAGS)
+        .unwrap()
+        .write(&[2; 512])
+        .unwrap();
+    assert_eq!(fs.used(), 1024);
+
+    // space is only given back once the last name for a file is gone and it is no longer open
+    fs.link("/big", "/big2").unwrap();
+    fs.delete("/big").unwrap();
+    assert_eq!(fs.used(), 1024);
+    fs.delete("/big2").unwrap();
+    assert_eq!(fs.used(), 1024);
+
+    // the open file can still be read and written, like an unlinked file on rustnix-fs
+    file.seek(0).unwrap();
+    file.write(&[3; 4]).unwrap();
+    let mut buf = [0; 8];
+    file.seek(0).unwrap();
+    assert_eq!(file.read(&mut buf).unwrap(), 8);
+    assert_eq!(buf, [3, 3, 3, 3, 1, 1, 1, 1]);
+
+    file.close().unwrap();
+    file.close().unwrap();
+    assert_eq!(fs.used(), 512);
+}
@@ -119,7 +119,65 @@ impl TryFrom<&str> for User {
     }
 }
 
+/// get a user by name, or None if there is no such user or the users are not loaded yet
+pub fn get_user(name: &str) -> Option<&'static User> {
+    USERS.get()?.iter().find(|u| u.name == name)
+}
+
 /// get a uid by name
 pub fn get_uid(name: &str) -> Option<u64> {
-    USERS.get().unwrap().iter().find(|u| u.name == name).map(|u| u.uid)
+    get_user(name).map(|u| u.uid)
+}
+
+/// the rwx bits a user is granted on a file with the given owner and Unix-style permissions
+///
+/// uid 0 is root, and is granted everything, and a user not in /etc/users only gets the bits for others.
+///
+/// Inodes only record an owner, not a group, so the group bits are for the owner's first group in /etc/users. This means a file cannot be shared with any other group its owner is in, and changing the owner's first group changes who the group bits apply to
+pub fn access_bits(name: &str, owner: u64, perms: [u8; 3]) -> u8 {
+    let [owner_bits, group_bits, other_bits] = perms;
+    let Some(user) = get_user(name) else {
+        return other_bits;
+    };
+
+    if user.uid == 0 {
+        return 7;
+    }
+
+    if user.uid == owner {
+        return owner_bits;
+    }
+
+    let group = USERS
+        .get()
+        .and_then(|users| users.iter().find(|u| u.uid == owner))
+        .and_then(|u| u.groups.first());
+
+    if group.is_some_and(|group| user.groups.contains(group)) {
+        group_bits
+    } else {
+        other_bits
+    }
+}
+
+/// test that users get the owner, group or other bits of a file, and root gets everything
+#[test_case]
+fn test_access_bits() {
+    // tests have no disk to load /etc/users from
+    USERS.init_once(|| {
+        vec![
+            User::new(0, "root", "", "/root", "/bin/sh", vec!["root".to_string()]),
+            User::new(1000, "alice", "", "/home/alice", "/bin/sh", vec!["staff".to_string()]),
+            User::new(1001, "bob", "", "/home/bob", "/bin/sh", vec!["users".to_string(), "staff".to_string()]),
+            User::new(1002, "eve", "", "/home/eve", "/bin/sh", vec!["users".to_string()]),
+        ]
+    });
+
+    let perms = [6, 4, 0];
+    assert_eq!(access_bits("root", 1000, perms), 7);
+    assert_eq!(access_bits("alice", 1000, perms), 6);
+    // alice's first group is staff, which bob is in but eve is not
+    assert_eq!(access_bits("bob", 1000, perms), 4);
+    assert_eq!(access_bits("eve", 1000, perms), 0);
+    assert_eq!(access_bits("nobody", 1000, [7, 7, 5]), 5);
 }
@@ -0,0 +1,371 @@
+/*
+ * the virtual filesystem layer
+ * the mount table maps directories to the filesystems mounted on them. Every path-based operation goes to the filesystem mounted on the longest matching directory,
+ * with the path made relative to it, so a filesystem only ever sees paths starting at its own root.
+ * devfs and procfs are mounted at /dev and /proc like any other filesystem, and volumes on ATA drives and their partitions can be mounted on any directory.
+ * /tmp is a tmpfs, so it is there even without a disk - including while running the tests.
+ */
+
+use core::fmt::{Debug, Formatter};
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use log::{trace, warn};
+use spin::Mutex;
+
+use super::{
+    devices::{dev::DevFs, proc::ProcFs},
+    file::{FileError, FileSystem, IOEvent, Stream},
+    fs::{FileStat, FsError},
+    tmpfs::{TMP_SIZE, TmpFs},
+};
+
+#[cfg(test)]
+use super::{
+    file::{ALL_FLAGS, FileFlags},
+    fs::{FILESYSTEMS, VirtFs, mount_drive},
+};
+
+/// a filesystem mounted on a directory
+pub struct Mount {
+    /// the directory it is mounted on, as a normalised absolute path
+    pub path: String,
+    fs: Box<dyn FileSystem + Send>,
+}
+
+/// the mount table
+pub static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+
+/// a file opened through the mount table, shared by every descriptor it is copied into
+#[derive(Clone)]
+pub struct OpenFile {
+    path: String,
+    stream: Arc<Mutex<Box<dyn Stream + Send>>>,
+}
+
+impl Debug for OpenFile {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        f.debug_struct("OpenFile").field("path", &self.path).finish()
+    }
+}
+
+impl Stream for OpenFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, FileError> {
+        self.stream.lock().read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, FileError> {
+        self.stream.lock().write(buf)
+    }
+
+    fn close(&mut self) -> Result<(), FileError> {
+        self.stream.lock().close()
+    }
+
+    fn flush(&mut self) -> Result<(), FileError> {
+        self.stream.lock().flush()
+    }
+
+    fn poll(&mut self, event: IOEvent) -> bool {
+        self.stream.lock().poll(event)
+    }
+
+    fn seek(&mut self, pos: usize) -> Result<usize, FileError> {
+        self.stream.lock().seek(pos)
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), FileError> {
+        self.stream.lock().truncate(len)
+    }
+}
+
+/// tidy up an absolute path, dropping empty and `.` components and applying `..`, which cannot go above the root
+pub fn normalise(path: &str) -> String {
+    let mut parts = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+
+    if parts.is_empty() {
+        return "/".to_string();
+    }
+
+    let mut normalised = String::new();
+    for part in parts {
+        normalised.push('/');
+        normalised.push_str(part);
+    }
+    normalised
+}
+
+/// the path within a mount, if the path is its directory or under it
+fn relative_to<'a>(mount: &str, path: &'a str) -> Option<&'a str> {
+    if mount == "/" {
+        return Some(path);
+    }
+
+    match path.strip_prefix(mount)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        // a prefix of another directory's name must not match it
+        _ => None,
+    }
+}
+
+/// find the mount a normalised path is on, and the path within it
+fn resolve<'a>(mounts: &[Mount], path: &'a str) -> Result<(usize, &'a str), FileError> {
+    mounts
+        .iter()
+        .enumerate()
+        .filter_map(|(index, mount)| Some((index, relative_to(&mount.path, path)?)))
+        .max_by_key(|(index, _)| mounts[*index].path.len())
+        .ok_or(FileError::NotFoundError(FsError::FilesystemNotFound.into()))
+}
+
+/// check whether anything is mounted on a normalised path or under it, in which case it cannot be moved or removed
+fn is_busy(mounts: &[Mount], path: &str) -> bool {
+    mounts.iter().any(|mount| relative_to(path, &mount.path).is_some())
+}
+
+/// run an operation on the filesystem a path is on, with the path made relative to its mount
+fn on_mount<T>(
+    path: &str,
+    op: impl FnOnce(&mut dyn FileSystem, &str) -> Result<T, FileError>,
+) -> Result<T, FileError> {
+    let path = normalise(path);
+    let mut mounts = MOUNTS.lock();
+    let (index, relative) = resolve(&mounts, &path)?;
+    op(mounts[index].fs.as_mut(), relative)
+}
+
+/// run an operation on the filesystem two paths are on, which must be the same one
+fn on_same_mount<T>(
+    old: &str,
+    new: &str,
+    op: impl FnOnce(&mut dyn FileSystem, &str, &str) -> Result<T, FileError>,
+) -> Result<T, FileError> {
+    let (old, new) = (normalise(old), normalise(new));
+    let mut mounts = MOUNTS.lock();
+    let (index, old_relative) = resolve(&mounts, &old)?;
+    let (new_index, new_relative) = resolve(&mounts, &new)?;
+    if index != new_index {
+        return Err(FsError::CrossDevice.into());
+    }
+
+    if is_busy(&mounts, &old) || is_busy(&mounts, &new) {
+        return Err(FsError::Busy.into());
+    }
+
+    op(mounts[index].fs.as_mut(), old_relative, new_relative)
+}
+
+/// mount a filesystem on a directory
+pub fn mount(path: &str, fs: Box<dyn FileSystem + Send>) -> Result<(), FileError> {
+    let path = normalise(path);
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|mount| mount.path == path) {
+        return Err(FileError::WriteError(FsError::FilesystemExists.into()));
+    }
+
+    trace!("Mounting filesystem at {}", path);
+    mounts.push(Mount { path, fs });
+    Ok(())
+}
+
+/// write back and unmount the filesystem mounted on a directory, which must have nothing else mounted under it
+pub fn umount(path: &str) -> Result<(), FileError> {
+    let path = normalise(path);
+    let mut mounts = MOUNTS.lock();
+    let index = mounts
+        .iter()
+        .position(|mount| mount.path == path)
+        .ok_or(FileError::NotFoundError(FsError::FilesystemNotFound.into()))?;
+
+    if mounts
+        .iter()
+        .any(|mount| mount.path != path && relative_to(&path, &mount.path).is_some())
+    {
+        return Err(FsError::Busy.into());
+    }
+
+    mounts[index].fs.sync()?;
+    trace!("Unmounting filesystem at {}", path);
+    mounts.remove(index);
+    Ok(())
+}
+
+/// the directories that have a filesystem mounted on them
+pub fn mount_points() -> Vec<String> {
+    MOUNTS.lock().iter().map(|mount| mount.path.clone()).collect()
+}
+
+/// open a file on whichever filesystem it is on
+pub fn open(path: &str, flags: u8) -> Result<OpenFile, FileError> {
+    let stream = on_mount(path, |fs, path| fs.open(path, flags))?;
+    Ok(OpenFile {
+        path: normalise(path),
+        stream: Arc::new(Mutex::new(stream)),
+    })
+}
+
+/// make an empty file with the given permissions and owner
+pub fn create(path: &str, perms: [u8; 3], owner: u64) -> Result<(), FileError> {
+    on_mount(path, |fs, path| fs.create(path, perms, owner))
+}
+
+/// check if a file exists
+pub fn exists(path: &str) -> bool {
+    on_mount(path, |fs, path| Ok(fs.exists(path))).unwrap_or(false)
+}
+
+/// get the size, owner, permissions and timestamps of a file
+pub fn stat(path: &str) -> Result<FileStat, FileError> {
+    on_mount(path, |fs, path| fs.stat(path))
+}
+
+/// list the contents of a directory
+pub fn list(path: &str) -> Result<Vec<String>, FileError> {
+    on_mount(path, |fs, path| fs.list(path))
+}
+
+/// create a directory
+pub fn mkdir(path: &str, perms: [u8; 3]) -> Result<(), FileError> {
+    on_mount(path, |fs, path| fs.mkdir(path, perms))
+}
+
+/// remove an empty directory, which must not be a mount point
+pub fn rmdir(path: &str) -> Result<(), FileError> {
+    let path = normalise(path);
+    if is_busy(&MOUNTS.lock(), &path) {
+        return Err(FsError::Busy.into());
+    }
+
+    on_mount(&path, |fs, path| fs.rmdir(path))
+}
+
+/// remove a name for a file, freeing the file once its last name is gone
+pub fn delete(path: &str) -> Result<(), FileError> {
+    let path = normalise(path);
+    if is_busy(&MOUNTS.lock(), &path) {
+        return Err(FsError::Busy.into());
+    }
+
+    on_mount(&path, |fs, path| fs.delete(path))
+}
+
+/// change the permissions of a file
+pub fn chmod(path: &str, perms: [u8; 3]) -> Result<(), FileError> {
+    on_mount(path, |fs, path| fs.chmod(path, perms))
+}
+
+/// change the owner of a file
+pub fn chown(path: &str, owner: u64) -> Result<(), FileError> {
+    on_mount(path, |fs, path| fs.chown(path, owner))
+}
+
+/// cut a file down or grow it to the given length
+pub fn truncate(path: &str, len: u64) -> Result<(), FileError> {
+    on_mount(path, |fs, path| fs.truncate(path, len))
+}
+
+/// move a file or directory within the filesystem it is on
+pub fn rename(old: &str, new: &str) -> Result<(), FileError> {
+    on_same_mount(old, new, |fs, old, new| fs.rename(old, new))
+}
+
+/// give an existing file a second name, on the filesystem it is on
+pub fn link(old: &str, new: &str) -> Result<(), FileError> {
+    on_same_mount(old, new, |fs, old, new| fs.link(old, new))
+}
+
+/// make a symbolic link, on the filesystem its directory is on - the target is kept as given, and resolved within that filesystem
+pub fn symlink(target: &str, path: &str, owner: u64) -> Result<(), FileError> {
+    on_mount(path, |fs, path| fs.symlink(target, path, owner))
+}
+
+/// read where a symbolic link points
+pub fn readlink(path: &str) -> Result<String, FileError> {
+    on_mount(path, |fs, path| fs.readlink(path))
+}
+
+/// mount devfs at /dev and procfs at /proc
+pub fn init() {
+    trace!("Initializing VFS");
+
+    if let Err(err) = mount("/dev", Box::new(DevFs)) {
+        warn!("Failed to mount /dev: {:?}", err);
+    }
+
+    if let Err(err) = mount("/proc", Box::new(ProcFs)) {
+        warn!("Failed to mount /proc: {:?}", err);
+    }
+
+    if let Err(err) = mount("/tmp", Box::new(TmpFs::new(TMP_SIZE))) {
+        warn!("Failed to mount /tmp: {:?}", err);
+    }
+}
+
+/// test that paths are tidied up before they are matched against mount points
+#[test_case]
+fn test_normalise() {
+    assert_eq!(normalise("/"), "/");
+    assert_eq!(normalise("//bin/./hello/"), "/bin/hello");
+    assert_eq!(normalise("/mnt/../etc/users"), "/etc/users");
+    assert_eq!(normalise("/../.."), "/");
+
+    assert_eq!(relative_to("/", "/bin"), Some("/bin"));
+    assert_eq!(relative_to("/mnt", "/mnt"), Some("/"));
+    assert_eq!(relative_to("/mnt", "/mnt/disk"), Some("/disk"));
+    assert_eq!(relative_to("/mnt", "/mnt2/disk"), None);
+}
+
+/// test routing operations to a rustnix-fs volume mounted under /mnt, and refusing to move or remove mount points
+#[test_case]
+fn test_mounts() {
+    // nothing reaches the drive until the first flush, so the volume lives in memory
+    VirtFs::new(1, 1, 0, (1 + 1024 + 1024) * 512).unwrap();
+    mount_drive(1, 1, 0, "/mnt").unwrap();
+    assert_eq!(
+        mount("/mnt/", Box::new(DevFs)),
+        Err(FileError::WriteError(FsError::FilesystemExists.into()))
+    );
+
+    mkdir("/mnt/dir", [7, 5, 5]).unwrap();
+    open("/mnt/dir/file", ALL_FLAGS).unwrap().write(b"Hello, world!").unwrap();
+    assert_eq!(stat("/mnt/dir/../dir/./file").unwrap().size, 13);
+    assert_eq!(list("/mnt").unwrap(), ["dir".to_string()]);
+
+    let mut buf = [0; 13];
+    open("/mnt/dir/file", FileFlags::Read as u8).unwrap().read(&mut buf).unwrap();
+    assert_eq!(&buf, b"Hello, world!");
+
+    // devfs is its own filesystem, so nothing can be moved onto it
+    assert_eq!(rename("/mnt/dir/file", "/dev/file"), Err(FsError::CrossDevice.into()));
+    assert_eq!(rmdir("/mnt"), Err(FsError::Busy.into()));
+    assert_eq!(rename("/mnt/dir", "/mnt"), Err(FsError::Busy.into()));
+
+    mount("/mnt/dev", Box::new(DevFs)).unwrap();
+    assert!(exists("/mnt/dev/null"));
+    assert_eq!(umount("/mnt"), Err(FsError::Busy.into()));
+    umount("/mnt/dev").unwrap();
+    assert!(!exists("/mnt/dev/null"));
+
+    // the volume was never formatted, so it is taken out of the table without writing it back
+    MOUNTS.lock().retain(|mount| mount.path != "/mnt");
+    FILESYSTEMS.lock().remove(&(1, 1, 0));
+    assert!(!exists("/mnt/dir/file"));
+    assert_eq!(
+        umount("/mnt"),
+        Err(FileError::NotFoundError(FsError::FilesystemNotFound.into()))
+    );
+}
@@ -12,7 +12,7 @@ extern crate alloc;
 /// internal modules, not exposed to userspace
 pub mod internal;
 #[allow(unused_imports)] // fs is used
-use internal::{acpi, ata, clk, fs, gdt, interrupts, keyboard, memory, syscall, user, vga};
+use internal::{acpi, ata, clk, fs, gdt, interrupts, keyboard, memory, syscall, user, vfs, vga};
 pub use {
     syscall::ALLOC, syscall::CLOSE, syscall::EXEC, syscall::EXIT, syscall::FLUSH, syscall::FREE,
     syscall::GETERRNO, syscall::GETPID, syscall::KIND, syscall::OPEN, syscall::READ,
@@ -239,7 +239,9 @@ pub fn init(boot_info: &'static BootInfo) {
     ata::init();
     info!("ATA initialized");
 
-    #[cfg(not(test))] // tests don't have attached disk
+    vfs::init();
+    info!("VFS initialized");
+
     fs::init();
     info!("Filesystem initialized");
 
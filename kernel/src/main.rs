@@ -39,6 +39,9 @@ fn panic(info: &PanicInfo) -> ! {
 
 entry_point!(kmain);
 
+/// the programs tried, in order, as the first process
+const INIT_PATHS: [&str; 2] = ["/bin/init", "/bin/hello.o"];
+
 fn kmain(boot_info: &'static BootInfo) -> ! {
     rustnix::init(boot_info);
 
@@ -57,7 +60,11 @@ fn kmain(boot_info: &'static BootInfo) -> ! {
     let args_ptr = args.as_ptr() as usize;
     let args_len = args.len();
 
-    let path = "/bin/hello.o";
+    // the first of these that exists becomes the first process
+    let path = INIT_PATHS
+        .iter()
+        .find(|path| rustnix::internal::vfs::exists(path))
+        .unwrap_or(&INIT_PATHS[0]);
     let path_ptr = path.as_ptr() as usize;
     let path_len = path.len();
 
@@ -65,7 +72,8 @@ fn kmain(boot_info: &'static BootInfo) -> ! {
 
     kprintln!("and we're back");
 
-    loop {}
+    // EXEC only returns once every process has exited, so there is nothing left to run
+    rustnix::hlt_loop()
 
     // let mut buf = vec![0;512];
 
@@ -85,22 +85,110 @@ static inline usize syscall4(usize n, usize arg1, usize arg2, usize arg3, usize
 #define BOOTTIME 0x17
 #define TIME 0x18
 #define SEEK 0x19
+#define STAT 0x1A
+#define LINK 0x1B
+#define SYMLINK 0x1C
+#define READLINK 0x1D
+#define RENAME 0x1E
+#define FTRUNCATE 0x1F
+#define UNLINK 0x20
+#define MOUNT 0x21
+#define UMOUNT 0x22
+
+// Options for waitpid
+#define WNOHANG 1
 
 typedef long isize;
 
+// Written by stat, times are in seconds since the Unix epoch
+struct stat {
+    u64 inode;
+    u64 kind; // 0 for a regular file, 1 for a directory, 2 for a symbolic link
+    u64 size;
+    u64 blocks;
+    u64 owner;
+    u64 mode; // Unix mode bits, such as 0755
+    u64 ctime;
+    u64 mtime;
+    u64 atime;
+};
+
 // Function implementations
+// Returns the program's exit code once it has exited
 usize spawn(const char *path, const char **args, usize path_len, usize args_len) {
     usize res = syscall4(SPAWN, (usize)path, path_len, (usize)args, args_len);
     return res;
 }
 
+// Returns the child's PID in the parent, and 0 in the child, which starts with a copy of the parent's memory
+isize fork() {
+    usize res = syscall0(FORK);
+    return (isize)res;
+}
+
+// Waits for the child pid (or any child if pid is -1) to exit, and returns its pid, or 0 with WNOHANG if none has exited yet
+// The exit code is written to status, unless it is null
+isize waitpid(isize pid, usize *status, usize options) {
+    usize res = syscall3(WAITPID, (usize)pid, (usize)status, options);
+    return (isize)res;
+}
+
 isize write(usize fd, const char *string, usize len) {
     usize res = syscall3(WRITE, fd, (usize)string, len);
     return (isize)res;
 }
 
-isize open(const char *path, u8 flags, usize path_len) {
-    usize res = syscall3(OPEN, (usize)path, path_len, (usize)flags);
+// A file made by the CREATE flag gets the permission bits of mode, such as 0666, less the umask
+isize open(const char *path, u8 flags, usize path_len, usize mode) {
+    usize res = syscall4(OPEN, (usize)path, path_len, (usize)flags, mode);
+    return (isize)res;
+}
+
+isize stat(const char *path, usize path_len, struct stat *buf) {
+    usize res = syscall3(STAT, (usize)path, path_len, (usize)buf);
+    return (isize)res;
+}
+
+isize link(const char *old, usize old_len, const char *new, usize new_len) {
+    usize res = syscall4(LINK, (usize)old, old_len, (usize)new, new_len);
+    return (isize)res;
+}
+
+isize symlink(const char *target, usize target_len, const char *path, usize path_len) {
+    usize res = syscall4(SYMLINK, (usize)target, target_len, (usize)path, path_len);
+    return (isize)res;
+}
+
+// Returns the number of bytes written to buf, which is not null-terminated
+isize readlink(const char *path, usize path_len, char *buf, usize buf_len) {
+    usize res = syscall4(READLINK, (usize)path, path_len, (usize)buf, buf_len);
+    return (isize)res;
+}
+
+isize rename(const char *old, usize old_len, const char *new, usize new_len) {
+    usize res = syscall4(RENAME, (usize)old, old_len, (usize)new, new_len);
+    return (isize)res;
+}
+
+isize ftruncate(usize fd, usize len) {
+    usize res = syscall2(FTRUNCATE, fd, len);
+    return (isize)res;
+}
+
+isize unlink(const char *path, usize path_len) {
+    usize res = syscall2(UNLINK, (usize)path, path_len);
+    return (isize)res;
+}
+
+// Mounts the rustnix-fs, ext2 or FAT32 (read-only) volume on a partition of an ATA drive at an existing directory, root only
+// Partitions are numbered from 1 in the order of the partition table, and partition 0 is the whole drive
+isize mount(usize bus, usize dsk, usize part, const char *path, usize path_len) {
+    usize res = syscall4(MOUNT, bus, dsk | part << 8, (usize)path, path_len);
+    return (isize)res;
+}
+
+isize umount(const char *path, usize path_len) {
+    usize res = syscall2(UMOUNT, (usize)path, path_len);
     return (isize)res;
 }
 